@@ -0,0 +1,56 @@
+use std::io::{self, Write};
+use tokio::sync::mpsc;
+use warp::hyper::{body::Bytes, Body};
+use zip::write::{SimpleFileOptions, StreamWriter};
+use zip::ZipWriter;
+
+/// Zip archive written from a blocking task straight into a response body,
+/// so the archive is never held in memory as a whole.
+pub struct ZipStream {
+    writer: ZipWriter<StreamWriter<io::BufWriter<ChannelWriter>>>,
+}
+
+impl ZipStream {
+    pub fn new() -> (Self, Body) {
+        let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(16);
+        let body = Body::wrap_stream(futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|chunk| (chunk, rx))
+        }));
+        let writer = ZipWriter::new_stream(io::BufWriter::new(ChannelWriter { tx }));
+
+        (Self { writer }, body)
+    }
+
+    /// Must be called from a blocking context (e.g. `spawn_blocking`).
+    pub fn add_file(&mut self, name: &str, contents: &[u8]) -> io::Result<()> {
+        self.writer
+            .start_file(name, SimpleFileOptions::default())
+            .map_err(io::Error::other)?;
+        self.writer.write_all(contents)
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.writer
+            .finish()
+            .map_err(io::Error::other)?
+            .into_inner()
+            .flush()
+    }
+}
+
+struct ChannelWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx
+            .blocking_send(Ok(Bytes::copy_from_slice(buf)))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client went away"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use crate::{
+    db::{live_note, DB},
+    error::Error::*,
+    events::NoteEvent,
+    response::{NoteData, SingleNoteResponse},
+    Result,
+};
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use std::str::FromStr;
+
+impl DB {
+    /// Archives or restores a note. Archived notes stay readable by id but
+    /// are left out of lists unless asked for. A note already in the
+    /// requested state is returned as it is, without events.
+    pub async fn set_archived(
+        &self,
+        id: &str,
+        archived: bool,
+    ) -> Result<Option<SingleNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+
+        // `$ne` also matches notes without the field, which count as not
+        // archived.
+        let filter = doc! {"_id": oid, "deletedAt": null, "archived": {"$ne": archived}};
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let changed = self
+            .note_collection
+            .find_one_and_update(filter, doc! {"$set": {"archived": archived}}, options)
+            .await
+            .map_err(MongoQueryError)?;
+
+        let note = match changed {
+            Some(note) => Some((note, true)),
+            None => self
+                .note_collection
+                .find_one(live_note(oid), None)
+                .await
+                .map_err(MongoQueryError)?
+                .map(|note| (note, false)),
+        };
+        let Some((note, changed)) = note else {
+            return Ok(None);
+        };
+
+        let note_response = SingleNoteResponse {
+            status: "success".to_string(),
+            data: NoteData {
+                note: self.doc_to_note(&note)?,
+                draft: None,
+            },
+        };
+
+        if changed {
+            self.adjust_note_count(if archived { -1 } else { 1 }).await;
+            self.events
+                .publish(NoteEvent::changed(&note_response, &["archived"]));
+        }
+
+        Ok(Some(note_response))
+    }
+}
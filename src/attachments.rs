@@ -0,0 +1,277 @@
+use crate::{
+    db::{live_note, DB},
+    error::Error::*,
+    response::AttachmentResponse,
+    Result,
+};
+use futures::io::{AsyncReadExt, AsyncWriteExt};
+use futures::{Stream, StreamExt};
+use log::warn;
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::gridfs::{FilesCollectionDocument, GridFsDownloadStream};
+use mongodb::options::{GridFsFindOptions, GridFsUploadOptions};
+use std::fmt::Display;
+use std::str::FromStr;
+use warp::hyper::body::{Buf, Bytes};
+
+const LOG_TARGET: &str = "api::attachments";
+
+/// GridFS bucket the files go to, as `attachments.files` and
+/// `attachments.chunks`.
+pub const ATTACHMENT_BUCKET: &str = "attachments";
+
+pub const DEFAULT_MAX_ATTACHMENT_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Room for the multipart boundaries and part headers around the file.
+pub const MULTIPART_OVERHEAD_BYTES: u64 = 16 * 1024;
+
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Bytes read from GridFS per response chunk.
+const DOWNLOAD_CHUNK_BYTES: usize = 64 * 1024;
+
+impl DB {
+    /// Streams an uploaded file into GridFS, linked to the note through its
+    /// metadata. `None` if there is no such live note. A file going over
+    /// `max_attachment_bytes` is aborted, leaving no chunks behind.
+    pub async fn upload_attachment<S, B, E>(
+        &self,
+        note_id: &str,
+        filename: Option<&str>,
+        content_type: Option<&str>,
+        chunks: S,
+    ) -> Result<Option<AttachmentResponse>>
+    where
+        S: Stream<Item = std::result::Result<B, E>>,
+        B: Buf,
+        E: Display,
+    {
+        let note_oid =
+            ObjectId::from_str(note_id).map_err(|_| InvalidIDError(note_id.to_owned()))?;
+        let note = self
+            .collection
+            .find_one(live_note(note_oid), None)
+            .await
+            .map_err(MongoQueryError)?;
+        if note.is_none() {
+            return Ok(None);
+        }
+
+        let filename = attachment_filename(filename);
+        let content_type = content_type.unwrap_or(DEFAULT_CONTENT_TYPE);
+        let options = GridFsUploadOptions::builder()
+            .metadata(doc! {"noteId": note_oid, "contentType": content_type})
+            .build();
+        let mut upload = self.attachments.open_upload_stream(&filename, options);
+
+        futures::pin_mut!(chunks);
+        let mut size: u64 = 0;
+        while let Some(chunk) = chunks.next().await {
+            let mut chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    let _ = upload.abort().await;
+                    return Err(BadRequestError(format!("Upload was interrupted: {}", e)));
+                }
+            };
+            size += chunk.remaining() as u64;
+            if size > self.max_attachment_bytes {
+                let _ = upload.abort().await;
+                return Err(PayloadTooLargeError(format!(
+                    "Attachments may be at most {} bytes",
+                    self.max_attachment_bytes
+                )));
+            }
+            while chunk.has_remaining() {
+                let written = chunk.chunk().len();
+                if let Err(e) = upload.write_all(chunk.chunk()).await {
+                    let _ = upload.abort().await;
+                    return Err(AttachmentError(e.to_string()));
+                }
+                chunk.advance(written);
+            }
+        }
+        upload
+            .close()
+            .await
+            .map_err(|e| AttachmentError(e.to_string()))?;
+
+        let file = self
+            .attachment_file(upload.id().clone())
+            .await?
+            .ok_or_else(|| AttachmentError(format!("{} vanished after upload", filename)))?;
+        Ok(Some(attachment_to_response(&file)))
+    }
+
+    /// The note's attachments, oldest first.
+    pub async fn list_attachments(&self, note_id: &str) -> Result<Option<Vec<AttachmentResponse>>> {
+        let note_oid =
+            ObjectId::from_str(note_id).map_err(|_| InvalidIDError(note_id.to_owned()))?;
+        let note = self
+            .collection
+            .find_one(live_note(note_oid), None)
+            .await
+            .map_err(MongoQueryError)?;
+        if note.is_none() {
+            return Ok(None);
+        }
+
+        let options = GridFsFindOptions::builder()
+            .sort(doc! {"uploadDate": 1, "_id": 1})
+            .build();
+        let mut cursor = self
+            .attachments
+            .find(doc! {"metadata.noteId": note_oid}, options)
+            .await
+            .map_err(MongoQueryError)?;
+        let mut attachments = Vec::new();
+        while let Some(file) = cursor.next().await {
+            attachments.push(attachment_to_response(&file.map_err(MongoQueryError)?));
+        }
+        Ok(Some(attachments))
+    }
+
+    /// The attachment and a stream of its bytes. `None` when there is no
+    /// such file or its note is gone.
+    pub async fn open_attachment(
+        &self,
+        file_id: &str,
+    ) -> Result<Option<(AttachmentResponse, GridFsDownloadStream)>> {
+        let file_oid =
+            ObjectId::from_str(file_id).map_err(|_| InvalidIDError(file_id.to_owned()))?;
+        let Some(file) = self.attachment_file(file_oid.into()).await? else {
+            return Ok(None);
+        };
+        let Some(note_oid) = attachment_note(&file) else {
+            return Ok(None);
+        };
+        let note = self
+            .collection
+            .find_one(live_note(note_oid), None)
+            .await
+            .map_err(MongoQueryError)?;
+        if note.is_none() {
+            return Ok(None);
+        }
+
+        let stream = self
+            .attachments
+            .open_download_stream(file.id.clone())
+            .await
+            .map_err(MongoQueryError)?;
+        Ok(Some((attachment_to_response(&file), stream)))
+    }
+
+    /// Called once notes are gone for good. The notes are already deleted,
+    /// so a failure is only logged.
+    pub async fn delete_attachments_of(&self, note_oids: &[ObjectId]) {
+        self.delete_attachments(doc! {"metadata.noteId": {"$in": note_oids}})
+            .await;
+    }
+
+    pub(crate) async fn delete_attachments(&self, filter: Document) {
+        let mut cursor = match self.attachments.find(filter, None).await {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                warn!(target: LOG_TARGET, "Could not look up attachments to delete: {:?}", e);
+                return;
+            }
+        };
+        let mut ids = Vec::new();
+        while let Some(file) = cursor.next().await {
+            match file {
+                Ok(file) => ids.push(file.id),
+                Err(e) => {
+                    warn!(target: LOG_TARGET, "Could not read an attachment to delete: {:?}", e)
+                }
+            }
+        }
+        for id in ids {
+            if let Err(e) = self.attachments.delete(id.clone()).await {
+                warn!(target: LOG_TARGET, "Could not delete attachment {}: {:?}", id, e);
+            }
+        }
+    }
+
+    async fn attachment_file(&self, id: Bson) -> Result<Option<FilesCollectionDocument>> {
+        let mut cursor = self
+            .attachments
+            .find(doc! {"_id": id}, None)
+            .await
+            .map_err(MongoQueryError)?;
+        cursor.next().await.transpose().map_err(MongoQueryError)
+    }
+}
+
+/// The response body for a download, read from GridFS as the client takes
+/// it. An error ends the body early; the status has been sent already.
+pub fn download_body(
+    stream: GridFsDownloadStream,
+) -> impl Stream<Item = std::io::Result<Bytes>> + Send {
+    futures::stream::unfold(Some(stream), |stream| async move {
+        let mut stream = stream?;
+        let mut buffer = vec![0; DOWNLOAD_CHUNK_BYTES];
+        match stream.read(&mut buffer).await {
+            Ok(0) => None,
+            Ok(read) => {
+                buffer.truncate(read);
+                Some((Ok(Bytes::from(buffer)), Some(stream)))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+}
+
+/// `Content-Disposition` for a download. The plain `filename` keeps only
+/// printable ASCII; `filename*` carries the full name.
+pub fn content_disposition(filename: &str) -> String {
+    let ascii: String = filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        ascii,
+        percent_encoding::utf8_percent_encode(filename, percent_encoding::NON_ALPHANUMERIC)
+    )
+}
+
+/// The client's filename without any directory part.
+fn attachment_filename(filename: Option<&str>) -> String {
+    filename
+        .and_then(|filename| filename.rsplit(['/', '\\']).next())
+        .map(str::trim)
+        .filter(|filename| !filename.is_empty())
+        .unwrap_or("attachment")
+        .to_owned()
+}
+
+fn attachment_note(file: &FilesCollectionDocument) -> Option<ObjectId> {
+    file.metadata
+        .as_ref()
+        .and_then(|metadata| metadata.get_object_id("noteId").ok())
+}
+
+fn attachment_to_response(file: &FilesCollectionDocument) -> AttachmentResponse {
+    let content_type = file
+        .metadata
+        .as_ref()
+        .and_then(|metadata| metadata.get_str("contentType").ok())
+        .unwrap_or(DEFAULT_CONTENT_TYPE);
+    AttachmentResponse {
+        id: match &file.id {
+            Bson::ObjectId(id) => id.to_hex(),
+            id => id.to_string(),
+        },
+        noteId: attachment_note(file)
+            .map(|id| id.to_hex())
+            .unwrap_or_default(),
+        filename: file.filename.clone().unwrap_or_default(),
+        size: file.length,
+        contentType: content_type.to_owned(),
+        uploadedAt: file.upload_date.to_chrono(),
+    }
+}
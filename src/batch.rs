@@ -0,0 +1,110 @@
+use crate::{
+    db::DB,
+    error::Error::*,
+    model::NoteModel,
+    response::{BatchGetResponse, NoteListResponse, NoteResponse},
+    Result,
+};
+use futures::StreamExt;
+use log::error;
+use mongodb::bson::{doc, oid::ObjectId};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use tokio::task::JoinSet;
+
+pub const MAX_BATCH_IDS: usize = 100;
+
+/// Ids per `$in` query; large `$in` lists plan and run worse than several
+/// small ones.
+const BATCH_CHUNK_SIZE: usize = 50;
+
+/// How many chunk queries run at once.
+const BATCH_CONCURRENCY: usize = 4;
+
+const LOG_TARGET: &str = "api::batch";
+
+impl DB {
+    /// The notes with the given ids, in the order asked for, plus the ids
+    /// that matched nothing. Any malformed id fails the whole request.
+    pub async fn notes_by_ids(&self, ids: &[String]) -> Result<BatchGetResponse> {
+        let mut wanted: Vec<ObjectId> = Vec::new();
+        let mut seen: HashSet<ObjectId> = HashSet::new();
+        for id in ids {
+            let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+            if seen.insert(oid) {
+                wanted.push(oid);
+            }
+        }
+
+        let mut found: HashMap<ObjectId, NoteResponse> = HashMap::new();
+        let chunk_count = wanted.len().div_ceil(BATCH_CHUNK_SIZE);
+        let mut chunks = wanted.chunks(BATCH_CHUNK_SIZE).enumerate();
+        let mut queries = JoinSet::new();
+        loop {
+            while queries.len() < BATCH_CONCURRENCY {
+                let Some((index, chunk)) = chunks.next() else {
+                    break;
+                };
+                let db = self.clone();
+                let chunk = chunk.to_vec();
+                queries.spawn(async move { (index, db.notes_in(&chunk).await) });
+            }
+            let Some(notes) = queries.join_next().await else {
+                break;
+            };
+            let (index, notes) = notes.map_err(|e| UnavailableError(e.to_string()))?;
+            // Returning drops the set, which cancels the chunks still running.
+            let notes = notes.inspect_err(|e| {
+                error!(
+                    target: LOG_TARGET,
+                    "Batch get chunk {} of {} failed: {}",
+                    index + 1,
+                    chunk_count,
+                    e
+                )
+            })?;
+            for note in notes {
+                found.insert(note.id, self.doc_to_note(&note)?);
+            }
+        }
+
+        let mut notes = Vec::new();
+        let mut missing = Vec::new();
+        for oid in wanted {
+            match found.remove(&oid) {
+                Some(note) => notes.push(note.into()),
+                None => missing.push(oid.to_hex()),
+            }
+        }
+
+        Ok(BatchGetResponse {
+            list: NoteListResponse {
+                status: "success".to_string(),
+                results: notes.len(),
+                page_info: None,
+                collation: None,
+                facets: None,
+                server_time: None,
+                next_cursor: None,
+                has_next: None,
+                has_prev: None,
+                notes,
+            },
+            missing,
+        })
+    }
+
+    async fn notes_in(&self, ids: &[ObjectId]) -> Result<Vec<NoteModel>> {
+        let mut cursor = self
+            .note_collection
+            .find(doc! {"_id": {"$in": ids}, "deletedAt": null}, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        let mut notes = Vec::new();
+        while let Some(note) = cursor.next().await {
+            notes.push(note.map_err(MongoQueryError)?);
+        }
+        Ok(notes)
+    }
+}
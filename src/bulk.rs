@@ -0,0 +1,112 @@
+use crate::{
+    db::DB,
+    error::{Error::*, DUPLICATE_KEY},
+    events::NoteEvent,
+    positions::POSITION_GAP,
+    response::{BulkCreateResult, NoteData, SingleNoteResponse},
+    schema::CreateNoteSchema,
+    Result,
+};
+use futures::StreamExt;
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::error::ErrorKind;
+use mongodb::options::InsertManyOptions;
+use std::collections::HashMap;
+
+pub const MAX_BULK_NOTES: usize = 500;
+
+impl DB {
+    /// Inserts the notes in one unordered `insert_many`, so a duplicate title
+    /// only fails its own item. Each note keeps the index it was submitted
+    /// under, which is what the results report.
+    pub async fn create_notes(
+        &self,
+        notes: &[(usize, CreateNoteSchema)],
+    ) -> Result<Vec<BulkCreateResult>> {
+        if notes.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Ids are assigned here because a partially failed insert_many does
+        // not report which ids it did insert.
+        let mut ids = Vec::with_capacity(notes.len());
+        let mut slugs = Vec::with_capacity(notes.len());
+        let mut documents = Vec::with_capacity(notes.len());
+        let mut position = self.next_position().await?;
+        for (_, body) in notes {
+            let id = ObjectId::new();
+            let slug = self.free_slug(&body.title, None, &slugs).await?;
+            let mut document = self.new_note_document(body)?;
+            document.insert("_id", id);
+            document.insert("slug", &slug);
+            document.insert("position", position);
+            position += POSITION_GAP;
+            ids.push(id);
+            slugs.push(slug);
+            documents.push(document);
+        }
+
+        let options = InsertManyOptions::builder().ordered(false).build();
+        let mut failures: HashMap<usize, BulkCreateResult> = HashMap::new();
+        if let Err(e) = self.collection.insert_many(documents, options).await {
+            let ErrorKind::BulkWrite(failure) = e.kind.as_ref() else {
+                return Err(MongoQueryError(e));
+            };
+            if failure.write_concern_error.is_some() {
+                return Err(MongoQueryError(e));
+            }
+            for write_error in failure.write_errors.iter().flatten() {
+                let index = notes[write_error.index].0;
+                let result = match write_error.code {
+                    DUPLICATE_KEY => BulkCreateResult::failed(
+                        index,
+                        "DUPLICATE_TITLE",
+                        format!(
+                            "a note titled '{}' already exists",
+                            notes[write_error.index].1.title
+                        ),
+                    ),
+                    _ => {
+                        BulkCreateResult::failed(index, "WRITE_FAILED", write_error.message.clone())
+                    }
+                };
+                failures.insert(write_error.index, result);
+            }
+        }
+
+        let inserted: Vec<ObjectId> = ids
+            .iter()
+            .enumerate()
+            .filter(|(position, _)| !failures.contains_key(position))
+            .map(|(_, id)| *id)
+            .collect();
+        self.adjust_note_count(inserted.len() as i64).await;
+
+        let mut cursor = self
+            .note_collection
+            .find(doc! {"_id": {"$in": &inserted}}, None)
+            .await
+            .map_err(MongoQueryError)?;
+        while let Some(note) = cursor.next().await {
+            let note_response = SingleNoteResponse {
+                status: "success".to_string(),
+                data: NoteData {
+                    note: self.doc_to_note(&note.map_err(MongoQueryError)?)?,
+                    draft: None,
+                },
+            };
+            self.events.publish(NoteEvent::created(&note_response));
+        }
+
+        Ok(notes
+            .iter()
+            .zip(ids)
+            .enumerate()
+            .map(|(position, ((index, _), id))| {
+                failures
+                    .remove(&position)
+                    .unwrap_or_else(|| BulkCreateResult::created(*index, id.to_hex()))
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{
+    db::{live_note, DB},
+    error::Error::*,
+    events::NoteEvent,
+    model::NoteModel,
+    replies::not_found_message,
+    response::{FieldError, NoteData, SingleNoteResponse},
+    schema::{ChecklistItemSchema, UpdateChecklistItemSchema},
+    Result,
+};
+use chrono::Utc;
+use mongodb::bson::{self, doc, oid::ObjectId, Bson, Document};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument, UpdateModifications};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub const MAX_ITEM_TEXT_LENGTH: usize = 500;
+
+/// One entry of a note's checklist, stored in its `items` array. Items are
+/// addressed by their index in that array; `position` is only an ordering
+/// hint for clients and is not renumbered when an item is removed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChecklistItem {
+    pub text: String,
+    #[serde(default)]
+    pub done: bool,
+    #[serde(default)]
+    pub position: i64,
+}
+
+/// Item text is stored trimmed and may not be empty.
+pub fn normalize_item_text(field: &str, text: &str) -> Result<String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(ValidationError(vec![item_error(
+            field,
+            "REQUIRED",
+            "Item text must not be empty".to_string(),
+        )]));
+    }
+    if text.chars().count() > MAX_ITEM_TEXT_LENGTH {
+        return Err(ValidationError(vec![item_error(
+            field,
+            "TOO_LONG",
+            format!(
+                "Item text must be at most {} characters",
+                MAX_ITEM_TEXT_LENGTH
+            ),
+        )]));
+    }
+    Ok(text.to_owned())
+}
+
+/// The stored form of a whole checklist sent with a note, positioned in the
+/// order given. Every invalid item is reported at once.
+pub fn checklist_items(items: &[ChecklistItemSchema]) -> Result<Bson> {
+    let mut checklist = Vec::with_capacity(items.len());
+    let mut errors = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        match normalize_item_text(&format!("items[{}].text", index), &item.text) {
+            Ok(text) => checklist.push(ChecklistItem {
+                text,
+                done: item.done,
+                position: index as i64,
+            }),
+            Err(ValidationError(item_errors)) => errors.extend(item_errors),
+            Err(e) => return Err(e),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(ValidationError(errors));
+    }
+    bson::to_bson(&checklist).map_err(MongoSerializeBsonError)
+}
+
+impl DB {
+    /// Appends an item, positioned after the last one.
+    pub async fn add_checklist_item(
+        &self,
+        id: &str,
+        item: &ChecklistItemSchema,
+    ) -> Result<Option<SingleNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+        let text = normalize_item_text("text", &item.text)?;
+
+        // `$literal` keeps text starting with `$` from reading as a field path.
+        let new_item = doc! {
+            "text": {"$literal": text},
+            "done": item.done,
+            "position": {"$add": [{"$ifNull": [{"$max": "$items.position"}, -1]}, 1]},
+        };
+        let update = vec![doc! {"$set": {
+            "items": {"$concatArrays": [{"$ifNull": ["$items", []]}, [new_item]]},
+            "updatedAt": Utc::now(),
+        }}];
+        self.update_checklist(live_note(oid), update).await
+    }
+
+    /// Toggles `done` and/or rewrites the text of the item at `index`.
+    pub async fn update_checklist_item(
+        &self,
+        id: &str,
+        index: usize,
+        body: &UpdateChecklistItemSchema,
+    ) -> Result<Option<SingleNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+        if body.text.is_none() && body.done.is_none() {
+            return Err(BadRequestError(
+                "Body must set at least one of text or done".to_string(),
+            ));
+        }
+
+        let mut set = doc! {"updatedAt": Utc::now()};
+        if let Some(text) = &body.text {
+            set.insert(
+                format!("items.{}.text", index),
+                normalize_item_text("text", text)?,
+            );
+        }
+        if let Some(done) = body.done {
+            set.insert(format!("items.{}.done", index), done);
+        }
+        self.update_checklist_at(oid, index, doc! {"$set": set})
+            .await
+    }
+
+    /// Removes the item at `index`; later items move up one index.
+    pub async fn remove_checklist_item(
+        &self,
+        id: &str,
+        index: usize,
+    ) -> Result<Option<SingleNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+
+        // `$slice` refuses a count of zero, so an item at the front only
+        // keeps the tail.
+        let tail = doc! {"$slice": ["$items", index as i64 + 1, {"$size": "$items"}]};
+        let items = match index {
+            0 => Bson::Document(tail),
+            _ => Bson::Document(doc! {"$concatArrays": [
+                {"$slice": ["$items", 0, index as i64]},
+                tail,
+            ]}),
+        };
+        let update = vec![doc! {"$set": {"items": items, "updatedAt": Utc::now()}}];
+        self.update_checklist_at(oid, index, update).await
+    }
+
+    /// Runs an update that needs the item at `index` to exist. An index out
+    /// of range on an existing note is a `NotFoundError`.
+    async fn update_checklist_at(
+        &self,
+        oid: ObjectId,
+        index: usize,
+        update: impl Into<UpdateModifications>,
+    ) -> Result<Option<SingleNoteResponse>> {
+        let mut filter = live_note(oid);
+        filter.insert(format!("items.{}", index), doc! {"$exists": true});
+        match self.update_checklist(filter, update).await? {
+            Some(note) => Ok(Some(note)),
+            None if self.note_exists(oid).await? => Err(NotFoundError(not_found_message(
+                "Checklist item",
+                &index.to_string(),
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    async fn update_checklist(
+        &self,
+        filter: Document,
+        update: impl Into<UpdateModifications>,
+    ) -> Result<Option<SingleNoteResponse>> {
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let note: Option<NoteModel> = self
+            .note_collection
+            .find_one_and_update(filter, update, options)
+            .await
+            .map_err(MongoQueryError)?;
+        let Some(note) = note else {
+            return Ok(None);
+        };
+
+        let note_response = SingleNoteResponse {
+            status: "success".to_string(),
+            data: NoteData {
+                note: self.doc_to_note(&note)?,
+                draft: None,
+            },
+        };
+        self.events
+            .publish(NoteEvent::changed(&note_response, &["items"]));
+
+        Ok(Some(note_response))
+    }
+}
+
+fn item_error(field: &str, code: &str, message: String) -> FieldError {
+    FieldError {
+        field: field.to_owned(),
+        code: code.to_string(),
+        message,
+    }
+}
@@ -0,0 +1,127 @@
+use crate::config::Config;
+use ipnet::IpNet;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use warp::Filter;
+
+/// Parses `TRUSTED_PROXIES`, a comma-separated list of CIDR ranges. A bare
+/// address counts as a single-host range.
+pub fn parse_trusted_proxies(value: &str) -> std::result::Result<Vec<IpNet>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| format!("'{}' is not a CIDR range or IP address", entry))
+        })
+        .collect()
+}
+
+/// The address of the client behind any trusted proxies.
+///
+/// `X-Forwarded-For` is only believed when the peer itself is trusted.
+/// The chain is then walked right to left, skipping trusted hops. The
+/// first untrusted address is the client. Anything left of it could have
+/// been written by the client, so it is ignored.
+pub fn resolve(
+    peer: Option<IpAddr>,
+    forwarded_for: Option<&str>,
+    trusted: &[IpNet],
+) -> Option<IpAddr> {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+
+    let mut client = peer?;
+    if !is_trusted(&client) {
+        return Some(client);
+    }
+
+    let Some(forwarded_for) = forwarded_for else {
+        return Some(client);
+    };
+    for hop in forwarded_for.rsplit(',') {
+        let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+            // A garbled entry ends the chain; the last good hop stands.
+            break;
+        };
+        client = hop;
+        if !is_trusted(&hop) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+pub fn with_client_ip(
+    config: Config,
+) -> impl Filter<Extract = (Option<IpAddr>,), Error = Infallible> + Clone {
+    warp::addr::remote()
+        .and(
+            warp::header::optional::<String>("x-forwarded-for")
+                .or(warp::any().map(|| None))
+                .unify(),
+        )
+        .map(
+            move |peer: Option<SocketAddr>, forwarded_for: Option<String>| {
+                resolve(
+                    peer.map(|addr| addr.ip()),
+                    forwarded_for.as_deref(),
+                    &config.trusted_proxies,
+                )
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_through_trusted_proxies_only() {
+        let trusted = parse_trusted_proxies("10.0.0.0/8, 192.168.1.1, fd00::/8").unwrap();
+        let cases = [
+            // Untrusted peer: a forged header is ignored.
+            ("203.0.113.9", Some("1.2.3.4"), "203.0.113.9"),
+            ("203.0.113.9", Some("10.0.0.1, 1.2.3.4"), "203.0.113.9"),
+            // Trusted peer without a header is the client itself.
+            ("10.0.0.2", None, "10.0.0.2"),
+            // Trusted chain: the first untrusted hop from the right.
+            ("10.0.0.2", Some("198.51.100.7"), "198.51.100.7"),
+            (
+                "10.0.0.2",
+                Some("6.6.6.6, 198.51.100.7, 192.168.1.1, 10.1.2.3"),
+                "198.51.100.7",
+            ),
+            // Every hop trusted: the leftmost one.
+            ("10.0.0.2", Some("10.9.9.9,10.1.2.3"), "10.9.9.9"),
+            // Malformed entries end the chain at the last good hop.
+            ("10.0.0.2", Some("198.51.100.7, garbage"), "10.0.0.2"),
+            ("10.0.0.2", Some("6.6.6.6, nonsense, 10.1.2.3"), "10.1.2.3"),
+            ("10.0.0.2", Some(""), "10.0.0.2"),
+            ("10.0.0.2", Some("198.51.100.7:443"), "10.0.0.2"),
+            // IPv6 peers and hops.
+            ("fd00::1", Some("2001:db8::1, fd12::3"), "2001:db8::1"),
+            ("2001:db8::9", Some("2001:db8::1"), "2001:db8::9"),
+            ("10.0.0.2", Some("2001:db8::1"), "2001:db8::1"),
+        ];
+        for (peer, forwarded_for, client) in cases {
+            assert_eq!(
+                resolve(Some(peer.parse().unwrap()), forwarded_for, &trusted),
+                Some(client.parse().unwrap()),
+                "peer {} with {:?}",
+                peer,
+                forwarded_for
+            );
+        }
+        assert_eq!(resolve(None, Some("1.2.3.4"), &trusted), None);
+    }
+
+    #[test]
+    fn nobody_is_trusted_by_default() {
+        let peer = "10.0.0.2".parse().unwrap();
+        assert_eq!(resolve(Some(peer), Some("1.2.3.4"), &[]), Some(peer));
+        assert!(parse_trusted_proxies("10.0.0.0/8, not-a-range").is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use crate::{error::Error::InvalidQueryError, Result};
+use mongodb::options::{Collation, CollationStrength};
+use serde::Serialize;
+
+/// Locales a client may ask to sort by. `simple` is Mongo's binary
+/// comparison, i.e. what listings did before collations were supported.
+pub const SUPPORTED_LOCALES: &[&str] = &[
+    "simple", "en", "de", "fr", "es", "it", "nl", "pt", "sv", "da", "nb", "fi", "pl", "cs", "tr",
+];
+
+/// Tertiary: accents and case both matter, Mongo's own default.
+pub const DEFAULT_STRENGTH: u8 = 3;
+
+/// Secondary: accents matter, case does not.
+pub const CASE_INSENSITIVE_STRENGTH: u8 = 2;
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CollationSpec {
+    pub locale: String,
+    pub strength: u8,
+}
+
+impl CollationSpec {
+    pub fn parse(locale: &str, strength: Option<u8>) -> Result<Self> {
+        let locale = locale.trim().to_ascii_lowercase();
+        if !SUPPORTED_LOCALES.contains(&locale.as_str()) {
+            return Err(InvalidQueryError(format!(
+                "Unsupported collation '{}', expected one of: {}",
+                locale,
+                SUPPORTED_LOCALES.join(", ")
+            )));
+        }
+
+        let strength = strength.unwrap_or(DEFAULT_STRENGTH);
+        if !(1..=5).contains(&strength) {
+            return Err(InvalidQueryError(format!(
+                "Collation strength must be between 1 and 5, got {}",
+                strength
+            )));
+        }
+
+        Ok(Self { locale, strength })
+    }
+
+    /// Name of the title index built with this collation, so indexes for
+    /// different defaults can live side by side.
+    pub fn index_name(&self) -> String {
+        format!("title_{}_{}", self.locale, self.strength)
+    }
+
+    /// Name of the newest-first index built with this collation.
+    pub fn created_index_name(&self) -> String {
+        format!("createdAt_{}_{}", self.locale, self.strength)
+    }
+
+    /// Name of the pinned-first, newest-first index built with this
+    /// collation.
+    pub fn pinned_index_name(&self) -> String {
+        format!("pinned_createdAt_{}_{}", self.locale, self.strength)
+    }
+
+    /// Name of the pinned-first, manual order index built with this
+    /// collation.
+    pub fn position_index_name(&self) -> String {
+        format!("pinned_position_{}_{}", self.locale, self.strength)
+    }
+
+    /// Name of the category index built with this collation.
+    pub fn category_index_name(&self) -> String {
+        format!("category_{}_{}", self.locale, self.strength)
+    }
+
+    pub fn to_mongo(&self) -> Collation {
+        let strength = match self.strength {
+            1 => CollationStrength::Primary,
+            2 => CollationStrength::Secondary,
+            3 => CollationStrength::Tertiary,
+            4 => CollationStrength::Quaternary,
+            _ => CollationStrength::Identical,
+        };
+        let builder = Collation::builder().locale(self.locale.clone());
+        // The simple locale rejects every other collation option.
+        if self.locale == "simple" {
+            builder.build()
+        } else {
+            builder.strength(strength).build()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::ListedNote;
+    use crate::schema::{
+        CreateNoteSchema, ListExtras, ListOrder, NoteListFilter, NoteSortField, NotesQuery, Page,
+        SortKey,
+    };
+    use crate::test_support::{config, drop_live_db, live_db};
+
+    #[test]
+    fn parses_supported_locales_and_strengths() {
+        assert_eq!(
+            CollationSpec::parse(" DE ", None).unwrap(),
+            CollationSpec {
+                locale: "de".to_string(),
+                strength: DEFAULT_STRENGTH
+            }
+        );
+        assert_eq!(CollationSpec::parse("sv", Some(1)).unwrap().strength, 1);
+
+        for (locale, strength, message) in [
+            (
+                "xx",
+                None,
+                "Unsupported collation 'xx', expected one of: simple, en,",
+            ),
+            (
+                "en",
+                Some(0),
+                "Collation strength must be between 1 and 5, got 0",
+            ),
+            (
+                "en",
+                Some(6),
+                "Collation strength must be between 1 and 5, got 6",
+            ),
+        ] {
+            match CollationSpec::parse(locale, strength) {
+                Err(InvalidQueryError(e)) => assert!(e.starts_with(message), "{}", e),
+                other => panic!("{} {:?} was accepted: {:?}", locale, strength, other),
+            }
+        }
+    }
+
+    #[test]
+    fn index_names_carry_locale_and_strength() {
+        let spec = CollationSpec::parse("sv", Some(2)).unwrap();
+        assert_eq!(
+            [
+                spec.index_name(),
+                spec.created_index_name(),
+                spec.pinned_index_name(),
+                spec.position_index_name(),
+                spec.category_index_name(),
+            ],
+            [
+                "title_sv_2",
+                "createdAt_sv_2",
+                "pinned_createdAt_sv_2",
+                "pinned_position_sv_2",
+                "category_sv_2",
+            ]
+        );
+    }
+
+    #[test]
+    fn simple_takes_no_strength() {
+        let simple = CollationSpec::parse("simple", None).unwrap().to_mongo();
+        assert_eq!(simple.locale, "simple");
+        assert!(simple.strength.is_none());
+        let de = CollationSpec::parse("de", Some(2)).unwrap().to_mongo();
+        assert!(matches!(de.strength, Some(CollationStrength::Secondary)));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn titles_sort_by_the_requested_locale() {
+        let db = live_db(&config()).await;
+        for title in ["Zebra", "Öl", "Birne", "Äpfel", "Apfel"] {
+            let body: CreateNoteSchema =
+                serde_json::from_value(serde_json::json!({"title": title, "content": "x"}))
+                    .unwrap();
+            db.create_note(&body).await.unwrap();
+        }
+
+        let titles = |locale: &str| {
+            let query = NotesQuery {
+                filter: NoteListFilter::default(),
+                order: ListOrder::Sorted(vec![SortKey {
+                    field: NoteSortField::Title,
+                    descending: false,
+                }]),
+                page: Page { page: 1, limit: 10 },
+                collation: Some(CollationSpec::parse(locale, None).unwrap()),
+                extras: ListExtras {
+                    facets: false,
+                    total: false,
+                },
+                projection: None,
+            };
+            let db = db.clone();
+            async move {
+                let list = db.fetch_notes(&query).await.unwrap();
+                list.notes
+                    .into_iter()
+                    .map(|note| match note {
+                        ListedNote::Full(note) => note.title,
+                        ListedNote::Partial(_) => panic!("no projection was asked for"),
+                    })
+                    .collect::<Vec<String>>()
+            }
+        };
+
+        // German files umlauts with their base letter, Swedish after z.
+        assert_eq!(
+            titles("de").await,
+            ["Apfel", "Äpfel", "Birne", "Öl", "Zebra"]
+        );
+        assert_eq!(
+            titles("sv").await,
+            ["Apfel", "Birne", "Zebra", "Äpfel", "Öl"]
+        );
+        drop_live_db(&db).await;
+    }
+}
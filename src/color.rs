@@ -0,0 +1,25 @@
+use crate::{error::Error::*, response::FieldError, Result};
+
+/// Colors a note may be labelled with by name; anything else has to be a
+/// `#rrggbb` value.
+pub const NAMED_COLORS: &[&str] = &["red", "green", "blue", "yellow", "purple", "gray"];
+
+/// Lowercases `value` and checks that it is a named color or `#rrggbb`.
+pub fn normalize_color(field: &str, value: &str) -> Result<String> {
+    let color = value.trim().to_lowercase();
+    let is_hex = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if !is_hex && !NAMED_COLORS.contains(&color.as_str()) {
+        return Err(ValidationError(vec![FieldError {
+            field: field.to_owned(),
+            code: "INVALID_COLOR".to_string(),
+            message: format!(
+                "{} must be one of {} or a #rrggbb value",
+                field,
+                NAMED_COLORS.join(", ")
+            ),
+        }]));
+    }
+    Ok(color)
+}
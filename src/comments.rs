@@ -0,0 +1,150 @@
+use crate::{
+    db::{live_note, DB},
+    error::Error::*,
+    model::CommentModel,
+    replies::not_found_message,
+    response::{CommentResponse, FieldError, PageTotal, Paginated, TotalSource},
+    schema::{CreateCommentSchema, Page},
+    Result,
+};
+use chrono::Utc;
+use futures::StreamExt;
+use log::warn;
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::options::FindOptions;
+use std::str::FromStr;
+
+const LOG_TARGET: &str = "api::comments";
+
+impl DB {
+    /// Adds a comment to a live note. `None` if there is no such note, so
+    /// that no comment is left pointing at nothing.
+    pub async fn create_comment(
+        &self,
+        note_id: &str,
+        body: &CreateCommentSchema,
+    ) -> Result<Option<CommentResponse>> {
+        let note_oid =
+            ObjectId::from_str(note_id).map_err(|_| InvalidIDError(note_id.to_owned()))?;
+        let errors: Vec<FieldError> = [("author", &body.author), ("body", &body.body)]
+            .into_iter()
+            .filter(|(_, value)| value.trim().is_empty())
+            .map(|(field, _)| FieldError {
+                field: field.to_string(),
+                code: "REQUIRED".to_string(),
+                message: format!("{} must not be empty", field),
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(ValidationError(errors));
+        }
+
+        let note = self
+            .collection
+            .find_one(live_note(note_oid), None)
+            .await
+            .map_err(MongoQueryError)?;
+        if note.is_none() {
+            return Ok(None);
+        }
+
+        let comment = CommentModel {
+            id: ObjectId::new(),
+            noteId: note_oid,
+            author: body.author.trim().to_owned(),
+            body: body.body.to_owned(),
+            createdAt: Utc::now(),
+        };
+        self.comment_collection
+            .insert_one(&comment, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        Ok(Some(comment_to_response(&comment)))
+    }
+
+    /// The note's comments, newest first.
+    pub async fn list_note_comments(
+        &self,
+        note_id: &str,
+        page: &Page,
+    ) -> Result<Paginated<CommentResponse>> {
+        let note_oid =
+            ObjectId::from_str(note_id).map_err(|_| InvalidIDError(note_id.to_owned()))?;
+        let note = self
+            .collection
+            .find_one(live_note(note_oid), None)
+            .await
+            .map_err(MongoQueryError)?;
+        if note.is_none() {
+            return Err(NotFoundError(not_found_message("Note", note_id)));
+        }
+
+        let filter = doc! {"noteId": note_oid};
+        let total = self
+            .comment_collection
+            .count_documents(filter.clone(), None)
+            .await
+            .map_err(MongoQueryError)?;
+        let options = FindOptions::builder()
+            .sort(doc! {"createdAt": -1, "_id": -1})
+            .skip(page.skip())
+            .limit(page.limit as i64)
+            .build();
+        let mut cursor = self
+            .comment_collection
+            .find(filter, options)
+            .await
+            .map_err(MongoQueryError)?;
+
+        let mut comments = Vec::new();
+        while let Some(comment) = cursor.next().await {
+            comments.push(comment_to_response(&comment.map_err(MongoQueryError)?));
+        }
+        Ok(Paginated::new(
+            comments,
+            page,
+            PageTotal {
+                value: Some(total),
+                source: TotalSource::Count,
+            },
+        ))
+    }
+
+    /// `None` if the note has no such comment.
+    pub async fn delete_comment(&self, note_id: &str, comment_id: &str) -> Result<Option<()>> {
+        let note_oid =
+            ObjectId::from_str(note_id).map_err(|_| InvalidIDError(note_id.to_owned()))?;
+        let comment_oid =
+            ObjectId::from_str(comment_id).map_err(|_| InvalidIDError(comment_id.to_owned()))?;
+
+        let result = self
+            .comment_collection
+            .delete_one(doc! {"_id": comment_oid, "noteId": note_oid}, None)
+            .await
+            .map_err(MongoQueryError)?;
+        Ok((result.deleted_count > 0).then_some(()))
+    }
+
+    /// Called once notes are gone for good. The notes are already deleted,
+    /// so a failure is only logged.
+    pub async fn delete_comments_of(&self, note_oids: &[ObjectId]) {
+        let result = self
+            .comment_collection
+            .delete_many(doc! {"noteId": {"$in": note_oids}}, None)
+            .await;
+        if let Err(e) = result {
+            warn!(target: LOG_TARGET, "Could not delete comments of {:?}: {:?}", note_oids, e);
+        }
+    }
+}
+
+fn comment_to_response(comment: &CommentModel) -> CommentResponse {
+    CommentResponse {
+        id: comment.id.to_hex(),
+        noteId: comment.noteId.to_hex(),
+        author: comment.author.to_owned(),
+        body: comment.body.to_owned(),
+        createdAt: comment.createdAt,
+    }
+}
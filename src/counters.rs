@@ -0,0 +1,220 @@
+use crate::{
+    collation::CollationSpec,
+    db::{list_filter, DB},
+    error::{mongo_error_code, Error::*},
+    response::{PageTotal, TotalSource},
+    schema::NoteListFilter,
+    Result,
+};
+use chrono::Utc;
+use log::{error, warn};
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{CountOptions, UpdateOptions};
+use std::time::Duration;
+
+const LOG_TARGET: &str = "api::counters";
+
+/// Counter document holding the total of the default, unfiltered list.
+const NOTES_COUNTER: &str = "notes";
+
+/// The notes `NOTES_COUNTER` counts: those an unfiltered list shows, so
+/// archived notes are left out.
+pub fn counted_filter() -> Document {
+    list_filter(&NoteListFilter::default(), None)
+}
+
+/// Filtered totals are real counts, capped so a slow count cannot hold up
+/// the page it belongs to.
+const COUNT_MAX_TIME: Duration = Duration::from_millis(500);
+
+/// Mongo's `MaxTimeMSExpired`.
+const MAX_TIME_MS_EXPIRED: i32 = 50;
+
+impl DB {
+    /// Total for a list request: the maintained counter when nothing is
+    /// filtered, otherwise a time-capped `count_documents`.
+    pub async fn note_total(
+        &self,
+        filter: &NoteListFilter,
+        collation: Option<&CollationSpec>,
+    ) -> Result<PageTotal> {
+        let query = list_filter(filter, None);
+        if query == counted_filter() {
+            if let Some(count) = self.note_counter().await? {
+                return Ok(PageTotal {
+                    value: Some(count),
+                    source: TotalSource::Counter,
+                });
+            }
+        }
+
+        let options = CountOptions::builder()
+            .collation(collation.map(CollationSpec::to_mongo))
+            .max_time(COUNT_MAX_TIME)
+            .build();
+        match self.collection.count_documents(query, options).await {
+            Ok(count) => Ok(PageTotal {
+                value: Some(count),
+                source: TotalSource::Count,
+            }),
+            Err(e) if mongo_error_code(&e) == Some(MAX_TIME_MS_EXPIRED) => Ok(PageTotal {
+                value: None,
+                source: TotalSource::Unavailable,
+            }),
+            Err(e) => Err(MongoQueryError(e)),
+        }
+    }
+
+    /// Exact number of notes matching `filter`. Unlike `note_total` this
+    /// never falls back to the counter or gives up on a slow count.
+    pub async fn count_notes(
+        &self,
+        filter: &NoteListFilter,
+        collation: Option<CollationSpec>,
+    ) -> Result<u64> {
+        let collation = collation.unwrap_or_else(|| self.default_collation.clone());
+        let options = CountOptions::builder()
+            .collation(collation.to_mongo())
+            .build();
+        self.collection
+            .count_documents(list_filter(filter, None), options)
+            .await
+            .map_err(MongoQueryError)
+    }
+
+    /// Applies a create (+1) or delete (-1) of a counted note to the counter. The note write has
+    /// already succeeded, so a failure here is only logged; reconciliation
+    /// corrects the drift.
+    pub async fn adjust_note_count(&self, delta: i64) {
+        let result = self
+            .counter_collection
+            .update_one(
+                doc! {"_id": NOTES_COUNTER},
+                doc! {"$inc": {"count": delta}},
+                None,
+            )
+            .await;
+        if let Err(e) = result {
+            warn!(target: LOG_TARGET, "Could not adjust note counter by {}: {:?}", delta, e);
+        }
+    }
+
+    /// Recomputes the counter from a real count and returns it.
+    pub async fn reconcile_note_count(&self) -> Result<u64> {
+        let count = self
+            .collection
+            .count_documents(counted_filter(), None)
+            .await
+            .map_err(MongoQueryError)?;
+        let options = UpdateOptions::builder().upsert(true).build();
+        self.counter_collection
+            .update_one(
+                doc! {"_id": NOTES_COUNTER},
+                doc! {"$set": {"count": count as i64, "reconciledAt": Utc::now()}},
+                options,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+        Ok(count)
+    }
+
+    async fn note_counter(&self) -> Result<Option<u64>> {
+        let counter = self
+            .counter_collection
+            .find_one(doc! {"_id": NOTES_COUNTER}, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        Ok(counter.and_then(|counter| match counter.get("count") {
+            Some(Bson::Int64(n)) => Some((*n).max(0) as u64),
+            Some(Bson::Int32(n)) => Some((*n).max(0) as u64),
+            _ => None,
+        }))
+    }
+}
+
+/// Re-counts every `interval` to correct drift from failed adjustments.
+pub async fn reconcile_periodically(db: DB, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Err(e) = db.reconcile_note_count().await {
+            error!(target: LOG_TARGET, "Could not reconcile note counter: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::CreateNoteSchema;
+    use crate::test_support::{config, drop_live_db, live_db};
+
+    async fn assert_counter_is_exact(db: &DB, step: &str) {
+        let filter = NoteListFilter::default();
+        let total = db.note_total(&filter, None).await.unwrap();
+        assert!(matches!(total.source, TotalSource::Counter), "{}", step);
+        let count = db.count_notes(&filter, None).await.unwrap();
+        assert_eq!(total.value, Some(count), "after {}", step);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn the_counter_follows_every_kind_of_write() {
+        let db = live_db(&config()).await;
+        let notes: Vec<(usize, CreateNoteSchema)> = (0..6)
+            .map(|n| {
+                let category = if n < 3 { "keep" } else { "bulk" };
+                let body = serde_json::json!({
+                    "title": format!("Note {}", n),
+                    "content": "x",
+                    "category": category,
+                });
+                (n, serde_json::from_value(body).unwrap())
+            })
+            .collect();
+        db.create_notes(&notes).await.unwrap();
+        let single: CreateNoteSchema =
+            serde_json::from_value(serde_json::json!({"title": "Single", "content": "x"})).unwrap();
+        let id = db.create_note(&single).await.unwrap().data.note.id;
+        assert_counter_is_exact(&db, "creating").await;
+
+        db.set_archived(&id, true).await.unwrap().unwrap();
+        assert_counter_is_exact(&db, "archiving").await;
+        db.set_archived(&id, false).await.unwrap().unwrap();
+        assert_counter_is_exact(&db, "unarchiving").await;
+        db.delete_note(&id, None, false).await.unwrap().unwrap();
+        assert_counter_is_exact(&db, "trashing").await;
+        db.restore_note(&id).await.unwrap().unwrap();
+        assert_counter_is_exact(&db, "restoring").await;
+        db.set_archived(&id, true).await.unwrap().unwrap();
+        db.delete_note(&id, None, true).await.unwrap().unwrap();
+        assert_counter_is_exact(&db, "deleting an archived note").await;
+
+        let bulk = NoteListFilter {
+            categories: vec!["bulk".to_string()],
+            ..NoteListFilter::default()
+        };
+        assert_eq!(db.delete_notes(&bulk, None, false).await.unwrap(), 3);
+        assert_counter_is_exact(&db, "bulk trashing").await;
+        let keep = NoteListFilter {
+            categories: vec!["keep".to_string()],
+            ..NoteListFilter::default()
+        };
+        assert_eq!(db.delete_notes(&keep, None, true).await.unwrap(), 3);
+        assert_counter_is_exact(&db, "bulk deleting").await;
+
+        db.counter_collection
+            .update_one(
+                doc! {"_id": NOTES_COUNTER},
+                doc! {"$inc": {"count": 5}},
+                None,
+            )
+            .await
+            .unwrap();
+        let drifted = db.note_total(&NoteListFilter::default(), None).await;
+        assert_eq!(drifted.unwrap().value, Some(5));
+        assert_eq!(db.reconcile_note_count().await.unwrap(), 0);
+        assert_counter_is_exact(&db, "reconciling").await;
+        drop_live_db(&db).await;
+    }
+}
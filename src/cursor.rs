@@ -0,0 +1,185 @@
+use crate::{
+    error::{mongo_error_code, Error::*},
+    Result,
+};
+use futures::StreamExt;
+use log::warn;
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::options::FindOptions;
+use mongodb::{Collection, Cursor};
+
+const LOG_TARGET: &str = "api::cursor";
+
+/// Mongo's `CursorNotFound`: the server reaped an idle cursor (10 minutes by
+/// default), typically because the client consuming an export is slow.
+const CURSOR_NOT_FOUND: i32 = 43;
+const MAX_RESUMES: u32 = 100;
+pub const EXPORT_BATCH_SIZE: u32 = 500;
+
+/// Iterates `filter` in `_id` order and transparently re-opens the query with
+/// `_id > last emitted id` if the server-side cursor disappears, so every
+/// matching document is yielded exactly once however slow the consumer is.
+pub struct ResumableCursor {
+    collection: Collection<Document>,
+    filter: Document,
+    cursor: Option<Cursor<Document>>,
+    last_id: Option<ObjectId>,
+    resumes: u32,
+}
+
+impl ResumableCursor {
+    pub fn new(collection: Collection<Document>, filter: Document) -> Self {
+        Self {
+            collection,
+            filter,
+            cursor: None,
+            last_id: None,
+            resumes: 0,
+        }
+    }
+
+    pub async fn next(&mut self) -> Option<Result<Document>> {
+        loop {
+            if self.cursor.is_none() {
+                match Self::open(self.collection.clone(), self.resume_filter()).await {
+                    Ok(cursor) => self.cursor = Some(cursor),
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+
+            match self.cursor.as_mut()?.next().await {
+                Some(Ok(doc)) => return Some(self.emitted(doc)),
+                Some(Err(e)) => {
+                    if let Some(e) = self.lose_cursor(e) {
+                        return Some(Err(e));
+                    }
+                }
+                None => return None,
+            }
+        }
+    }
+
+    /// Remembers where `doc` is, to resume after it. A document without an
+    /// `ObjectId` is an error: a resume could not tell where it was.
+    fn emitted(&mut self, doc: Document) -> Result<Document> {
+        self.last_id = Some(doc.get_object_id("_id")?);
+        Ok(doc)
+    }
+
+    /// Drops the cursor the server reaped, so the next call re-opens the
+    /// query after `last_id`. Any other error, or one resume too many, is
+    /// returned instead.
+    fn lose_cursor(&mut self, e: mongodb::error::Error) -> Option<crate::error::Error> {
+        if mongo_error_code(&e) != Some(CURSOR_NOT_FOUND) || self.resumes >= MAX_RESUMES {
+            return Some(MongoQueryError(e));
+        }
+        self.resumes += 1;
+        warn!(
+            target: LOG_TARGET,
+            "Cursor lost after {:?}, resuming (attempt {})",
+            self.last_id, self.resumes
+        );
+        self.cursor = None;
+        None
+    }
+
+    fn resume_filter(&self) -> Document {
+        match self.last_id {
+            Some(last_id) => doc! {"$and": [self.filter.clone(), {"_id": {"$gt": last_id}}]},
+            None => self.filter.clone(),
+        }
+    }
+
+    /// Takes its inputs by value: a future borrowing `self` would not be
+    /// `Send`, as `Cursor` is not `Sync`, and streamed response bodies must
+    /// be.
+    async fn open(collection: Collection<Document>, filter: Document) -> Result<Cursor<Document>> {
+        let find_options = FindOptions::builder()
+            .sort(doc! {"_id": 1})
+            .batch_size(EXPORT_BATCH_SIZE)
+            .build();
+
+        collection
+            .find(filter, find_options)
+            .await
+            .map_err(MongoQueryError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::CreateNoteSchema;
+    use crate::test_support::{config, drop_live_db, live_db, offline_db, write_error};
+
+    fn cursor() -> ResumableCursor {
+        let db = offline_db(&config());
+        ResumableCursor::new(db.collection.clone(), doc! {"deletedAt": null})
+    }
+
+    #[tokio::test]
+    async fn resumes_after_the_last_emitted_id() {
+        let mut cursor = cursor();
+        assert_eq!(cursor.resume_filter(), doc! {"deletedAt": null});
+
+        let id = ObjectId::new();
+        cursor.emitted(doc! {"_id": id, "title": "Plan"}).unwrap();
+        assert_eq!(
+            cursor.resume_filter(),
+            doc! {"$and": [{"deletedAt": null}, {"_id": {"$gt": id}}]}
+        );
+
+        // Tampered with: the position must not fall back to the start.
+        assert!(matches!(
+            cursor.emitted(doc! {"_id": "6630f0c2a1b2c3d4e5f60718"}),
+            Err(MongoDataError(_))
+        ));
+        assert_eq!(cursor.last_id, Some(id));
+    }
+
+    #[tokio::test]
+    async fn only_a_reaped_cursor_is_resumed_and_only_so_often() {
+        let mut cursor = cursor();
+        assert!(matches!(
+            cursor.lose_cursor(write_error(11000, "duplicate key")),
+            Some(MongoQueryError(_))
+        ));
+        for _ in 0..MAX_RESUMES {
+            assert!(cursor
+                .lose_cursor(write_error(CURSOR_NOT_FOUND, "cursor id not found"))
+                .is_none());
+        }
+        assert!(matches!(
+            cursor.lose_cursor(write_error(CURSOR_NOT_FOUND, "cursor id not found")),
+            Some(MongoQueryError(_))
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn an_export_survives_losing_its_cursor() {
+        let db = live_db(&config()).await;
+        let mut seeded = Vec::new();
+        for n in 0..20 {
+            let body: CreateNoteSchema = serde_json::from_value(
+                serde_json::json!({"title": format!("Note {}", n), "content": "x"}),
+            )
+            .unwrap();
+            seeded.push(db.create_note(&body).await.unwrap().data.note.id);
+        }
+
+        let mut cursor = ResumableCursor::new(db.collection.clone(), doc! {"deletedAt": null});
+        let mut exported = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            exported.push(doc.unwrap().get_object_id("_id").unwrap().to_hex());
+            if exported.len() % 7 == 0 {
+                // As if the server had reaped the cursor mid-export.
+                let lost = write_error(CURSOR_NOT_FOUND, "cursor id not found");
+                assert!(cursor.lose_cursor(lost).is_none());
+            }
+        }
+
+        assert_eq!(exported, seeded);
+        drop_live_db(&db).await;
+    }
+}
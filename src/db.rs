@@ -1,22 +1,236 @@
-use crate::response::{NoteData, NoteListResponse, NoteResponse, SingleNoteResponse};
+use crate::attachments::ATTACHMENT_BUCKET;
+use crate::checklist;
+use crate::collation::CollationSpec;
+use crate::color;
+use crate::config::Config;
+use crate::counters::counted_filter;
+use crate::cursor::ResumableCursor;
+use crate::events::{EventBus, NoteEvent};
+use crate::monitoring::MongoMonitor;
+use crate::normalize::{tidy_title, TitleNormalizer};
+use crate::positions::POSITION_GAP;
+use crate::preview;
+use crate::query_parser::{QueryField, QueryTerm};
+use crate::reading_time;
+use crate::response::{
+    CategoryFacet, DraftData, DraftResponse, FieldError, ListedNote, MergeNoteData,
+    MergeNoteResponse, MergeSourceResponse, NoteData, NoteFacets, NoteListResponse, NoteResponse,
+    PageTotal, Paginated, PartialNoteResponse, PublishedFacet, SingleDraftResponse,
+    SingleNoteResponse, TotalSource,
+};
+use crate::slug::{self, MAX_SLUG_ATTEMPTS};
+use crate::tags;
 use crate::{
-    error::Error::*, model::NoteModel, schema::CreateNoteSchema, schema::UpdateNoteSchema, Result,
+    error::{mongo_error_code, title_write_error, Error, Error::*, DUPLICATE_KEY, WRITE_CONFLICT},
+    model::NoteDraftModel,
+    model::NoteModel,
+    model::ReminderModel,
+    model::{CommentModel, NoteRevisionModel},
+    schema::CreateNoteSchema,
+    schema::UpdateNoteSchema,
+    schema::{ArchivedFilter, DeletedFilter, NoteListFilter},
+    schema::{ListExtras, ListOrder, NoteProjection, NotesQuery, Page},
+    schema::{MergeSourceFate, MergeStrategy},
+    Result,
 };
 use chrono::prelude::*;
-use futures::StreamExt;
-use mongodb::bson::{doc, oid::ObjectId, Document};
-use mongodb::options::{FindOneAndUpdateOptions, FindOptions, IndexOptions, ReturnDocument};
-use mongodb::{bson, options::ClientOptions, Client, Collection, IndexModel};
+use futures::{Stream, StreamExt};
+use log::{error, warn};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::options::{
+    AggregateOptions, DeleteOptions, FindOneAndDeleteOptions, FindOneAndUpdateOptions, FindOptions,
+    GridFsBucketOptions, ReturnDocument, UpdateOptions,
+};
+use mongodb::{bson, gridfs::GridFsBucket, options::ClientOptions, Client, Collection};
 use std::str::FromStr;
+use std::sync::Arc;
+
+const LOG_TARGET: &str = "api::db";
+
+const DRAFT_MIN_INTERVAL_MS: i64 = 1000;
+/// `_id` of the `meta` document `bootstrap_notes` leaves behind.
+const BOOTSTRAP_SENTINEL: &str = "bootstrap";
+
+/// Matches the note with this id unless it is in the trash.
+pub fn live_note(oid: ObjectId) -> Document {
+    doc! {"_id": oid, "deletedAt": null}
+}
+
+/// Mongo filter for a list request. `skip` names a dimension to leave out,
+/// which is how each facet ignores its own filter.
+pub fn list_filter(filter: &NoteListFilter, skip: Option<&str>) -> Document {
+    let mut query = Document::new();
+    if let Some(title) = &filter.title {
+        query.insert("title_normalized", title);
+    }
+    if !filter.ids.is_empty() {
+        query.insert("_id", doc! {"$in": &filter.ids});
+    }
+    if skip != Some("category") {
+        match filter.categories.as_slice() {
+            [] => {}
+            [category] => {
+                query.insert("category", category);
+            }
+            categories => {
+                query.insert("category", doc! {"$in": categories});
+            }
+        }
+    }
+    if let Some(published) = filter.published {
+        if skip != Some("published") {
+            query.insert("published", published);
+        }
+    }
+    if let Some(search) = &filter.search {
+        let pattern = escape_regex(search);
+        query.insert(
+            "$or",
+            vec![
+                doc! {"title": {"$regex": &pattern, "$options": "i"}},
+                doc! {"content": {"$regex": &pattern, "$options": "i"}},
+            ],
+        );
+    }
+    if filter.created_from.is_some() || filter.created_to.is_some() {
+        let mut range = Document::new();
+        if let Some(from) = filter.created_from {
+            range.insert("$gte", from);
+        }
+        if let Some(to) = filter.created_to {
+            range.insert("$lte", to);
+        }
+        query.insert("createdAt", range);
+    }
+    if let Some(since) = filter.updated_since {
+        query.insert("updatedAt", doc! {"$gte": since});
+    }
+    // Live notes have no `deletedAt` at all.
+    match filter.deleted {
+        DeletedFilter::Exclude => {
+            query.insert("deletedAt", Bson::Null);
+        }
+        DeletedFilter::Only => {
+            query.insert("deletedAt", doc! {"$ne": null});
+        }
+        DeletedFilter::Include => {}
+    }
+    // Notes from before archiving existed have no `archived` field.
+    match filter.archived {
+        ArchivedFilter::Exclude => {
+            query.insert("archived", doc! {"$ne": true});
+        }
+        ArchivedFilter::Only => {
+            query.insert("archived", true);
+        }
+        ArchivedFilter::All => {}
+    }
+    if !filter.tags.is_empty() || !filter.tags_all.is_empty() {
+        let mut tags = Document::new();
+        if !filter.tags.is_empty() {
+            tags.insert("$in", &filter.tags);
+        }
+        if !filter.tags_all.is_empty() {
+            tags.insert("$all", &filter.tags_all);
+        }
+        query.insert("tags", tags);
+    }
+    if let Some(color) = &filter.color {
+        query.insert("color", color);
+    }
+    // Notes from before pinning existed were backfilled with `false`.
+    match filter.pinned {
+        Some(true) => {
+            query.insert("pinned", true);
+        }
+        Some(false) => {
+            query.insert("pinned", doc! {"$ne": true});
+        }
+        None => {}
+    }
+    if !filter.query.is_empty() {
+        let terms: Vec<Document> = filter.query.iter().map(query_condition).collect();
+        query.insert("$and", terms);
+    }
+    if let Some(text) = &filter.text {
+        query.insert("$text", doc! {"$search": text});
+    }
+    query
+}
+
+/// Mongo condition for one `?q=` term. Negated terms use `$nor`, which also
+/// keeps notes that lack the field.
+fn query_condition(term: &QueryTerm) -> Document {
+    let substring = |value: &str| doc! {"$regex": escape_regex(value), "$options": "i"};
+    let condition = match &term.field {
+        QueryField::Title => doc! {"title": substring(&term.value)},
+        QueryField::Content => doc! {"content": substring(&term.value)},
+        QueryField::Category => doc! {"category": &term.value},
+        QueryField::Published(published) => doc! {"published": published},
+    };
+    if term.negated {
+        doc! {"$nor": [condition]}
+    } else {
+        condition
+    }
+}
+
+/// A failed restore: a unique index refusing the note back is a conflict.
+fn restore_write_error(e: mongodb::error::Error) -> Error {
+    match mongo_error_code(&e) {
+        Some(DUPLICATE_KEY) => ConflictError(
+            "Another note has this note's title now; rename that note first".to_string(),
+        ),
+        _ => MongoQueryError(e),
+    }
+}
+
+/// Escapes `literal` for use inside a `$regex`.
+pub fn escape_regex(literal: &str) -> String {
+    let mut escaped = String::with_capacity(literal.len());
+    for c in literal.chars() {
+        if "\\.^$|?*+()[]{}".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// What the UI shows for a category; notes without one are grouped as
+/// "Uncategorized", as in the exported site.
+fn category_label(category: &str) -> &str {
+    match category {
+        "" => "Uncategorized",
+        category => category,
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct DB {
     pub note_collection: Collection<NoteModel>,
     pub collection: Collection<Document>,
+    pub lock_collection: Collection<Document>,
+    pub meta_collection: Collection<Document>,
+    pub counter_collection: Collection<Document>,
+    pub reminder_collection: Collection<ReminderModel>,
+    pub revision_collection: Collection<NoteRevisionModel>,
+    pub comment_collection: Collection<CommentModel>,
+    pub attachments: GridFsBucket,
+    pub events: EventBus,
+    pub client: Client,
+    pub supports_transactions: bool,
+    pub default_collation: CollationSpec,
+    pub title_normalizer: TitleNormalizer,
+    pub max_note_revisions: u32,
+    pub max_attachment_bytes: u64,
+    pub skip_malformed_notes: bool,
+    pub max_content_length: usize,
+    pub monitor: Arc<MongoMonitor>,
 }
 
 impl DB {
-    pub async fn init() -> Result<Self> {
+    pub async fn init(config: &Config) -> Result<Self> {
         let mongodb_uri: String = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set.");
         let database_name: String =
             std::env::var("MONGO_INITDB_DATABASE").expect("MONGO_INITDB_DATABASE must be set.");
@@ -25,194 +239,2068 @@ impl DB {
 
         let mut client_options = ClientOptions::parse(mongodb_uri).await?;
         client_options.app_name = Some(database_name.to_string());
+        if let Some(min_pool_size) = config.min_pool_size {
+            client_options.min_pool_size = Some(min_pool_size);
+        }
+        let monitor = Arc::new(MongoMonitor::new());
+        client_options.sdam_event_handler = Some(monitor.clone());
+        client_options.cmap_event_handler = Some(monitor.clone());
 
         let client = Client::with_options(client_options)?;
-        let database = client.database(database_name.as_str());
 
-        let note_collection = database.collection(mongodb_note_collection.as_str());
-        let collection = database.collection::<Document>(mongodb_note_collection.as_str());
+        // Transactions need a replica set or mongos; a standalone server
+        // reports neither `setName` nor the mongos marker.
+        let supports_transactions = match client
+            .database("admin")
+            .run_command(doc! {"hello": 1}, None)
+            .await
+        {
+            Ok(hello) => hello.contains_key("setName") || hello.get_str("msg") == Ok("isdbgrid"),
+            Err(_) => false,
+        };
 
         println!("✅ Database connected successfully");
 
-        Ok(Self {
+        let db = Self::with_client(
+            client,
+            &database_name,
+            &mongodb_note_collection,
+            config,
+            supports_transactions,
+            monitor,
+        );
+        db.run_setup().await?;
+        db.log_indexes().await?;
+
+        Ok(db)
+    }
+
+    /// The handles for `database_name`, without talking to the server.
+    pub(crate) fn with_client(
+        client: Client,
+        database_name: &str,
+        note_collection_name: &str,
+        config: &Config,
+        supports_transactions: bool,
+        monitor: Arc<MongoMonitor>,
+    ) -> Self {
+        let database = client.database(database_name);
+
+        let note_collection = database.collection(note_collection_name);
+        let collection = database.collection::<Document>(note_collection_name);
+        let lock_collection = database.collection::<Document>("locks");
+        let meta_collection = database.collection::<Document>("meta");
+        let counter_collection = database.collection::<Document>("counters");
+        let reminder_collection = database.collection::<ReminderModel>("reminders");
+        let revision_collection = database.collection::<NoteRevisionModel>("note_revisions");
+        let comment_collection = database.collection::<CommentModel>("comments");
+        let attachments = database.gridfs_bucket(
+            GridFsBucketOptions::builder()
+                .bucket_name(ATTACHMENT_BUCKET.to_string())
+                .build(),
+        );
+
+        Self {
             note_collection,
             collection,
-        })
+            lock_collection,
+            meta_collection,
+            counter_collection,
+            reminder_collection,
+            revision_collection,
+            comment_collection,
+            attachments,
+            events: EventBus::new(),
+            client,
+            supports_transactions,
+            default_collation: config.default_collation.clone(),
+            title_normalizer: config.title_normalizer,
+            max_note_revisions: config.max_note_revisions,
+            max_attachment_bytes: config.max_attachment_bytes,
+            skip_malformed_notes: config.skip_malformed_notes,
+            max_content_length: config.max_content_length,
+            monitor,
+        }
     }
 
-    pub async fn fetch_notes(&self, limit: i64, page: i64) -> Result<NoteListResponse> {
+    pub async fn fetch_notes(&self, query: &NotesQuery) -> Result<NoteListResponse> {
+        let NotesQuery {
+            filter,
+            order,
+            page,
+            extras,
+            ..
+        } = query;
+        // Text indexes only support simple binary comparison.
+        let collation = match filter.text {
+            Some(_) => None,
+            None => Some(
+                query
+                    .collation
+                    .clone()
+                    .unwrap_or_else(|| self.default_collation.clone()),
+            ),
+        };
+        // Pinned notes lead every order; `_id` last keeps pages stable when
+        // sort values tie.
+        let mut find_filter = list_filter(filter, None);
+        let sort = match order {
+            ListOrder::Default => doc! {"pinned": -1, "createdAt": -1, "_id": -1},
+            ListOrder::Sorted(keys) => {
+                let mut sort_doc = doc! {"pinned": -1};
+                for key in keys {
+                    sort_doc.insert(key.field.key(), if key.descending { -1 } else { 1 });
+                }
+                sort_doc.insert("_id", 1);
+                sort_doc
+            }
+            // Only the page moves; totals and facets still cover the whole
+            // filtered list.
+            // The page after a pinned note still holds the older pinned
+            // notes and then every unpinned one.
+            ListOrder::After(after) => {
+                let after_cursor = if self.is_pinned(*after).await? {
+                    doc! {"$or": [
+                        {"pinned": true, "_id": {"$lt": after}},
+                        {"pinned": {"$ne": true}},
+                    ]}
+                } else {
+                    doc! {"pinned": {"$ne": true}, "_id": {"$lt": after}}
+                };
+                find_filter = doc! {"$and": [find_filter, after_cursor]};
+                doc! {"pinned": -1, "_id": -1}
+            }
+            ListOrder::Relevance => {
+                doc! {"pinned": -1, "score": {"$meta": "textScore"}, "_id": 1}
+            }
+        };
+        let partial = query.projection.is_some();
+        let projection = query
+            .projection
+            .as_ref()
+            .map(|projection| match projection {
+                NoteProjection::Only(fields) => {
+                    let mut projection = doc! {"_id": 1};
+                    for field in fields {
+                        projection.insert(field.key(), 1);
+                    }
+                    projection
+                }
+                NoteProjection::Except(fields) => {
+                    let mut projection = Document::new();
+                    for field in fields {
+                        projection.insert(field.key(), 0);
+                    }
+                    projection
+                }
+            });
+        // One note more than the page holds tells whether another page
+        // follows, even when nothing is counted.
         let find_options = FindOptions::builder()
-            .limit(limit)
-            .skip(u64::try_from((page - 1) * limit).unwrap())
+            .limit(page.limit as i64 + 1)
+            .skip(page.skip())
+            .sort(sort)
+            .projection(projection)
+            .collation(collation.as_ref().map(CollationSpec::to_mongo))
+            .build();
+        let facets = async {
+            // `$text` has to open a pipeline, so it cannot run inside `$facet`.
+            if extras.facets && filter.text.is_none() {
+                let collation = collation.as_ref().unwrap_or(&self.default_collation);
+                self.note_facets(filter, collation).await.map(Some)
+            } else {
+                Ok(None)
+            }
+        };
+
+        // The count and facets see the same filter as the page; running them
+        // side by side keeps the list from paying for each in turn.
+        let total = async {
+            if extras.total {
+                self.note_total(filter, collation.as_ref()).await
+            } else {
+                Ok(PageTotal {
+                    value: None,
+                    source: TotalSource::Skipped,
+                })
+            }
+        };
+        let (mut json_result, total, facets) = tokio::try_join!(
+            self.find_page(find_filter, find_options, partial),
+            total,
+            facets,
+        )?;
+
+        let has_next = json_result.len() as u64 > page.limit;
+        json_result.truncate(page.limit as usize);
+        let next_cursor = match (order, json_result.last()) {
+            (ListOrder::Sorted(_) | ListOrder::Relevance, _) => None,
+            (_, Some(last)) if has_next => Some(last.id().to_owned()),
+            _ => None,
+        };
+        let mut json_note_list = NoteListResponse::from(Paginated::new(json_result, page, total));
+        if let Some(page_info) = &mut json_note_list.page_info {
+            page_info.has_more = has_next;
+        }
+        json_note_list.collation = collation;
+        json_note_list.facets = facets;
+        json_note_list.next_cursor = next_cursor;
+        json_note_list.has_next = Some(has_next);
+        json_note_list.has_prev = Some(match order {
+            ListOrder::After(_) => true,
+            _ => page.page > 1,
+        });
+
+        Ok(json_note_list)
+    }
+
+    async fn find_page(
+        &self,
+        filter: Document,
+        find_options: FindOptions,
+        partial: bool,
+    ) -> Result<Vec<ListedNote>> {
+        let mut json_result: Vec<ListedNote> = Vec::new();
+        if partial {
+            let mut cursor = self
+                .collection
+                .find(filter, find_options)
+                .await
+                .map_err(MongoQueryError)?;
+            while let Some(doc) = cursor.next().await {
+                json_result.push(Self::doc_to_partial_note(&doc.map_err(MongoQueryError)?).into());
+            }
+        } else {
+            // Read raw so that a note which does not fit `NoteModel` can be
+            // named, and skipped if so configured.
+            let mut cursor = self
+                .collection
+                .find(filter, find_options)
+                .await
+                .map_err(MongoQueryError)?;
+            while let Some(doc) = cursor.next().await {
+                let doc = doc.map_err(MongoQueryError)?;
+                let note = match bson::from_document::<NoteModel>(doc.clone()) {
+                    Ok(note) => note,
+                    Err(e) if self.skip_malformed_notes => {
+                        warn!(
+                            target: LOG_TARGET,
+                            "Skipping note {} that does not deserialize: {}",
+                            doc.get("_id").map(Bson::to_string).unwrap_or_default(),
+                            e
+                        );
+                        continue;
+                    }
+                    Err(e) => return Err(MongoDeserializeBsonError(e)),
+                };
+                json_result.push(self.doc_to_note(&note)?.into());
+            }
+        }
+        Ok(json_result)
+    }
+
+    /// Every category with its note count, largest first. Notes with an
+    /// empty or missing category are counted together under `""`.
+    pub async fn list_categories(&self, published: Option<bool>) -> Result<Vec<CategoryFacet>> {
+        let filter = NoteListFilter {
+            published,
+            ..NoteListFilter::default()
+        };
+        let pipeline = vec![
+            doc! {"$match": list_filter(&filter, None)},
+            doc! {"$group": {
+                "_id": {"$ifNull": ["$category", ""]},
+                "count": {"$sum": 1},
+            }},
+            doc! {"$sort": {"count": -1, "_id": 1}},
+        ];
+        let options = AggregateOptions::builder()
+            .collation(self.default_collation.to_mongo())
             .build();
 
         let mut cursor = self
-            .note_collection
-            .find(None, find_options)
+            .collection
+            .aggregate(pipeline, options)
             .await
             .map_err(MongoQueryError)?;
+        let mut categories = Vec::new();
+        while let Some(bucket) = cursor.next().await {
+            let bucket = bucket.map_err(MongoQueryError)?;
+            let value = bucket.get_str("_id").unwrap_or("").to_string();
+            let count = match bucket.get("count") {
+                Some(Bson::Int32(n)) => *n as u64,
+                Some(Bson::Int64(n)) => *n as u64,
+                _ => 0,
+            };
+            categories.push(CategoryFacet {
+                label: category_label(&value).to_string(),
+                value,
+                count,
+            });
+        }
+        Ok(categories)
+    }
+
+    /// Counts per category and per published state in one `$facet` pass.
+    /// Each facet applies every active filter except its own dimension, so
+    /// picking a category still shows how many notes the other categories
+    /// hold.
+    async fn note_facets(
+        &self,
+        filter: &NoteListFilter,
+        collation: &CollationSpec,
+    ) -> Result<NoteFacets> {
+        let group_by = |field: &str| {
+            vec![
+                doc! {"$group": {"_id": format!("${}", field), "count": {"$sum": 1}}},
+                doc! {"$sort": {"count": -1, "_id": 1}},
+            ]
+        };
+        let mut category_pipeline = vec![doc! {"$match": list_filter(filter, Some("category"))}];
+        category_pipeline.extend(group_by("category"));
+        let mut published_pipeline = vec![doc! {"$match": list_filter(filter, Some("published"))}];
+        published_pipeline.extend(group_by("published"));
+
+        let pipeline = vec![doc! {"$facet": {
+            "category": category_pipeline,
+            "published": published_pipeline,
+        }}];
+        let options = AggregateOptions::builder()
+            .collation(collation.to_mongo())
+            .build();
+
+        let mut cursor = self
+            .collection
+            .aggregate(pipeline, options)
+            .await
+            .map_err(MongoQueryError)?;
+        let result = match cursor.next().await {
+            Some(doc) => doc.map_err(MongoQueryError)?,
+            None => Document::new(),
+        };
 
-        let mut json_result: Vec<NoteResponse> = Vec::new();
-        while let Some(doc) = cursor.next().await {
-            json_result.push(self.doc_to_note(&doc.unwrap())?);
+        let buckets = |name: &str| -> Vec<Document> {
+            result
+                .get_array(name)
+                .map(|values| {
+                    values
+                        .iter()
+                        .filter_map(|value| value.as_document().cloned())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let count = |bucket: &Document| match bucket.get("count") {
+            Some(Bson::Int32(n)) => *n as u64,
+            Some(Bson::Int64(n)) => *n as u64,
+            _ => 0,
+        };
+
+        let category = buckets("category")
+            .iter()
+            .map(|bucket| {
+                let value = bucket.get_str("_id").unwrap_or("").to_string();
+                CategoryFacet {
+                    label: category_label(&value).to_string(),
+                    value,
+                    count: count(bucket),
+                }
+            })
+            .collect();
+        let published = buckets("published")
+            .iter()
+            .map(|bucket| PublishedFacet {
+                value: bucket.get_bool("_id").unwrap_or(false),
+                count: count(bucket),
+            })
+            .collect();
+
+        Ok(NoteFacets {
+            category,
+            published,
+        })
+    }
+
+    /// Every note outside the trash in `_id` order, one at a time, for
+    /// exports too large to collect. The first error ends the stream.
+    pub fn stream_notes(&self) -> impl Stream<Item = Result<NoteResponse>> {
+        let cursor = ResumableCursor::new(self.collection.clone(), doc! {"deletedAt": null});
+        futures::stream::unfold(Some((self.clone(), cursor)), |state| async move {
+            let (db, mut cursor) = state?;
+            let note = cursor.next().await?.and_then(|doc| {
+                let id = doc
+                    .get_object_id("_id")
+                    .map(|id| id.to_hex())
+                    .unwrap_or_default();
+                bson::from_document::<NoteModel>(doc)
+                    .map_err(|e| ExportError(format!("could not read note {}: {}", id, e)))
+                    .and_then(|note| db.doc_to_note(&note))
+            });
+            let next = note.is_ok().then_some((db, cursor));
+            Some((note, next))
+        })
+    }
+
+    pub fn published_notes_cursor(&self) -> ResumableCursor {
+        ResumableCursor::new(
+            self.collection.clone(),
+            doc! {"published": true, "deletedAt": null},
+        )
+    }
+
+    pub fn notes_by_ids_cursor(&self, ids: &[ObjectId]) -> ResumableCursor {
+        ResumableCursor::new(
+            self.collection.clone(),
+            doc! {"_id": {"$in": ids}, "deletedAt": null},
+        )
+    }
+
+    /// Inserts the note and answers with what was inserted, so there is no
+    /// second read that could miss it.
+    pub async fn create_note(&self, body: &CreateNoteSchema) -> Result<SingleNoteResponse> {
+        let mut doc_with_dates = self.new_note_document(body)?;
+        doc_with_dates.insert("_id", ObjectId::new());
+        doc_with_dates.insert("position", self.next_position().await?);
+        let mut attempt = 1;
+        loop {
+            let slug = self.free_slug(&body.title, None, &[]).await?;
+            doc_with_dates.insert("slug", &slug);
+            match self.collection.insert_one(&doc_with_dates, None).await {
+                Ok(_) => break,
+                // Another note took the slug since it was picked.
+                Err(e)
+                    if attempt < MAX_SLUG_ATTEMPTS
+                        && mongo_error_code(&e) == Some(DUPLICATE_KEY)
+                        && self.slug_taken(&slug).await? =>
+                {
+                    attempt += 1;
+                }
+                Err(e) => return Err(title_write_error(e, &tidy_title(&body.title))),
+            }
         }
 
-        let json_note_list = NoteListResponse {
+        let note_doc: NoteModel =
+            bson::from_document(doc_with_dates).map_err(MongoDeserializeBsonError)?;
+        let note_response = SingleNoteResponse {
             status: "success".to_string(),
-            results: json_result.len(),
-            notes: json_result,
+            data: NoteData {
+                note: self.doc_to_note(&note_doc)?,
+                draft: None,
+            },
         };
 
-        Ok(json_note_list)
+        self.adjust_note_count(1).await;
+        self.events.publish(NoteEvent::created(&note_response));
+
+        Ok(note_response)
     }
 
-    pub async fn create_note(&self, body: &CreateNoteSchema) -> Result<Option<SingleNoteResponse>> {
+    /// The document `create_note` and `create_notes` insert: the body plus
+    /// timestamps, defaults and the derived fields.
+    pub(crate) fn new_note_document(&self, body: &CreateNoteSchema) -> Result<Document> {
+        body.validate(self.max_content_length)?;
         let published = body.published.to_owned().unwrap_or(false);
         let category = body.category.to_owned().unwrap_or("".to_string());
-        let serialized_data = bson::to_bson(&body).map_err(MongoSerializeBsonError)?;
-        let document = serialized_data.as_document().unwrap();
-        let options = IndexOptions::builder().unique(true).build();
-        let index = IndexModel::builder()
-            .keys(doc! {"title": 1})
-            .options(options)
-            .build();
-
-        self.note_collection
-            .create_index(index, None)
-            .await
-            .expect("error creating index!");
+        let document = bson::to_document(&body).map_err(MongoSerializeBsonError)?;
 
         let datetime = Utc::now();
 
-        let mut doc_with_dates = doc! {"createdAt": datetime, "updatedAt": datetime, "published": published, "category": category};
-        doc_with_dates.extend(document.clone());
+        let title_normalized = self.title_normalizer.normalize(&body.title);
 
-        let insert_result = self
+        let mut doc_with_dates = doc! {"createdAt": datetime, "updatedAt": datetime, "published": published, "category": category, "title_normalized": title_normalized, "pinned": false};
+        if published {
+            doc_with_dates.insert("publishedAt", datetime);
+        }
+        doc_with_dates.extend(document);
+        doc_with_dates.insert("title", tidy_title(&body.title));
+        doc_with_dates.insert("tags", tags::normalize_tags(&body.tags)?);
+        if let Some(color) = &body.color {
+            doc_with_dates.insert("color", color::normalize_color("color", color)?);
+        }
+        if !body.items.is_empty() {
+            doc_with_dates.insert("items", checklist::checklist_items(&body.items)?);
+        }
+        if let Some(preview) = preview::extract(&body.content) {
+            doc_with_dates.insert(
+                "preview",
+                bson::to_bson(&preview).map_err(MongoSerializeBsonError)?,
+            );
+        }
+        Ok(doc_with_dates)
+    }
+
+    /// Creates the starter notes exactly once per database. A sentinel with a
+    /// fixed `_id` is inserted first, so when several replicas race only the
+    /// one whose insert succeeds goes on; the rest get `None`. The sentinel
+    /// and the notes are written in one transaction where the deployment
+    /// supports it; elsewhere a failed insert takes the sentinel back out so
+    /// a retry can bootstrap.
+    pub async fn bootstrap_notes(
+        &self,
+        starters: &[CreateNoteSchema],
+    ) -> Result<Option<NoteListResponse>> {
+        let mut ids = Vec::with_capacity(starters.len());
+        let mut slugs = Vec::with_capacity(starters.len());
+        let mut documents = Vec::with_capacity(starters.len());
+        let mut position = self.next_position().await?;
+        for starter in starters {
+            let id = ObjectId::new();
+            let slug = self.free_slug(&starter.title, None, &slugs).await?;
+            let mut document = self.new_note_document(starter)?;
+            document.insert("_id", id);
+            document.insert("slug", &slug);
+            document.insert("position", position);
+            position += POSITION_GAP;
+            ids.push(id);
+            slugs.push(slug);
+            documents.push(document);
+        }
+
+        let mut session = self.client.start_session(None).await?;
+        if self.supports_transactions {
+            session.start_transaction(None).await?;
+        }
+
+        let sentinel = doc! {"_id": BOOTSTRAP_SENTINEL, "createdAt": Utc::now()};
+        match self
+            .meta_collection
+            .insert_one_with_session(sentinel, None, &mut session)
+            .await
+        {
+            Ok(_) => {}
+            // In a transaction, a sentinel another replica has not committed
+            // yet conflicts instead of being a duplicate.
+            Err(e) if matches!(mongo_error_code(&e), Some(DUPLICATE_KEY | WRITE_CONFLICT)) => {
+                return Ok(None)
+            }
+            Err(e) => return Err(MongoQueryError(e)),
+        }
+
+        // A database that already has notes counts as bootstrapped; the
+        // sentinel stays so later calls stop at the insert above.
+        let existing = self
             .collection
-            .insert_one(&doc_with_dates, None)
+            .count_documents_with_session(doc! {}, None, &mut session)
             .await
-            .map_err(|e| {
-                if e.to_string()
-                    .contains("E11000 duplicate key error collection")
-                {
-                    return MongoDuplicateError(e);
+            .map_err(MongoQueryError)?;
+        if existing > 0 {
+            if self.supports_transactions {
+                session.commit_transaction().await?;
+            }
+            return Ok(None);
+        }
+
+        if !documents.is_empty() {
+            if let Err(e) = self
+                .collection
+                .insert_many_with_session(&documents, None, &mut session)
+                .await
+            {
+                // An open transaction is aborted when the session drops.
+                if !self.supports_transactions {
+                    self.undo_bootstrap(&ids).await;
                 }
-                return MongoQueryError(e);
-            })?;
+                return Err(MongoQueryError(e));
+            }
+        }
 
-        let new_id = insert_result
-            .inserted_id
-            .as_object_id()
-            .expect("issue with new _id");
+        if self.supports_transactions {
+            session.commit_transaction().await?;
+        }
+        self.adjust_note_count(documents.len() as i64).await;
 
-        let note_doc = self
-            .note_collection
-            .find_one(doc! {"_id":new_id }, None)
+        let mut notes: Vec<ListedNote> = Vec::with_capacity(documents.len());
+        for document in documents {
+            let note_doc: NoteModel =
+                bson::from_document(document).map_err(MongoDeserializeBsonError)?;
+            let note_response = SingleNoteResponse {
+                status: "success".to_string(),
+                data: NoteData {
+                    note: self.doc_to_note(&note_doc)?,
+                    draft: None,
+                },
+            };
+            self.events.publish(NoteEvent::created(&note_response));
+            notes.push(note_response.data.note.into());
+        }
+
+        Ok(Some(NoteListResponse {
+            status: "success".to_string(),
+            results: notes.len(),
+            page_info: None,
+            collation: None,
+            facets: None,
+            server_time: None,
+            next_cursor: None,
+            has_next: None,
+            has_prev: None,
+            notes,
+        }))
+    }
+
+    /// Without a transaction: removes what a failed bootstrap left behind,
+    /// the sentinel last so no other replica starts while notes remain.
+    async fn undo_bootstrap(&self, ids: &[ObjectId]) {
+        if let Err(e) = self
+            .collection
+            .delete_many(doc! {"_id": {"$in": ids}}, None)
             .await
-            .map_err(MongoQueryError)?;
+        {
+            error!(target: LOG_TARGET, "Could not remove partial bootstrap notes: {:?}", e);
+            return;
+        }
+        if let Err(e) = self
+            .meta_collection
+            .delete_one(doc! {"_id": BOOTSTRAP_SENTINEL}, None)
+            .await
+        {
+            error!(target: LOG_TARGET, "Could not remove the bootstrap sentinel: {:?}", e);
+        }
+    }
 
-        if note_doc.is_none() {
+    pub async fn get_note(
+        &self,
+        id: &str,
+        include_draft: bool,
+    ) -> Result<Option<SingleNoteResponse>> {
+        let oid = match ObjectId::from_str(id) {
+            Ok(oid) => oid,
+            Err(_) if slug::is_slug(id) => match self.note_id_by_slug(id).await? {
+                Some(oid) => oid,
+                None => return Ok(None),
+            },
+            Err(_) => return Err(InvalidIDError(id.to_owned())),
+        };
+
+        let Some(note) = self
+            .note_collection
+            .find_one(live_note(oid), None)
+            .await
+            .map_err(MongoQueryError)?
+        else {
             return Ok(None);
-        }
+        };
 
+        let draft = match (&note.draft, include_draft) {
+            (Some(draft), true) => Some(self.draft_to_response(draft, &note)),
+            _ => None,
+        };
         let note_response = SingleNoteResponse {
             status: "success".to_string(),
             data: NoteData {
-                note: self.doc_to_note(&note_doc.unwrap()).unwrap(),
+                note: self.doc_to_note(&note)?,
+                draft,
             },
         };
 
         Ok(Some(note_response))
     }
 
-    pub async fn get_note(&self, id: &str) -> Result<Option<SingleNoteResponse>> {
+    pub async fn edit_note(
+        &self,
+        id: &str,
+        body: &UpdateNoteSchema,
+        not_modified_since: Option<DateTime<Utc>>,
+    ) -> Result<Option<SingleNoteResponse>> {
         let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+        let mut query = live_note(oid);
+        if let Some(since) = not_modified_since {
+            query.extend(Self::unmodified_since_filter(since));
+        }
 
-        let note_doc = self
+        // The note as it was goes into the revision history, so the update
+        // returns it and the edited note is read afterwards.
+        let find_one_and_update_options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::Before)
+            .build();
+
+        let mut update = self.note_update(body)?;
+        Self::set_updated_at(&mut update, Utc::now());
+        update.insert("$inc", doc! {"revision": 1});
+
+        let previous = self
             .note_collection
-            .find_one(doc! {"_id":oid }, None)
+            .find_one_and_update(query, update, find_one_and_update_options)
             .await
-            .map_err(MongoQueryError)?;
+            .map_err(|e| match &body.title {
+                Some(Some(title)) => title_write_error(e, &tidy_title(title)),
+                _ => MongoQueryError(e),
+            })?;
 
-        if note_doc.is_none() {
+        let Some(previous) = previous else {
+            if not_modified_since.is_some() {
+                self.check_precondition(oid).await?;
+            }
             return Ok(None);
+        };
+        self.record_revision(&previous).await;
+        if body.published == Some(Some(true)) && previous.published != Some(true) {
+            self.stamp_published(&[oid]).await?;
         }
 
+        let Some(note_doc) = self
+            .note_collection
+            .find_one(live_note(oid), None)
+            .await
+            .map_err(MongoQueryError)?
+        else {
+            return Ok(None);
+        };
+
         let note_response = SingleNoteResponse {
             status: "success".to_string(),
             data: NoteData {
-                note: self.doc_to_note(&note_doc.unwrap()).unwrap(),
+                note: self.doc_to_note(&note_doc)?,
+                draft: None,
             },
         };
 
+        self.events
+            .publish(NoteEvent::updated(&note_response, body));
+        // A removed flag reads as unpublished.
+        let published = note_doc.published.unwrap_or(false);
+        if body.published.is_some() && previous.published.unwrap_or(false) != published {
+            self.events.publish(NoteEvent::published(id, published));
+        }
+
         Ok(Some(note_response))
     }
 
-    pub async fn edit_note(
+    /// The `$set`/`$unset` update for a PATCH body, including the fields
+    /// derived from title and content.
+    pub(crate) fn note_update(&self, body: &UpdateNoteSchema) -> Result<Document> {
+        body.validate(self.max_content_length)?;
+        let mut document = doc! {};
+        let mut unset = doc! {};
+        if let Some(Some(title)) = &body.title {
+            document.insert("title", tidy_title(title));
+            document.insert("title_normalized", self.title_normalizer.normalize(title));
+        }
+        let mut update = doc! {};
+        if let Some(Some(content)) = &body.content {
+            document.insert("content", content);
+            Self::set_preview(content, &mut document, &mut update)?;
+        }
+        match &body.category {
+            Some(Some(category)) => {
+                document.insert("category", category);
+            }
+            Some(None) => {
+                unset.insert("category", "");
+            }
+            None => {}
+        }
+        match body.published {
+            Some(Some(published)) => {
+                document.insert("published", published);
+            }
+            Some(None) => {
+                unset.insert("published", "");
+            }
+            None => {}
+        }
+        match &body.color {
+            Some(Some(color)) => {
+                document.insert("color", color::normalize_color("color", color)?);
+            }
+            Some(None) => {
+                unset.insert("color", "");
+            }
+            None => {}
+        }
+        match &body.items {
+            Some(Some(items)) => {
+                document.insert("items", checklist::checklist_items(items)?);
+            }
+            Some(None) => {
+                unset.insert("items", "");
+            }
+            None => {}
+        }
+        if !unset.is_empty() {
+            match update.get_document_mut("$unset") {
+                Ok(existing) => existing.extend(unset),
+                Err(_) => {
+                    update.insert("$unset", unset);
+                }
+            }
+        }
+        if !document.is_empty() || update.is_empty() {
+            update.insert("$set", document);
+        }
+        Ok(update)
+    }
+
+    /// Adds `updatedAt` to an update from `note_update`, which may have
+    /// only `$unset` something.
+    fn set_updated_at(update: &mut Document, at: DateTime<Utc>) {
+        match update.get_document_mut("$set") {
+            Ok(set) => {
+                set.insert("updatedAt", at);
+            }
+            Err(_) => {
+                update.insert("$set", doc! {"updatedAt": at});
+            }
+        }
+    }
+
+    /// Replaces every field a client controls, keeping `_id` and
+    /// `createdAt`; optional fields left out fall back to their defaults and
+    /// any draft is dropped. A missing note is created under the given id,
+    /// reported by the `bool`, unless `not_modified_since` is set. With
+    /// `keep_title` a different title is refused, as titles then only change
+    /// through the rename endpoint.
+    pub async fn replace_note(
         &self,
         id: &str,
-        body: &UpdateNoteSchema,
-    ) -> Result<Option<SingleNoteResponse>> {
+        body: &CreateNoteSchema,
+        not_modified_since: Option<DateTime<Utc>>,
+        keep_title: bool,
+    ) -> Result<Option<(SingleNoteResponse, bool)>> {
         let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
-        let query = doc! {
-            "_id": oid,
+        body.validate(self.max_content_length)?;
+        let existing = self
+            .note_collection
+            .find_one(doc! {"_id": oid}, None)
+            .await
+            .map_err(MongoQueryError)?;
+        // Putting a note that is in the trash brings it back as new.
+        let restoring = existing
+            .as_ref()
+            .is_some_and(|existing| existing.deletedAt.is_some());
+        let existing = existing.filter(|existing| existing.deletedAt.is_none());
+        if let Some(existing) = &existing {
+            if keep_title && existing.title != tidy_title(&body.title) {
+                return Err(BadRequestError(format!(
+                    "Use POST /api/notes/{}/rename to change a title",
+                    id
+                )));
+            }
+        }
+
+        // A precondition names a version of an existing note, so there is
+        // nothing to create when it does not hold.
+        let upsert = not_modified_since.is_none();
+        let mut query = doc! {"_id": oid};
+        if let Some(since) = not_modified_since {
+            query.extend(Self::unmodified_since_filter(since));
+        }
+
+        let now = Utc::now();
+        let published = body.published.unwrap_or(false);
+        let mut set = doc! {
+            "title": tidy_title(&body.title),
+            "title_normalized": self.title_normalizer.normalize(&body.title),
+            "content": &body.content,
+            "category": body.category.clone().unwrap_or_default(),
+            "published": published,
+            "tags": tags::normalize_tags(&body.tags)?,
+            "updatedAt": now,
         };
+        let was_published = existing
+            .as_ref()
+            .is_some_and(|existing| existing.published == Some(true));
+        if published && !was_published {
+            set.insert("publishedAt", now);
+        }
+        let mut unset = doc! {"draft": "", "deletedAt": ""};
+        match &body.color {
+            Some(color) => {
+                set.insert("color", color::normalize_color("color", color)?);
+            }
+            None => {
+                unset.insert("color", "");
+            }
+        }
+        if body.items.is_empty() {
+            unset.insert("items", "");
+        } else {
+            set.insert("items", checklist::checklist_items(&body.items)?);
+        }
+        match preview::extract(&body.content) {
+            Some(preview) => {
+                set.insert(
+                    "preview",
+                    bson::to_bson(&preview).map_err(MongoSerializeBsonError)?,
+                );
+            }
+            None => {
+                unset.insert("preview", "");
+            }
+        }
+        let mut set_on_insert = doc! {"createdAt": now, "pinned": false};
+        if upsert && existing.is_none() && !restoring {
+            set_on_insert.insert("slug", self.free_slug(&body.title, Some(oid), &[]).await?);
+            set_on_insert.insert("position", self.next_position().await?);
+        }
+        let mut update = doc! {
+            "$set": set,
+            "$unset": unset,
+            "$setOnInsert": set_on_insert,
+        };
+        // Like a PATCH, replacing a live note keeps what it replaced.
+        if existing.is_some() {
+            update.insert("$inc", doc! {"revision": 1});
+        }
 
-        let find_one_and_update_options = FindOneAndUpdateOptions::builder()
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(upsert)
             .return_document(ReturnDocument::After)
             .build();
+        let note = self
+            .note_collection
+            .find_one_and_update(query, update, options)
+            .await
+            .map_err(|e| title_write_error(e, &tidy_title(&body.title)))?;
 
-        let serialized_data = bson::to_bson(body).map_err(MongoSerializeBsonError)?;
-        let document = serialized_data.as_document().unwrap();
-        let update = doc! {"$set": document};
+        let Some(note) = note else {
+            if not_modified_since.is_some() {
+                self.check_precondition(oid).await?;
+            }
+            return Ok(None);
+        };
+        let created = existing.is_none() && (restoring || note.createdAt == note.updatedAt);
+        if let Some(existing) = &existing {
+            self.record_revision(existing).await;
+        }
 
-        let note_doc = self
+        let note_response = SingleNoteResponse {
+            status: "success".to_string(),
+            data: NoteData {
+                note: self.doc_to_note(&note)?,
+                draft: None,
+            },
+        };
+
+        if created {
+            if !note.archived {
+                self.adjust_note_count(1).await;
+            }
+            self.events.publish(NoteEvent::created(&note_response));
+        } else {
+            self.events.publish(NoteEvent::replaced(&note_response));
+            if existing
+                .and_then(|existing| existing.published)
+                .unwrap_or_default()
+                != published
+            {
+                self.events.publish(NoteEvent::published(id, published));
+            }
+        }
+
+        Ok(Some((note_response, created)))
+    }
+
+    /// Moves a note to the trash by stamping `deletedAt`, or with
+    /// `permanent` removes it, whether or not it is in the trash already.
+    pub async fn delete_note(
+        &self,
+        id: &str,
+        not_modified_since: Option<DateTime<Utc>>,
+        permanent: bool,
+    ) -> Result<Option<()>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+        let mut query = if permanent {
+            doc! {"_id": oid}
+        } else {
+            live_note(oid)
+        };
+        if let Some(since) = not_modified_since {
+            query.extend(Self::unmodified_since_filter(since));
+        }
+
+        let deleted = if permanent {
+            let options = FindOneAndDeleteOptions::builder()
+                .projection(doc! {"archived": 1, "deletedAt": 1})
+                .build();
+            self.collection
+                .find_one_and_delete(query, options)
+                .await
+                .map_err(MongoQueryError)?
+        } else {
+            // `updatedAt` moves too, so `?updatedSince=` picks up the
+            // tombstone.
+            let now = Utc::now();
+            let options = FindOneAndUpdateOptions::builder()
+                .projection(doc! {"archived": 1})
+                .build();
+            self.collection
+                .find_one_and_update(
+                    query,
+                    doc! {"$set": {"deletedAt": now, "updatedAt": now}},
+                    options,
+                )
+                .await
+                .map_err(MongoQueryError)?
+        };
+
+        let Some(deleted) = deleted else {
+            if not_modified_since.is_some() {
+                self.check_precondition(oid).await?;
+            }
+            return Ok(None);
+        };
+
+        // A note already in the trash was accounted for when it went there.
+        if matches!(deleted.get("deletedAt"), None | Some(Bson::Null)) {
+            if deleted.get_bool("archived") != Ok(true) {
+                self.adjust_note_count(-1).await;
+            }
+            self.cancel_note_reminders(oid).await;
+            self.events.publish(NoteEvent::deleted(id));
+        }
+        // Comments, attachments and revisions stay with a note in the trash,
+        // ready for a restore.
+        if permanent {
+            self.delete_note_data(&[oid]).await;
+        }
+
+        Ok(Some(()))
+    }
+
+    /// Removes what belongs to notes that are gone for good: their
+    /// comments, attachments and revisions. The notes are already deleted,
+    /// so failures are only logged.
+    pub(crate) async fn delete_note_data(&self, note_oids: &[ObjectId]) {
+        self.delete_comments_of(note_oids).await;
+        self.delete_attachments_of(note_oids).await;
+        self.delete_revisions_of(note_oids).await;
+    }
+
+    /// Applies a PATCH body to every note matching `filter` and bumps their
+    /// `updatedAt`. Returns the matched and modified counts.
+    pub async fn update_notes(
+        &self,
+        filter: &NoteListFilter,
+        collation: Option<CollationSpec>,
+        body: &UpdateNoteSchema,
+    ) -> Result<(u64, u64)> {
+        let collation = collation.unwrap_or_else(|| self.default_collation.clone());
+        let query = list_filter(filter, None);
+        let ids = self.matching_ids(query.clone(), &collation).await?;
+        if ids.is_empty() {
+            return Ok((0, 0));
+        }
+
+        // The notes whose flag this flips: only they get a `published`
+        // event, and those it takes live get `publishedAt` stamped.
+        let flipping = match body.published {
+            Some(published) => {
+                let flips = match published {
+                    Some(true) => doc! {"published": {"$ne": true}},
+                    // A removed flag reads as unpublished.
+                    _ => doc! {"published": true},
+                };
+                let flipping = doc! {"$and": [query.clone(), {"_id": {"$in": &ids}}, flips]};
+                self.matching_ids(flipping, &collation).await?
+            }
+            None => Vec::new(),
+        };
+
+        let mut update = self.note_update(body)?;
+        Self::set_updated_at(&mut update, Utc::now());
+        let options = UpdateOptions::builder()
+            .collation(collation.to_mongo())
+            .build();
+        let result = self
+            .collection
+            .update_many(
+                doc! {"$and": [query, {"_id": {"$in": &ids}}]},
+                update,
+                options,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+        if body.published == Some(Some(true)) {
+            self.stamp_published(&flipping).await?;
+        }
+
+        let mut cursor = self
             .note_collection
-            .find_one_and_update(query, update, find_one_and_update_options)
+            .find(doc! {"_id": {"$in": &ids}}, None)
             .await
             .map_err(MongoQueryError)?;
+        while let Some(note) = cursor.next().await {
+            let note = note.map_err(MongoQueryError)?;
+            let note_response = SingleNoteResponse {
+                status: "success".to_string(),
+                data: NoteData {
+                    note: self.doc_to_note(&note)?,
+                    draft: None,
+                },
+            };
+            self.events
+                .publish(NoteEvent::updated(&note_response, body));
+            if flipping.contains(&note.id) {
+                self.events.publish(NoteEvent::published(
+                    &note_response.data.note.id,
+                    note.published.unwrap_or(false),
+                ));
+            }
+        }
 
-        if note_doc.is_none() {
-            return Ok(None);
+        Ok((result.matched_count, result.modified_count))
+    }
+
+    /// Moves every live note, archived or not, from category `from` to
+    /// `to`. Matching uses the default collation like `?category=` does.
+    /// Returns the matched and modified counts.
+    pub async fn rename_category(&self, from: &str, to: &str) -> Result<(u64, u64)> {
+        let to = to.trim();
+        if to.is_empty() {
+            return Err(ValidationError(vec![FieldError {
+                field: "to".to_string(),
+                code: "REQUIRED".to_string(),
+                message: "New category name must not be empty".to_string(),
+            }]));
+        }
+
+        let filter = NoteListFilter {
+            categories: vec![from.to_owned()],
+            archived: ArchivedFilter::All,
+            ..NoteListFilter::default()
+        };
+        let body = UpdateNoteSchema {
+            category: Some(Some(to.to_owned())),
+            ..UpdateNoteSchema::default()
+        };
+        self.update_notes(&filter, None, &body).await
+    }
+
+    /// Ids of the notes matching `query`, so that a bulk write can follow
+    /// up on exactly the notes it touched.
+    pub(crate) async fn matching_ids(
+        &self,
+        query: Document,
+        collation: &CollationSpec,
+    ) -> Result<Vec<ObjectId>> {
+        let options = FindOptions::builder()
+            .projection(doc! {"_id": 1})
+            .collation(collation.to_mongo())
+            .build();
+        let mut cursor = self
+            .collection
+            .find(query, options)
+            .await
+            .map_err(MongoQueryError)?;
+        let mut ids = Vec::new();
+        while let Some(note) = cursor.next().await {
+            ids.push(note.map_err(MongoQueryError)?.get_object_id("_id")?);
         }
+        Ok(ids)
+    }
+
+    /// Takes a note back out of the trash. Errors with a conflict when the
+    /// note is not in the trash or its title has been taken since.
+    pub async fn restore_note(&self, id: &str) -> Result<Option<SingleNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let restored = self
+            .note_collection
+            .find_one_and_update(
+                doc! {"_id": oid, "deletedAt": {"$ne": null}},
+                doc! {"$unset": {"deletedAt": ""}, "$set": {"updatedAt": Utc::now()}},
+                options,
+            )
+            .await
+            .map_err(restore_write_error)?;
+
+        let Some(note) = restored else {
+            let exists = self
+                .collection
+                .count_documents(doc! {"_id": oid}, None)
+                .await
+                .map_err(MongoQueryError)?;
+            if exists > 0 {
+                return Err(ConflictError("note is not deleted".to_string()));
+            }
+            return Ok(None);
+        };
 
         let note_response = SingleNoteResponse {
             status: "success".to_string(),
             data: NoteData {
-                note: self.doc_to_note(&note_doc.unwrap()).unwrap(),
+                note: self.doc_to_note(&note)?,
+                draft: None,
             },
         };
 
+        if !note.archived {
+            self.adjust_note_count(1).await;
+        }
+        // Clients saw it go with a deleted event.
+        self.events.publish(NoteEvent::created(&note_response));
+
         Ok(Some(note_response))
     }
 
-    pub async fn delete_note(&self, id: &str) -> Result<Option<()>> {
+    /// Notes in the trash, archived or not, newest first.
+    pub async fn trash_notes(&self, page: &Page) -> Result<NoteListResponse> {
+        let query = NotesQuery {
+            filter: NoteListFilter {
+                archived: ArchivedFilter::All,
+                deleted: DeletedFilter::Only,
+                ..NoteListFilter::default()
+            },
+            order: ListOrder::Default,
+            page: *page,
+            collation: None,
+            extras: ListExtras {
+                facets: false,
+                total: true,
+            },
+            projection: None,
+        };
+        self.fetch_notes(&query).await
+    }
+
+    /// Moves every note matching `filter` to the trash, or with `permanent`
+    /// deletes them outright. The matching ids are read first so reminders,
+    /// the counter and events can follow; a note that changes to no longer
+    /// match in between is left alone.
+    pub async fn delete_notes(
+        &self,
+        filter: &NoteListFilter,
+        collation: Option<CollationSpec>,
+        permanent: bool,
+    ) -> Result<u64> {
+        let collation = collation.unwrap_or_else(|| self.default_collation.clone());
+        let query = list_filter(filter, None);
+        let ids = self.matching_ids(query.clone(), &collation).await?;
+        if ids.is_empty() {
+            return Ok(0);
+        }
+        // With `archived=all` some of them are not in the counter.
+        let counted = self
+            .collection
+            .count_documents(
+                doc! {"$and": [counted_filter(), {"_id": {"$in": &ids}}]},
+                None,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+
+        let query = doc! {"$and": [query, {"_id": {"$in": &ids}}]};
+        let deleted_count = if permanent {
+            let options = DeleteOptions::builder()
+                .collation(collation.to_mongo())
+                .build();
+            self.collection
+                .delete_many(query, options)
+                .await
+                .map_err(MongoQueryError)?
+                .deleted_count
+        } else {
+            let now = Utc::now();
+            let options = UpdateOptions::builder()
+                .collation(collation.to_mongo())
+                .build();
+            self.collection
+                .update_many(
+                    query,
+                    doc! {"$set": {"deletedAt": now, "updatedAt": now}},
+                    options,
+                )
+                .await
+                .map_err(MongoQueryError)?
+                .modified_count
+        };
+
+        self.adjust_note_count(-(counted.min(deleted_count) as i64))
+            .await;
+        self.cancel_reminders_of(&ids).await;
+        for id in &ids {
+            self.events.publish(NoteEvent::deleted(&id.to_hex()));
+        }
+
+        Ok(deleted_count)
+    }
+
+    /// Deletes every note, trashed ones included, along with their
+    /// revisions. Only reachable with `ENABLE_DANGEROUS_ENDPOINTS`.
+    pub async fn delete_all_notes(&self) -> Result<u64> {
+        let live = NoteListFilter {
+            archived: ArchivedFilter::All,
+            ..NoteListFilter::default()
+        };
+        let ids = self
+            .matching_ids(list_filter(&live, None), &self.default_collation)
+            .await?;
+
+        let result = self
+            .collection
+            .delete_many(doc! {}, None)
+            .await
+            .map_err(MongoQueryError)?;
+        self.revision_collection
+            .delete_many(doc! {}, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        self.comment_collection
+            .delete_many(doc! {}, None)
+            .await
+            .map_err(MongoQueryError)?;
+        self.delete_attachments(doc! {}).await;
+
+        self.reconcile_note_count().await?;
+        self.cancel_reminders_of(&ids).await;
+        for id in &ids {
+            self.events.publish(NoteEvent::deleted(&id.to_hex()));
+        }
+
+        Ok(result.deleted_count)
+    }
+
+    /// Stores autosaved content without touching `content`, `updatedAt` or
+    /// emitting events. Saves closer than `DRAFT_MIN_INTERVAL_MS` apart are
+    /// refused by the update filter itself, so rate limiting costs no extra read.
+    pub async fn save_draft(&self, id: &str, content: &str) -> Result<Option<SingleDraftResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+        let now = Utc::now();
+        let query = doc! {
+            "_id": oid,
+            "deletedAt": null,
+            "$or": [
+                {"draft": null},
+                {"draft.savedAt": {"$lte": now - chrono::Duration::milliseconds(DRAFT_MIN_INTERVAL_MS)}},
+            ],
+        };
+        let update = doc! {"$set": {"draft": {"content": content, "savedAt": now}}};
+        let find_one_and_update_options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        let note_doc = self
+            .note_collection
+            .find_one_and_update(query, update, find_one_and_update_options)
+            .await
+            .map_err(MongoQueryError)?;
+
+        let note = match note_doc {
+            Some(note) => note,
+            None => {
+                let exists = self
+                    .collection
+                    .count_documents(live_note(oid), None)
+                    .await
+                    .map_err(MongoQueryError)?;
+                if exists > 0 {
+                    return Err(RateLimitedError(
+                        "Drafts can be saved at most once per second".to_string(),
+                    ));
+                }
+                return Ok(None);
+            }
+        };
+
+        let draft = self.draft_to_response(
+            &NoteDraftModel {
+                content: content.to_owned(),
+                savedAt: now,
+            },
+            &note,
+        );
+
+        Ok(Some(SingleDraftResponse {
+            status: "success".to_string(),
+            data: DraftData { draft },
+        }))
+    }
+
+    /// Promotes the draft through the normal edit path, then clears it unless
+    /// a newer autosave arrived in the meantime.
+    pub async fn commit_draft(&self, id: &str) -> Result<Option<SingleNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+
+        let note_doc = self
+            .note_collection
+            .find_one(live_note(oid), None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        let draft = match note_doc {
+            None => return Ok(None),
+            Some(NoteModel { draft: None, .. }) => {
+                return Err(ConflictError("Note has no draft to commit".to_string()))
+            }
+            Some(NoteModel {
+                draft: Some(draft), ..
+            }) => draft,
+        };
+
+        let body = UpdateNoteSchema {
+            content: Some(Some(draft.content)),
+            ..Default::default()
+        };
+        let note_response = self.edit_note(id, &body, None).await?;
+
+        self.collection
+            .update_one(
+                doc! {"_id": oid, "draft.savedAt": draft.savedAt},
+                doc! {"$unset": {"draft": ""}},
+                None,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+
+        Ok(note_response)
+    }
+
+    pub async fn discard_draft(&self, id: &str) -> Result<Option<()>> {
         let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
 
         let result = self
             .collection
-            .delete_one(doc! {"_id":oid }, None)
+            .update_one(live_note(oid), doc! {"$unset": {"draft": ""}}, None)
             .await
             .map_err(MongoQueryError)?;
 
-        if result.deleted_count == 0 {
+        if result.matched_count == 0 {
             return Ok(None);
         }
 
         Ok(Some(()))
     }
 
-    fn doc_to_note(&self, note: &NoteModel) -> Result<NoteResponse> {
+    /// Folds `source_id` into `target_id` (inside a transaction when the
+    /// deployment supports them): the content is joined, tags unioned and
+    /// the earlier `createdAt` kept. The target's previous state becomes a
+    /// revision. The source is then kept, trashed or deleted for good.
+    pub async fn merge_notes(
+        &self,
+        target_id: &str,
+        source_id: &str,
+        strategy: MergeStrategy,
+        source_fate: MergeSourceFate,
+    ) -> Result<Option<MergeNoteResponse>> {
+        let target_oid =
+            ObjectId::from_str(target_id).map_err(|_| InvalidIDError(target_id.to_owned()))?;
+        let source_oid =
+            ObjectId::from_str(source_id).map_err(|_| InvalidIDError(source_id.to_owned()))?;
+        if target_oid == source_oid {
+            return Err(BadRequestError(
+                "A note cannot be merged into itself".to_string(),
+            ));
+        }
+
+        let mut session = self.client.start_session(None).await?;
+        if self.supports_transactions {
+            session.start_transaction(None).await?;
+        }
+
+        let target = self
+            .note_collection
+            .find_one_with_session(live_note(target_oid), None, &mut session)
+            .await
+            .map_err(MongoQueryError)?;
+        let source = self
+            .note_collection
+            .find_one_with_session(live_note(source_oid), None, &mut session)
+            .await
+            .map_err(MongoQueryError)?;
+        let (target, source) = match (target, source) {
+            (Some(target), Some(source)) => (target, source),
+            _ => return Ok(None),
+        };
+
+        let section = format!(
+            "_Merged from \"{}\" ({})_\n\n{}",
+            source.title,
+            source.createdAt.format("%Y-%m-%d"),
+            source.content
+        );
+        let content = match strategy {
+            MergeStrategy::Append => format!("{}\n\n---\n\n{}", target.content, section),
+            MergeStrategy::Prepend => format!("{}\n\n---\n\n{}", section, target.content),
+        };
+        let created_at = target.createdAt.min(source.createdAt);
+        let mut tags = target.tags.clone();
+        for tag in &source.tags {
+            if !tags.contains(tag) {
+                tags.push(tag.to_owned());
+            }
+        }
+
+        let find_one_and_update_options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let mut set = doc! {
+            "createdAt": created_at,
+            "updatedAt": Utc::now(),
+            "tags": tags,
+        };
+        let mut update = doc! {"$inc": {"revision": 1}};
+        Self::set_preview(&content, &mut set, &mut update)?;
+        set.insert("content", content);
+        update.insert("$set", set);
+        let merged = self
+            .note_collection
+            .find_one_and_update_with_session(
+                live_note(target_oid),
+                update,
+                find_one_and_update_options,
+                &mut session,
+            )
+            .await
+            .map_err(MongoQueryError)?
+            .ok_or_else(|| ConflictError("Target note was deleted during merge".to_string()))?;
+        self.record_revision_with_session(&target, &mut session)
+            .await?;
+
+        let source_removed = match source_fate {
+            MergeSourceFate::Keep => false,
+            MergeSourceFate::Trash => {
+                let now = Utc::now();
+                let result = self
+                    .collection
+                    .update_one_with_session(
+                        live_note(source_oid),
+                        doc! {"$set": {"deletedAt": now, "updatedAt": now}},
+                        None,
+                        &mut session,
+                    )
+                    .await
+                    .map_err(MongoQueryError)?;
+                result.modified_count > 0
+            }
+            MergeSourceFate::Delete => {
+                let result = self
+                    .collection
+                    .delete_one_with_session(doc! {"_id": source_oid}, None, &mut session)
+                    .await
+                    .map_err(MongoQueryError)?;
+                result.deleted_count > 0
+            }
+        };
+
+        if self.supports_transactions {
+            session.commit_transaction().await?;
+        }
+
+        let note = self.doc_to_note(&merged)?;
+        self.events.publish(NoteEvent::Updated {
+            note: note.clone(),
+            changed_fields: vec![
+                "content".to_string(),
+                "createdAt".to_string(),
+                "tags".to_string(),
+            ],
+        });
+        if source_removed {
+            if !source.archived {
+                self.adjust_note_count(-1).await;
+            }
+            self.cancel_note_reminders(source_oid).await;
+            self.events.publish(NoteEvent::deleted(source_id));
+        }
+        if source_removed && source_fate == MergeSourceFate::Delete {
+            self.delete_note_data(&[source_oid]).await;
+        }
+
+        Ok(Some(MergeNoteResponse {
+            status: "success".to_string(),
+            data: MergeNoteData {
+                note,
+                source: MergeSourceResponse {
+                    id: source_id.to_owned(),
+                    trashed: source_removed && source_fate == MergeSourceFate::Trash,
+                    deleted: source_removed && source_fate == MergeSourceFate::Delete,
+                },
+            },
+        }))
+    }
+
+    fn draft_to_response(&self, draft: &NoteDraftModel, note: &NoteModel) -> DraftResponse {
+        DraftResponse {
+            content: draft.content.to_owned(),
+            savedAt: draft.savedAt,
+            draft_newer_than_content: draft.savedAt > note.updatedAt,
+        }
+    }
+
+    /// HTTP-dates only carry whole seconds while `updatedAt` is stored with
+    /// millisecond precision, so a note counts as unmodified for the whole
+    /// second named by the header.
+    fn unmodified_since_filter(since: DateTime<Utc>) -> Document {
+        doc! {"updatedAt": {"$lt": since + chrono::Duration::seconds(1)}}
+    }
+
+    /// Called when a conditional write matched nothing: if the note exists the
+    /// precondition failed, otherwise the caller reports a plain 404.
+    async fn check_precondition(&self, oid: ObjectId) -> Result<()> {
+        let note_doc = self
+            .note_collection
+            .find_one(live_note(oid), None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        match note_doc {
+            Some(note) => Err(PreconditionFailedError(note.updatedAt)),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds the preview for new `content` to an update: `$set` when there is
+    /// one, `$unset` when the content no longer has an image or link.
+    fn set_preview(content: &str, set: &mut Document, update: &mut Document) -> Result<()> {
+        match preview::extract(content) {
+            Some(preview) => {
+                set.insert(
+                    "preview",
+                    bson::to_bson(&preview).map_err(MongoSerializeBsonError)?,
+                );
+            }
+            None => {
+                update.insert("$unset", doc! {"preview": ""});
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads whatever a projection left in the document; absent fields stay
+    /// `None` and are left out of the response.
+    fn doc_to_partial_note(note: &Document) -> PartialNoteResponse {
+        let date = |key: &str| note.get_datetime(key).ok().map(|date| date.to_chrono());
+        let word_count = note.get_str("content").ok().map(reading_time::word_count);
+        PartialNoteResponse {
+            id: note
+                .get_object_id("_id")
+                .map(|id| id.to_hex())
+                .unwrap_or_default(),
+            title: note.get_str("title").ok().map(str::to_owned),
+            content: note.get_str("content").ok().map(str::to_owned),
+            category: note.get_str("category").ok().map(str::to_owned),
+            published: note.get_bool("published").ok(),
+            color: note.get_str("color").ok().map(str::to_owned),
+            createdAt: date("createdAt"),
+            updatedAt: date("updatedAt"),
+            slug: note.get_str("slug").ok().map(str::to_owned),
+            publishedAt: date("publishedAt"),
+            archived: note.get_bool("archived").ok(),
+            pinned: note.get_bool("pinned").ok(),
+            items: note
+                .get("items")
+                .and_then(|items| bson::from_bson(items.clone()).ok()),
+            position: note.get_i64("position").ok(),
+            word_count,
+            reading_time_minutes: word_count.map(reading_time::reading_time_minutes),
+            tags: note.get_array("tags").ok().map(|tags| {
+                tags.iter()
+                    .filter_map(|tag| tag.as_str().map(str::to_owned))
+                    .collect()
+            }),
+            deletedAt: date("deletedAt"),
+            preview: note
+                .get("preview")
+                .and_then(|preview| bson::from_bson(preview.clone()).ok()),
+        }
+    }
+
+    pub(crate) fn doc_to_note(&self, note: &NoteModel) -> Result<NoteResponse> {
+        let word_count = reading_time::word_count(&note.content);
         let note_response = NoteResponse {
             id: note.id.to_hex(),
             title: note.title.to_owned(),
             content: note.content.to_owned(),
-            category: note.category.to_owned().unwrap(),
-            published: note.published.unwrap(),
+            category: note.category.to_owned().unwrap_or_default(),
+            published: note.published.unwrap_or_default(),
+            color: note.color.to_owned(),
             createdAt: note.createdAt,
             updatedAt: note.updatedAt,
+            slug: note.slug.to_owned(),
+            publishedAt: note.publishedAt,
+            archived: note.archived,
+            pinned: note.pinned,
+            tags: note.tags.to_owned(),
+            items: note.items.to_owned(),
+            position: note.position,
+            word_count,
+            reading_time_minutes: reading_time::reading_time_minutes(word_count),
+            deletedAt: note.deletedAt,
+            preview: note.preview.to_owned(),
         };
 
         Ok(note_response)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{config, drop_live_db, live_db, offline_db, write_error};
+
+    #[test]
+    fn search_terms_become_mongo_conditions() {
+        let terms =
+            crate::query_parser::parse_query(r#"title:"a.b" -category:personal published:false x"#)
+                .unwrap();
+        let conditions: Vec<Document> = terms.iter().map(query_condition).collect();
+        assert_eq!(
+            conditions,
+            [
+                doc! {"title": {"$regex": "a\\.b", "$options": "i"}},
+                doc! {"$nor": [{"category": "personal"}]},
+                doc! {"published": false},
+                doc! {"content": {"$regex": "x", "$options": "i"}},
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn every_patch_sets_updated_at() {
+        let config = config();
+        let db = offline_db(&config);
+        let at = Utc::now();
+
+        let title = UpdateNoteSchema {
+            title: Some(Some("Plan".to_string())),
+            ..UpdateNoteSchema::default()
+        };
+        let mut update = db.note_update(&title).unwrap();
+        DB::set_updated_at(&mut update, at);
+        let set = update.get_document("$set").unwrap();
+        assert_eq!(set.get_str("title").unwrap(), "Plan");
+        assert_eq!(
+            set.get_datetime("updatedAt").unwrap().to_chrono(),
+            at.trunc_subsecs(3)
+        );
+
+        // Clearing a field alone has no `$set` of its own.
+        let clear = UpdateNoteSchema {
+            color: Some(None),
+            ..UpdateNoteSchema::default()
+        };
+        let mut update = db.note_update(&clear).unwrap();
+        assert!(!update.contains_key("$set"));
+        DB::set_updated_at(&mut update, at);
+        assert_eq!(
+            update.get_document("$set").unwrap(),
+            &doc! {"updatedAt": at}
+        );
+        assert!(update.get_document("$unset").unwrap().contains_key("color"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn a_patch_moves_updated_at() {
+        let db = live_db(&config()).await;
+        let body: CreateNoteSchema =
+            serde_json::from_value(serde_json::json!({"title": "Plan", "content": "x"})).unwrap();
+        let created = db.create_note(&body).await.unwrap().data.note;
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+
+        let patch = UpdateNoteSchema {
+            category: Some(Some("work".to_string())),
+            ..UpdateNoteSchema::default()
+        };
+        let edited = db
+            .edit_note(&created.id, &patch, None)
+            .await
+            .unwrap()
+            .unwrap()
+            .data
+            .note;
+        assert!(edited.updatedAt > created.updatedAt);
+        assert_eq!(edited.createdAt, created.createdAt);
+        drop_live_db(&db).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn racing_bootstraps_create_one_set_of_starters() {
+        let db = live_db(&config()).await;
+        let starters: Arc<Vec<CreateNoteSchema>> =
+            Arc::new(serde_json::from_str(include_str!("bootstrap.json")).unwrap());
+
+        let replicas: Vec<_> = (0..8)
+            .map(|_| {
+                let db = db.clone();
+                let starters = starters.clone();
+                tokio::spawn(async move { db.bootstrap_notes(&starters).await })
+            })
+            .collect();
+        let mut created = Vec::new();
+        for replica in replicas {
+            if let Some(notes) = replica.await.unwrap().expect("bootstrap") {
+                created.push(notes);
+            }
+        }
+
+        assert_eq!(created.len(), 1, "more than one replica bootstrapped");
+        assert_eq!(created[0].results, starters.len());
+        let stored = db.collection.count_documents(doc! {}, None).await.unwrap();
+        assert_eq!(stored as usize, starters.len());
+        drop_live_db(&db).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn a_second_note_with_the_same_title_is_a_duplicate() {
+        let db = live_db(&config()).await;
+        let note: CreateNoteSchema =
+            serde_json::from_value(serde_json::json!({"title": "Groceries", "content": "Milk"}))
+                .unwrap();
+        db.create_note(&note).await.expect("first note");
+
+        match db.create_note(&note).await {
+            Err(MongoDuplicateError { field, value, .. }) => {
+                assert_eq!((field, value.as_str()), ("title", "Groceries"))
+            }
+            other => panic!("expected a duplicate error, got {:?}", other.map(|_| ())),
+        }
+        drop_live_db(&db).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn notes_from_the_same_millisecond_never_repeat_across_pages() {
+        let db = live_db(&config()).await;
+        let created = Utc::now();
+        let mut documents = Vec::new();
+        for i in 0..7 {
+            let body: CreateNoteSchema = serde_json::from_value(serde_json::json!({
+                "title": format!("Note {}", i),
+                "content": "Written at the same time",
+            }))
+            .unwrap();
+            let mut document = db.new_note_document(&body).unwrap();
+            document.insert("createdAt", created);
+            document.insert("updatedAt", created);
+            documents.push(document);
+        }
+        db.collection.insert_many(documents, None).await.unwrap();
+
+        let mut seen: Vec<String> = Vec::new();
+        for page in 1..=3 {
+            let list = db
+                .fetch_notes(&NotesQuery {
+                    filter: NoteListFilter::default(),
+                    order: ListOrder::Default,
+                    page: Page { page, limit: 3 },
+                    collation: None,
+                    extras: ListExtras {
+                        facets: false,
+                        total: false,
+                    },
+                    projection: None,
+                })
+                .await
+                .unwrap();
+            for note in &list.notes {
+                assert!(
+                    !seen.contains(&note.id().to_owned()),
+                    "{} seen twice",
+                    note.id()
+                );
+                seen.push(note.id().to_owned());
+            }
+        }
+        assert_eq!(seen.len(), 7);
+        drop_live_db(&db).await;
+    }
+
+    #[test]
+    fn a_restore_refused_by_the_title_index_is_a_conflict() {
+        match restore_write_error(write_error(DUPLICATE_KEY, "")) {
+            ConflictError(message) => assert!(message.contains("title"), "{}", message),
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+        assert!(matches!(
+            restore_write_error(write_error(121, "")),
+            MongoQueryError(_)
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn restore_takes_a_note_out_of_the_trash_once() {
+        let db = live_db(&config()).await;
+        let body: CreateNoteSchema =
+            serde_json::from_value(serde_json::json!({"title": "Plan", "content": "Steps"}))
+                .unwrap();
+        let id = db.create_note(&body).await.unwrap().data.note.id;
+
+        match db.restore_note(&id).await {
+            Err(ConflictError(message)) => assert_eq!(message, "note is not deleted"),
+            other => panic!("a live note was restored: {:?}", other.map(|_| ())),
+        }
+
+        db.delete_note(&id, None, false).await.unwrap().unwrap();
+        let restored = db.restore_note(&id).await.unwrap().unwrap();
+        assert_eq!(restored.data.note.title, "Plan");
+        assert!(restored.data.note.deletedAt.is_none());
+        assert!(db.get_note(&id, false).await.unwrap().is_some());
+
+        let missing = ObjectId::new().to_hex();
+        assert!(db.restore_note(&missing).await.unwrap().is_none());
+        drop_live_db(&db).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn overlapping_tags_tell_any_from_all() {
+        let db = live_db(&config()).await;
+        for (title, tags) in [
+            ("Only rust", vec!["rust"]),
+            ("Both", vec!["rust", "mongo"]),
+            ("Mongo and web", vec!["mongo", "web"]),
+            ("Untagged", vec![]),
+        ] {
+            let body: CreateNoteSchema = serde_json::from_value(serde_json::json!({
+                "title": title,
+                "content": "x",
+                "tags": tags,
+            }))
+            .unwrap();
+            db.create_note(&body).await.unwrap();
+        }
+
+        let titles = |filter: NoteListFilter| {
+            let db = db.clone();
+            async move {
+                let list = db
+                    .fetch_notes(&NotesQuery {
+                        filter,
+                        order: ListOrder::Default,
+                        page: Page { page: 1, limit: 10 },
+                        collation: None,
+                        extras: ListExtras {
+                            facets: false,
+                            total: true,
+                        },
+                        projection: None,
+                    })
+                    .await
+                    .unwrap();
+                let mut titles: Vec<String> = list
+                    .notes
+                    .iter()
+                    .map(|note| match note {
+                        ListedNote::Full(note) => note.title.clone(),
+                        ListedNote::Partial(_) => panic!("no projection was asked for"),
+                    })
+                    .collect();
+                titles.sort();
+                titles
+            }
+        };
+        let wanted = vec!["rust".to_string(), "mongo".to_string()];
+
+        let any = titles(NoteListFilter {
+            tags: wanted.clone(),
+            ..NoteListFilter::default()
+        })
+        .await;
+        assert_eq!(any, ["Both", "Mongo and web", "Only rust"]);
+
+        let all = titles(NoteListFilter {
+            tags_all: wanted,
+            ..NoteListFilter::default()
+        })
+        .await;
+        assert_eq!(all, ["Both"]);
+        drop_live_db(&db).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn delete_all_counts_trashed_notes_too() {
+        let db = live_db(&config()).await;
+        let mut ids = Vec::new();
+        for title in ["One", "Two", "Three"] {
+            let body: CreateNoteSchema =
+                serde_json::from_value(serde_json::json!({"title": title, "content": "x"}))
+                    .unwrap();
+            ids.push(db.create_note(&body).await.unwrap().data.note.id);
+        }
+        db.delete_note(&ids[0], None, false).await.unwrap().unwrap();
+
+        assert_eq!(db.delete_all_notes().await.unwrap(), 3);
+        assert_eq!(
+            db.collection.count_documents(doc! {}, None).await.unwrap(),
+            0
+        );
+        drop_live_db(&db).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn titles_differing_in_case_and_spacing_conflict() {
+        let mut config = config();
+        config.title_normalizer.case_fold = true;
+        let db = live_db(&config).await;
+        let note = |title: &str| -> CreateNoteSchema {
+            serde_json::from_value(serde_json::json!({"title": title, "content": "x"})).unwrap()
+        };
+
+        let stored = db.create_note(&note("  FOO   bar ")).await.unwrap();
+        assert_eq!(stored.data.note.title, "FOO bar");
+        match db.create_note(&note("foo bar")).await {
+            Err(MongoDuplicateError { field, value, .. }) => {
+                assert_eq!((field, value.as_str()), ("title", "foo bar"))
+            }
+            other => panic!("expected a duplicate error, got {:?}", other.map(|_| ())),
+        }
+        drop_live_db(&db).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn drafts_of_trashed_notes_are_not_found() {
+        let db = live_db(&config()).await;
+        let body: CreateNoteSchema =
+            serde_json::from_value(serde_json::json!({"title": "Plan", "content": "Steps"}))
+                .unwrap();
+        let id = db.create_note(&body).await.unwrap().data.note.id;
+        db.save_draft(&id, "More steps").await.unwrap().unwrap();
+        db.delete_note(&id, None, false).await.unwrap().unwrap();
+
+        assert!(db.save_draft(&id, "Even more").await.unwrap().is_none());
+        assert!(db.discard_draft(&id).await.unwrap().is_none());
+
+        db.restore_note(&id).await.unwrap().unwrap();
+        let oid = ObjectId::from_str(&id).unwrap();
+        let note = db.note_collection.find_one(doc! {"_id": oid}, None).await;
+        assert_eq!(note.unwrap().unwrap().draft.unwrap().content, "More steps");
+        drop_live_db(&db).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn published_events_only_follow_a_change() {
+        let db = live_db(&config()).await;
+        let body: CreateNoteSchema =
+            serde_json::from_value(serde_json::json!({"title": "Plan", "content": "Steps"}))
+                .unwrap();
+        let id = db.create_note(&body).await.unwrap().data.note.id;
+        let since = db.events.last_seq();
+
+        for published in [false, true, true] {
+            let patch = UpdateNoteSchema {
+                published: Some(Some(published)),
+                ..UpdateNoteSchema::default()
+            };
+            db.edit_note(&id, &patch, None).await.unwrap().unwrap();
+            let filter = NoteListFilter::default();
+            db.update_notes(&filter, None, &patch).await.unwrap();
+        }
+
+        let kinds: Vec<&str> = db
+            .events
+            .subscribe_since(since)
+            .backlog
+            .iter()
+            .filter_map(|envelope| match envelope.event {
+                NoteEvent::Published { .. } => Some("published"),
+                NoteEvent::Unpublished { .. } => Some("unpublished"),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(kinds, ["published"]);
+        drop_live_db(&db).await;
+    }
+}
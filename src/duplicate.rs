@@ -0,0 +1,143 @@
+use crate::{
+    db::{live_note, DB},
+    error::{mongo_error_code, Error::*, DUPLICATE_KEY},
+    events::NoteEvent,
+    response::{NoteData, SingleNoteResponse},
+    schema::{ChecklistItemSchema, CreateNoteSchema},
+    Result,
+};
+use mongodb::bson::{doc, oid::ObjectId};
+use std::str::FromStr;
+
+/// Titles tried before giving up: "(copy)", then "(copy 2)" up to this.
+const MAX_COPY_ATTEMPTS: u32 = 100;
+
+/// The title of the `attempt`th copy, counting from 1.
+fn copy_title(title: &str, attempt: u32) -> String {
+    match attempt {
+        1 => format!("{} (copy)", title),
+        n => format!("{} (copy {})", title, n),
+    }
+}
+
+impl DB {
+    /// Copies a note into a new, unpublished note titled "<title> (copy)",
+    /// numbering the copy when that title is taken. The draft is not copied.
+    pub async fn duplicate_note(&self, id: &str) -> Result<Option<SingleNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+        let Some(source) = self
+            .note_collection
+            .find_one(live_note(oid), None)
+            .await
+            .map_err(MongoQueryError)?
+        else {
+            return Ok(None);
+        };
+
+        for attempt in 1..=MAX_COPY_ATTEMPTS {
+            let body = CreateNoteSchema {
+                title: copy_title(&source.title, attempt),
+                content: source.content.clone(),
+                category: source.category.clone(),
+                published: Some(false),
+                color: source.color.clone(),
+                tags: source.tags.clone(),
+                items: source
+                    .items
+                    .iter()
+                    .map(|item| ChecklistItemSchema {
+                        text: item.text.clone(),
+                        done: item.done,
+                    })
+                    .collect(),
+            };
+            let new_id = ObjectId::new();
+            let mut document = self.new_note_document(&body)?;
+            document.insert("_id", new_id);
+            // A slug taken meanwhile fails like a taken title and moves on
+            // to the next copy.
+            document.insert("slug", self.free_slug(&body.title, None, &[]).await?);
+            document.insert("position", self.next_position().await?);
+
+            match self.collection.insert_one(document, None).await {
+                Ok(_) => {}
+                Err(e) if mongo_error_code(&e) == Some(DUPLICATE_KEY) => continue,
+                Err(e) => return Err(MongoQueryError(e)),
+            }
+
+            let note = self
+                .note_collection
+                .find_one(doc! {"_id": new_id}, None)
+                .await
+                .map_err(MongoQueryError)?
+                .ok_or_else(|| ConflictError("Copy was deleted right away".to_string()))?;
+            let note_response = SingleNoteResponse {
+                status: "success".to_string(),
+                data: NoteData {
+                    note: self.doc_to_note(&note)?,
+                    draft: None,
+                },
+            };
+
+            self.adjust_note_count(1).await;
+            self.events.publish(NoteEvent::created(&note_response));
+
+            return Ok(Some(note_response));
+        }
+
+        Err(ConflictError(format!(
+            "Could not find a free title for a copy of '{}'",
+            source.title
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{config, drop_live_db, live_db, offline_db};
+
+    #[test]
+    fn copies_are_numbered_from_the_second() {
+        assert_eq!(copy_title("Plan", 1), "Plan (copy)");
+        assert_eq!(copy_title("Plan", 2), "Plan (copy 2)");
+        assert_eq!(copy_title("Plan (copy)", 1), "Plan (copy) (copy)");
+    }
+
+    #[tokio::test]
+    async fn a_bad_id_is_refused_before_any_query() {
+        let config = config();
+        let db = offline_db(&config);
+        assert!(matches!(
+            db.duplicate_note("nope").await,
+            Err(InvalidIDError(id)) if id == "nope"
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn taken_copy_titles_are_skipped() {
+        let db = live_db(&config()).await;
+        let body: CreateNoteSchema = serde_json::from_value(serde_json::json!({
+            "title": "Plan",
+            "content": "Steps",
+            "published": true,
+        }))
+        .unwrap();
+        let source = db.create_note(&body).await.unwrap().data.note;
+
+        let mut titles = Vec::new();
+        for _ in 0..3 {
+            let copy = db.duplicate_note(&source.id).await.unwrap().unwrap();
+            assert_ne!(copy.data.note.id, source.id);
+            assert!(!copy.data.note.published);
+            assert_eq!(copy.data.note.content, "Steps");
+            titles.push(copy.data.note.title);
+        }
+        assert_eq!(titles, ["Plan (copy)", "Plan (copy 2)", "Plan (copy 3)"]);
+
+        let missing = ObjectId::new().to_hex();
+        assert!(db.duplicate_note(&missing).await.unwrap().is_none());
+        drop_live_db(&db).await;
+    }
+}
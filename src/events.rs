@@ -0,0 +1,400 @@
+use crate::response::{NoteResponse, SingleNoteResponse};
+use crate::schema::UpdateNoteSchema;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+/// The single event shape every change-notification mechanism serializes.
+/// Serialized as `{"type": "note.updated", ...}`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum NoteEvent {
+    #[serde(rename = "note.created")]
+    Created { note: NoteResponse },
+    #[serde(rename = "note.updated")]
+    Updated {
+        note: NoteResponse,
+        changed_fields: Vec<String>,
+    },
+    #[serde(rename = "note.deleted")]
+    Deleted { id: String },
+    #[serde(rename = "note.published")]
+    Published { id: String },
+    #[serde(rename = "note.unpublished")]
+    Unpublished { id: String },
+}
+
+impl NoteEvent {
+    pub fn created(note: &SingleNoteResponse) -> Self {
+        NoteEvent::Created {
+            note: note.data.note.clone(),
+        }
+    }
+
+    pub fn updated(note: &SingleNoteResponse, body: &UpdateNoteSchema) -> Self {
+        let mut changed_fields = Vec::new();
+        if body.title.is_some() {
+            changed_fields.push("title".to_string());
+        }
+        if body.content.is_some() {
+            changed_fields.push("content".to_string());
+        }
+        if body.category.is_some() {
+            changed_fields.push("category".to_string());
+        }
+        if body.published.is_some() {
+            changed_fields.push("published".to_string());
+        }
+        if body.color.is_some() {
+            changed_fields.push("color".to_string());
+        }
+        if body.items.is_some() {
+            changed_fields.push("items".to_string());
+        }
+
+        NoteEvent::Updated {
+            note: note.data.note.clone(),
+            changed_fields,
+        }
+    }
+
+    /// A full replacement: every client field counts as changed.
+    pub fn replaced(note: &SingleNoteResponse) -> Self {
+        NoteEvent::Updated {
+            note: note.data.note.clone(),
+            changed_fields: [
+                "title",
+                "content",
+                "category",
+                "published",
+                "color",
+                "items",
+            ]
+            .iter()
+            .map(|field| field.to_string())
+            .collect(),
+        }
+    }
+
+    /// A change to fields a PATCH body cannot carry, such as `archived`.
+    pub fn changed(note: &SingleNoteResponse, fields: &[&str]) -> Self {
+        NoteEvent::Updated {
+            note: note.data.note.clone(),
+            changed_fields: fields.iter().map(|field| field.to_string()).collect(),
+        }
+    }
+
+    pub fn deleted(id: &str) -> Self {
+        NoteEvent::Deleted { id: id.to_owned() }
+    }
+
+    pub fn published(id: &str, published: bool) -> Self {
+        if published {
+            NoteEvent::Published { id: id.to_owned() }
+        } else {
+            NoteEvent::Unpublished { id: id.to_owned() }
+        }
+    }
+
+    pub fn note_id(&self) -> &str {
+        match self {
+            NoteEvent::Created { note } | NoteEvent::Updated { note, .. } => &note.id,
+            NoteEvent::Deleted { id }
+            | NoteEvent::Published { id }
+            | NoteEvent::Unpublished { id } => id,
+        }
+    }
+}
+
+/// How many recent events are kept for clients catching up by `global_seq`.
+const RECENT_EVENTS: usize = 1024;
+
+/// A note's `seq` is forgotten once it has gone this long without events,
+/// so the map does not grow with every note ever touched. Its next event
+/// starts over at 1.
+const NOTE_SEQ_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How many events go by between sweeps for forgotten `seq`s.
+const SWEEP_EVERY: u64 = 1024;
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug, Clone)]
+pub struct NoteEventEnvelope {
+    /// Position in the stream of all events since startup.
+    pub global_seq: u64,
+    /// Position among the events of this note.
+    pub seq: u64,
+    pub occurredAt: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: NoteEvent,
+}
+
+pub struct Subscription {
+    pub receiver: broadcast::Receiver<NoteEventEnvelope>,
+    /// Buffered events after the requested sequence.
+    pub backlog: Vec<NoteEventEnvelope>,
+    /// False when some events after the requested sequence have already
+    /// left the buffer.
+    pub complete: bool,
+}
+
+#[derive(Debug, Default)]
+struct Sequences {
+    global: u64,
+    /// Last `seq` of each note and when it was handed out.
+    per_note: HashMap<String, (u64, Instant)>,
+    recent: VecDeque<NoteEventEnvelope>,
+}
+
+impl Sequences {
+    fn forget_idle(&mut self, now: Instant) {
+        self.per_note
+            .retain(|_, (_, last)| now.duration_since(*last) < NOTE_SEQ_TTL);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct EventBus {
+    sender: broadcast::Sender<NoteEventEnvelope>,
+    sequences: Arc<Mutex<Sequences>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(256);
+
+        Self {
+            sender,
+            sequences: Arc::new(Mutex::new(Sequences::default())),
+        }
+    }
+
+    pub fn publish(&self, event: NoteEvent) {
+        let mut sequences = self.sequences.lock().unwrap();
+        let id = event.note_id().to_owned();
+        // Kept past a delete: a note restored from the trash keeps counting
+        // up rather than starting over at 1, unless it stayed there past
+        // `NOTE_SEQ_TTL`.
+        let now = Instant::now();
+        let seq = sequences.per_note.get(&id).map_or(0, |(seq, _)| *seq) + 1;
+        sequences.per_note.insert(id, (seq, now));
+        sequences.global += 1;
+        if sequences.global.is_multiple_of(SWEEP_EVERY) {
+            sequences.forget_idle(now);
+        }
+
+        let envelope = NoteEventEnvelope {
+            global_seq: sequences.global,
+            seq,
+            occurredAt: Utc::now(),
+            event,
+        };
+        if sequences.recent.len() == RECENT_EVENTS {
+            sequences.recent.pop_front();
+        }
+        sequences.recent.push_back(envelope.clone());
+
+        // Sent while still holding the lock so subscribers see events in
+        // `global_seq` order. No subscribers is not an error: the event
+        // simply has nobody to go to.
+        let _ = self.sender.send(envelope);
+    }
+
+    /// Subscribes and collects the buffered events after `since` in one
+    /// step, so nothing published in between is missed or seen twice.
+    pub fn subscribe_since(&self, since: u64) -> Subscription {
+        let sequences = self.sequences.lock().unwrap();
+        Subscription {
+            receiver: self.sender.subscribe(),
+            backlog: sequences
+                .recent
+                .iter()
+                .filter(|envelope| envelope.global_seq > since)
+                .cloned()
+                .collect(),
+            complete: sequences
+                .recent
+                .front()
+                .is_none_or(|oldest| oldest.global_seq <= since + 1),
+        }
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.sequences.lock().unwrap().global
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn at() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+    }
+
+    fn note() -> NoteResponse {
+        NoteResponse {
+            id: "6630f0c2a1b2c3d4e5f60718".to_string(),
+            title: "Groceries".to_string(),
+            content: "Milk".to_string(),
+            category: "home".to_string(),
+            published: false,
+            color: None,
+            createdAt: at(),
+            updatedAt: at(),
+            slug: Some("groceries".to_string()),
+            publishedAt: None,
+            archived: false,
+            pinned: false,
+            tags: vec!["errands".to_string()],
+            items: Vec::new(),
+            position: 1024,
+            word_count: 1,
+            reading_time_minutes: 1,
+            deletedAt: None,
+            preview: None,
+        }
+    }
+
+    fn note_json() -> serde_json::Value {
+        json!({
+            "id": "6630f0c2a1b2c3d4e5f60718",
+            "title": "Groceries",
+            "content": "Milk",
+            "category": "home",
+            "published": false,
+            "color": null,
+            "createdAt": "2024-05-01T12:00:00Z",
+            "updatedAt": "2024-05-01T12:00:00Z",
+            "slug": "groceries",
+            "archived": false,
+            "pinned": false,
+            "tags": ["errands"],
+            "items": [],
+            "position": 1024,
+            "word_count": 1,
+            "reading_time_minutes": 1
+        })
+    }
+
+    fn serialized(event: NoteEvent) -> serde_json::Value {
+        serde_json::to_value(NoteEventEnvelope {
+            global_seq: 7,
+            seq: 3,
+            occurredAt: at(),
+            event,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn created_snapshot() {
+        assert_eq!(
+            serialized(NoteEvent::Created { note: note() }),
+            json!({
+                "type": "note.created",
+                "global_seq": 7,
+                "seq": 3,
+                "occurredAt": "2024-05-01T12:00:00Z",
+                "note": note_json()
+            })
+        );
+    }
+
+    #[test]
+    fn updated_snapshot() {
+        let event = NoteEvent::Updated {
+            note: note(),
+            changed_fields: vec!["title".to_string(), "tags".to_string()],
+        };
+        assert_eq!(
+            serialized(event),
+            json!({
+                "type": "note.updated",
+                "global_seq": 7,
+                "seq": 3,
+                "occurredAt": "2024-05-01T12:00:00Z",
+                "note": note_json(),
+                "changed_fields": ["title", "tags"]
+            })
+        );
+    }
+
+    #[test]
+    fn id_only_snapshots() {
+        let id = "6630f0c2a1b2c3d4e5f60718";
+        for (event, kind) in [
+            (NoteEvent::deleted(id), "note.deleted"),
+            (NoteEvent::published(id, true), "note.published"),
+            (NoteEvent::published(id, false), "note.unpublished"),
+        ] {
+            assert_eq!(
+                serialized(event),
+                json!({
+                    "type": kind,
+                    "global_seq": 7,
+                    "seq": 3,
+                    "occurredAt": "2024-05-01T12:00:00Z",
+                    "id": id
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn note_seq_keeps_rising_after_a_delete() {
+        let bus = EventBus::new();
+        let id = "6630f0c2a1b2c3d4e5f60718";
+        bus.publish(NoteEvent::Created { note: note() });
+        bus.publish(NoteEvent::deleted(id));
+        bus.publish(NoteEvent::published("6630f0c2a1b2c3d4e5f60719", true));
+        bus.publish(NoteEvent::changed(
+            &SingleNoteResponse {
+                status: "success".to_string(),
+                data: crate::response::NoteData {
+                    note: note(),
+                    draft: None,
+                },
+            },
+            &["deletedAt"],
+        ));
+
+        let seqs: Vec<(u64, u64)> = bus
+            .subscribe_since(0)
+            .backlog
+            .iter()
+            .filter(|envelope| envelope.event.note_id() == id)
+            .map(|envelope| (envelope.global_seq, envelope.seq))
+            .collect();
+        assert_eq!(seqs, vec![(1, 1), (2, 2), (4, 3)]);
+    }
+
+    #[test]
+    fn idle_note_seqs_are_forgotten() {
+        let bus = EventBus::new();
+        bus.publish(NoteEvent::deleted("6630f0c2a1b2c3d4e5f60718"));
+        bus.publish(NoteEvent::deleted("6630f0c2a1b2c3d4e5f60718"));
+        let later = Instant::now() + Duration::from_secs(60);
+        bus.sequences.lock().unwrap().forget_idle(later);
+        bus.publish(NoteEvent::deleted("6630f0c2a1b2c3d4e5f60719"));
+        bus.sequences
+            .lock()
+            .unwrap()
+            .forget_idle(later + NOTE_SEQ_TTL);
+        bus.publish(NoteEvent::deleted("6630f0c2a1b2c3d4e5f60718"));
+
+        let seqs: Vec<u64> = bus
+            .subscribe_since(0)
+            .backlog
+            .iter()
+            .map(|envelope| envelope.seq)
+            .collect();
+        assert_eq!(seqs, vec![1, 2, 1, 1]);
+        assert_eq!(bus.sequences.lock().unwrap().per_note.len(), 1);
+    }
+}
@@ -0,0 +1,213 @@
+//! Property tests that throw malformed requests at the full `routes()` tree.
+//!
+//! Whatever comes in, the server must answer without panicking, every error
+//! must be the usual `{status, message}` JSON envelope, and a 500 may only
+//! mean the request got as far as the (here unreachable) database.
+
+use crate::routes::routes;
+use crate::test_support::{block_on, config, offline_db};
+use mongodb::bson::oid::ObjectId;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use proptest::prelude::*;
+use std::time::Duration;
+use warp::http::Response;
+use warp::hyper::body::Bytes;
+use warp::test::{request, RequestBuilder};
+
+/// The 500 messages of a query that reached the database.
+const DATABASE_FAILURES: [&str; 2] = ["Error during mongodb query", "MongoDB error"];
+
+/// Sends `request` through a fresh route tree. `None` when it was still
+/// waiting after a few seconds, which only the long-polling routes do.
+fn send(request: RequestBuilder) -> Option<Response<Bytes>> {
+    block_on(async {
+        let config = config();
+        let routes = routes(offline_db(&config), config);
+        tokio::time::timeout(Duration::from_secs(5), request.reply(&routes))
+            .await
+            .ok()
+    })
+}
+
+fn check(response: &Response<Bytes>) -> Result<(), String> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let body: serde_json::Value = serde_json::from_slice(response.body())
+        .map_err(|e| format!("{} with a body that is not JSON: {}", status, e))?;
+    let (Some(_), Some(message)) = (body["status"].as_str(), body["message"].as_str()) else {
+        return Err(format!("{} without the error envelope: {}", status, body));
+    };
+    if status.is_server_error() && !DATABASE_FAILURES.contains(&message) {
+        return Err(format!("{} before reaching the database: {}", status, body));
+    }
+    Ok(())
+}
+
+fn json_request(method: &str, path: &str, body: impl AsRef<[u8]>) -> RequestBuilder {
+    request()
+        .method(method)
+        .path(path)
+        .header("content-type", "application/json")
+        .body(body)
+}
+
+/// JSON text, including what `serde_json::Value` cannot hold: numbers past
+/// `f64`, lone surrogates and NUL characters.
+fn json_text() -> impl Strategy<Value = String> {
+    let leaf = prop_oneof![
+        Just("null".to_string()),
+        any::<bool>().prop_map(|b| b.to_string()),
+        any::<i64>().prop_map(|n| n.to_string()),
+        any::<f64>()
+            .prop_filter("finite", |n| n.is_finite())
+            .prop_map(|n| n.to_string()),
+        Just("1e400".to_string()),
+        Just("-123456789012345678901234567890".to_string()),
+        Just(r#""\ud800""#.to_string()),
+        Just(r#""a\u0000b""#.to_string()),
+        any::<String>().prop_map(|s| serde_json::to_string(&s).unwrap()),
+    ];
+    leaf.prop_recursive(4, 32, 6, |inner| {
+        prop_oneof![
+            prop::collection::vec(inner.clone(), 0..6).prop_map(|v| format!("[{}]", v.join(","))),
+            prop::collection::vec((any::<String>(), inner), 0..6).prop_map(|fields| {
+                let fields: Vec<String> = fields
+                    .into_iter()
+                    .map(|(k, v)| format!("{}:{}", serde_json::to_string(&k).unwrap(), v))
+                    .collect();
+                format!("{{{}}}", fields.join(","))
+            }),
+        ]
+    })
+}
+
+/// An object with the keys of `CreateNoteSchema`/`UpdateNoteSchema` and
+/// their legacy spellings, holding whatever JSON.
+fn note_body() -> impl Strategy<Value = String> {
+    let key = prop::sample::select(vec![
+        "title",
+        "content",
+        "category",
+        "published",
+        "isPublished",
+        "body",
+        "color",
+        "items",
+        "pinned",
+        "tags",
+    ]);
+    prop::collection::vec((key, json_text()), 0..6).prop_map(|fields| {
+        let fields: Vec<String> = fields
+            .into_iter()
+            .map(|(k, v)| format!("\"{}\":{}", k, v))
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    })
+}
+
+fn assert_answers(request: RequestBuilder) -> Result<(), TestCaseError> {
+    if let Some(response) = send(request) {
+        check(&response).map_err(TestCaseError::fail)?;
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(48))]
+
+    #[test]
+    fn arbitrary_bytes_as_a_new_note(body in prop::collection::vec(any::<u8>(), 0..256)) {
+        assert_answers(json_request("POST", "/api/notes", body))?;
+    }
+
+    #[test]
+    fn note_shaped_json_as_a_new_note(body in note_body()) {
+        assert_answers(json_request("POST", "/api/notes", body))?;
+    }
+
+    #[test]
+    fn note_shaped_json_as_an_edit(body in note_body()) {
+        let path = format!("/api/notes/{}", ObjectId::new().to_hex());
+        assert_answers(json_request("PATCH", &path, body))?;
+    }
+
+    #[test]
+    fn arbitrary_list_query(query in any::<String>()) {
+        let query = utf8_percent_encode(&query, NON_ALPHANUMERIC).to_string();
+        // Keep the separators so keys and values still come apart.
+        let query = query.replace("%3D", "=").replace("%26", "&");
+        assert_answers(request().method("GET").path(&format!("/api/notes?{}", query)))?;
+    }
+
+    #[test]
+    fn arbitrary_note_id(id in any::<String>()) {
+        let path = format!("/api/notes/{}", utf8_percent_encode(&id, NON_ALPHANUMERIC));
+        assert_answers(request().method("GET").path(&path))?;
+    }
+}
+
+#[test]
+fn known_nasty_requests() {
+    let deep = format!("{}{}", "[".repeat(10_000), "]".repeat(10_000));
+    let cases = [
+        (
+            "GET",
+            "/api/notes?page=99999999999999999999999999".to_string(),
+            String::new(),
+        ),
+        (
+            "GET",
+            "/api/notes?limit=18446744073709551616".to_string(),
+            String::new(),
+        ),
+        (
+            "GET",
+            "/api/notes?page=-1&limit=-1".to_string(),
+            String::new(),
+        ),
+        (
+            "GET",
+            "/api/notes?page=1e3&limit=NaN".to_string(),
+            String::new(),
+        ),
+        ("GET", "/api/notes/%00".to_string(), String::new()),
+        ("GET", "/api/notes/%ED%A0%80".to_string(), String::new()),
+        (
+            "POST",
+            "/api/notes".to_string(),
+            r#"{"title":"\ud800","content":"x"}"#.to_string(),
+        ),
+        (
+            "POST",
+            "/api/notes".to_string(),
+            "{\"title\":\"a\\u0000b\",\"content\":\"\\u0000\"}".to_string(),
+        ),
+        (
+            "POST",
+            "/api/notes".to_string(),
+            format!(r#"{{"title":"t","content":{}}}"#, deep),
+        ),
+        (
+            "POST",
+            "/api/notes".to_string(),
+            r#"{"title":"t","content":"c","published":1e400}"#.to_string(),
+        ),
+        ("POST", "/api/notes/bulk".to_string(), deep.clone()),
+        (
+            "PATCH",
+            format!("/api/notes/{}", ObjectId::new()),
+            r#"{"title":null}"#.to_string(),
+        ),
+        ("PATCH", format!("/api/notes/{}", ObjectId::new()), deep),
+    ];
+    for (method, path, body) in cases {
+        let Some(response) = send(json_request(method, &path, body)) else {
+            continue;
+        };
+        if let Err(e) = check(&response) {
+            panic!("{} {}: {}", method, path, e);
+        }
+    }
+}
@@ -1,88 +1,1565 @@
 use crate::{
+    archive::ZipStream,
+    attachments,
+    batch::MAX_BATCH_IDS,
+    bulk::MAX_BULK_NOTES,
+    collation::CollationSpec,
+    color,
+    config::Config,
     db::DB,
-    response::GenericResponse,
-    schema::UpdateNoteSchema,
-    schema::{CreateNoteSchema, FilterOptions},
-    WebResult,
+    error::{
+        Error::{
+            self, BadRequestError, BootstrapError, ExportError, InvalidIDError, InvalidQueryError,
+            PreconditionRequiredError,
+        },
+        ErrorCode,
+    },
+    inbound::{InboundIntegration, InboundRateLimiter},
+    json_patch::PatchOperation,
+    note_export::{self, MAX_EXPORT_IDS},
+    poll::{self, PollGuard, DEFAULT_POLL_TIMEOUT_SECS, MAX_POLL_TIMEOUT_SECS},
+    positions::MoveTarget,
+    query_parser::parse_query,
+    reminders::MAX_UPCOMING_HOURS,
+    replies::{
+        created_json, created_with_location, no_content, not_found_reply, ok_json, pagination_links,
+    },
+    response::SiteExportResponse,
+    response::{AdminConfigResponse, PollData, PollResponse, RecountData, RecountResponse},
+    response::{AttachmentData, AttachmentListResponse, SingleAttachmentResponse},
+    response::{BootstrapResponse, DevEchoData, DevEchoResponse},
+    response::{BulkCreateResponse, BulkCreateResult, BulkDeleteResponse, BulkUpdateResponse},
+    response::{
+        CategoriesResponse, CountResponse, ReminderData, SingleReminderResponse, SuggestResponse,
+    },
+    response::{CommentData, SingleCommentResponse},
+    response::{HealthResponse, InboundNoteData, InboundNoteResponse, PurgeTrashResponse},
+    schema::AddTagSchema,
+    schema::CategoriesOptions,
+    schema::CreateCommentSchema,
+    schema::MoveNoteSchema,
+    schema::PollOptions,
+    schema::RenameCategorySchema,
+    schema::{normalize_legacy_fields, BulkDeleteOptions, BulkUpdateOptions, DeleteNoteOptions},
+    schema::{
+        parse_projection, parse_sort, ArchivedFilter, DeletedFilter, ListExtras, ListOrder,
+        NoteListFilter, NoteSortField, NotesQuery, Page, SearchOptions, SortKey, SuggestOptions,
+    },
+    schema::{ChecklistItemSchema, UpdateChecklistItemSchema},
+    schema::{CreateNoteSchema, FilterOptions, GetNoteOptions, LegacyFields, SaveDraftSchema},
+    schema::{CreateReminderSchema, RenameNoteSchema, UpcomingRemindersOptions},
+    schema::{DevFailOptions, ExportNotesSchema, MergeNoteOptions, MergeNoteSchema, MergeStrategy},
+    schema::{EditNoteOptions, UpdateNoteSchema},
+    schema::{SiteExportFormat, SiteExportOptions},
+    search::{DEFAULT_SUGGESTIONS, MAX_SUGGESTIONS, MIN_SUGGEST_PREFIX_CHARS},
+    site_export::{self, DirWriter, ExportGuard},
+    tags,
+    validation::check_datetime_range,
+    warmup, Result, WebResult,
 };
-use warp::{http::StatusCode, reject, reply::json, reply::with_status, Reply};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use log::error;
+use mongodb::bson::oid::ObjectId;
+use percent_encoding::percent_decode_str;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+use warp::{
+    http::{header, HeaderMap, Method, Response, StatusCode},
+    hyper::body::{Body, Bytes},
+    multipart::FormData,
+    path::FullPath,
+    reject,
+    reply::json,
+    reply::with_status,
+    Reply,
+};
+
+const LOG_TARGET: &str = "api::export";
 
-pub async fn health_checker_handler() -> WebResult<impl Reply> {
+pub async fn health_checker_handler(db: DB) -> WebResult<impl Reply> {
     const MESSAGE: &str = "Build CRUD API with Rust and MongoDB";
 
-    let response_json = &GenericResponse {
-        status: "success".to_string(),
+    let mongodb = db.monitor.health();
+    let ready = warmup::is_ready();
+    let status = if !ready {
+        "starting"
+    } else if mongodb.degraded {
+        "degraded"
+    } else {
+        "success"
+    };
+    let response_json = &HealthResponse {
+        status: status.to_string(),
         message: MESSAGE.to_string(),
+        ready,
+        mongodb,
     };
     Ok(json(response_json))
 }
 
-pub async fn notes_list_handler(opts: FilterOptions, db: DB) -> WebResult<impl Reply> {
-    let limit = opts.limit.unwrap_or(10) as i64;
-    let page = opts.page.unwrap_or(1) as i64;
+/// The collation asked for by `collation`/`strength`, if any.
+fn requested_collation(opts: &FilterOptions, db: &DB) -> Result<Option<CollationSpec>> {
+    match &opts.collation {
+        Some(locale) => CollationSpec::parse(locale, opts.strength).map(Some),
+        None if opts.strength.is_some() => {
+            CollationSpec::parse(&db.default_collation.locale, opts.strength).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+/// The filter parameters shared by the list and count endpoints.
+fn note_list_filter(opts: &FilterOptions, db: &DB) -> Result<NoteListFilter> {
+    let filter = NoteListFilter {
+        title: opts
+            .title
+            .as_deref()
+            .map(|title| db.title_normalizer.normalize(title)),
+        categories: opts.categories.clone(),
+        published: opts.published,
+        search: opts.search.clone().filter(|search| !search.is_empty()),
+        created_from: parse_query_datetime("createdAt_gte", opts.created_at_gte.clone())?,
+        created_to: parse_query_datetime("createdAt_lte", opts.created_at_lte.clone())?,
+        updated_since: parse_query_datetime("updatedSince", opts.updated_since.clone())?,
+        query: opts
+            .q
+            .as_deref()
+            .map(parse_query)
+            .transpose()?
+            .unwrap_or_default(),
+        text: None,
+        ids: Vec::new(),
+        tags: opts
+            .tags
+            .as_deref()
+            .map(tags::parse_list)
+            .unwrap_or_default(),
+        tags_all: opts
+            .tags_all
+            .as_deref()
+            .map(tags::parse_list)
+            .unwrap_or_default(),
+        archived: ArchivedFilter::parse(opts.archived.as_deref())?,
+        pinned: opts.pinned,
+        color: opts
+            .color
+            .as_deref()
+            .map(|color| color::normalize_color("color", color))
+            .transpose()?,
+        deleted: DeletedFilter::Exclude,
+    };
+    if opts.tags.is_some() && opts.tags_all.is_some() {
+        return Err(InvalidQueryError(
+            "Use either tags or tags_all, not both".to_string(),
+        ));
+    }
+    if let (Some(from), Some(to)) = (filter.created_from, filter.created_to) {
+        if from > to {
+            return Err(InvalidQueryError(
+                "createdAt_gte must not be after createdAt_lte".to_string(),
+            ));
+        }
+    }
+    Ok(filter)
+}
+
+pub async fn notes_list_handler(
+    page: Page,
+    opts: FilterOptions,
+    raw_query: String,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let collation = requested_collation(&opts, &db).map_err(reject::custom)?;
+    let mut filter = note_list_filter(&opts, &db).map_err(reject::custom)?;
+    // Sync clients learn about trashed notes from their tombstones.
+    if filter.updated_since.is_some() {
+        filter.deleted = DeletedFilter::Include;
+    }
+    let extras = ListExtras {
+        facets: opts.include_facets.unwrap_or(false),
+        total: opts.include_total.unwrap_or(true),
+    };
+    let order = match (opts.sort, opts.sort_by, opts.after) {
+        (Some(_), Some(_), _) => {
+            return Err(reject::custom(InvalidQueryError(
+                "sort and sort_by cannot be combined".to_string(),
+            )))
+        }
+        (Some(_), _, Some(_)) | (_, Some(_), Some(_)) => {
+            return Err(reject::custom(InvalidQueryError(
+                "after cannot be combined with sort or sort_by".to_string(),
+            )))
+        }
+        (None, None, Some(_)) if opts.page.is_some() => {
+            return Err(reject::custom(InvalidQueryError(
+                "page and after cannot be combined".to_string(),
+            )))
+        }
+        (None, None, Some(after)) => ListOrder::After(
+            ObjectId::from_str(&after).map_err(|_| reject::custom(InvalidIDError(after)))?,
+        ),
+        (Some(sort), None, None) => ListOrder::Sorted(parse_sort(&sort).map_err(reject::custom)?),
+        (None, Some(field), None) => ListOrder::Sorted(vec![SortKey {
+            field,
+            descending: false,
+        }]),
+        // Sync clients page through changes oldest first.
+        (None, None, None) if filter.updated_since.is_some() => ListOrder::Sorted(vec![SortKey {
+            field: NoteSortField::UpdatedAt,
+            descending: false,
+        }]),
+        (None, None, None) => ListOrder::Default,
+    };
+
+    let projection = parse_projection(opts.fields.as_deref(), opts.exclude.as_deref())
+        .map_err(reject::custom)?;
+
+    // Taken before the query, so a note written while it runs is picked up
+    // again by the next sync rather than missed.
+    let server_time = filter.updated_since.map(|_| Utc::now());
+    let query = NotesQuery {
+        filter,
+        order,
+        page,
+        collation,
+        extras,
+        projection,
+    };
+    let mut result_json = db.fetch_notes(&query).await.map_err(reject::custom)?;
+    result_json.server_time = server_time;
+
+    let mut response = ok_json(&result_json);
+    let links = result_json.page_info.as_ref().and_then(|page_info| {
+        match (&query.order, &result_json.next_cursor) {
+            (ListOrder::After(_), None) => None,
+            (ListOrder::After(_), Some(cursor)) => Some(pagination_links(
+                "/api/notes",
+                &raw_query,
+                page_info,
+                Some(cursor),
+            )),
+            _ => Some(pagination_links("/api/notes", &raw_query, page_info, None)),
+        }
+    });
+    if let Some(value) = links.and_then(|links| header::HeaderValue::from_str(&links).ok()) {
+        response.headers_mut().insert(header::LINK, value);
+    }
+    Ok(response)
+}
+
+pub async fn batch_get_notes_handler(ids: Vec<String>, db: DB) -> WebResult<impl Reply> {
+    if ids.is_empty() || ids.len() > MAX_BATCH_IDS {
+        return Err(reject::custom(BadRequestError(format!(
+            "ids must contain between 1 and {} note ids",
+            MAX_BATCH_IDS
+        ))));
+    }
+
+    let response_json = db.notes_by_ids(&ids).await.map_err(reject::custom)?;
+    Ok(json(&response_json))
+}
+
+pub async fn bulk_create_notes_handler(
+    items: Vec<serde_json::Value>,
+    db: DB,
+) -> WebResult<impl Reply> {
+    if items.is_empty() || items.len() > MAX_BULK_NOTES {
+        return Err(reject::custom(BadRequestError(format!(
+            "notes must contain between 1 and {} items",
+            MAX_BULK_NOTES
+        ))));
+    }
+
+    // Items that do not parse are reported on their own and the rest still
+    // go in.
+    let mut results = Vec::new();
+    let mut notes = Vec::new();
+    for (index, mut item) in items.into_iter().enumerate() {
+        let parsed = normalize_legacy_fields(&mut item)
+            .and_then(|_| {
+                serde_json::from_value::<CreateNoteSchema>(item)
+                    .map_err(|e| BadRequestError(e.to_string()))
+            })
+            .and_then(|note| tags::normalize_tags(&note.tags).map(|_| note));
+        match parsed {
+            Ok(note) => notes.push((index, note)),
+            Err(Error::ValidationError(errors)) if !errors.is_empty() => results.push(
+                BulkCreateResult::failed(index, &errors[0].code, errors[0].message.clone()),
+            ),
+            Err(e) => results.push(BulkCreateResult::failed(
+                index,
+                "INVALID_NOTE",
+                e.to_string(),
+            )),
+        }
+    }
+    results.extend(db.create_notes(&notes).await.map_err(reject::custom)?);
+    results.sort_by_key(|result| result.index);
+
+    let created = results.iter().filter(|result| result.id.is_some()).count();
+    let response_json = BulkCreateResponse {
+        status: "success".to_string(),
+        created,
+        failed: results.len() - created,
+        results,
+    };
+    Ok(ok_json(&response_json))
+}
+
+pub async fn bulk_update_notes_handler(
+    body: UpdateNoteSchema,
+    legacy: LegacyFields,
+    opts: FilterOptions,
+    update_opts: BulkUpdateOptions,
+    db: DB,
+) -> WebResult<impl Reply> {
+    body.validate_nulls().map_err(reject::custom)?;
+    check_bulk_update(&body).map_err(reject::custom)?;
+
+    let collation = requested_collation(&opts, &db).map_err(reject::custom)?;
+    let mut filter = note_list_filter(&opts, &db).map_err(reject::custom)?;
+    if let Some(ids) = &update_opts.ids {
+        filter.ids = ids
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(|id| ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned())))
+            .collect::<Result<_>>()
+            .map_err(reject::custom)?;
+    }
+    check_bulk_confirmation(&filter, update_opts.confirm.as_deref(), "update")
+        .map_err(reject::custom)?;
+
+    let (matched_count, modified_count) = db
+        .update_notes(&filter, collation, &body)
+        .await
+        .map_err(reject::custom)?;
+
+    let response_json = BulkUpdateResponse {
+        status: "success".to_string(),
+        matched_count,
+        modified_count,
+    };
+    Ok(with_deprecation(ok_json(&response_json), &legacy))
+}
+
+/// A bulk PATCH may set anything but the unique title, and must set
+/// something.
+fn check_bulk_update(body: &UpdateNoteSchema) -> Result<()> {
+    if body.title.is_some() {
+        return Err(BadRequestError(
+            "Titles are unique and cannot be set on several notes at once".to_string(),
+        ));
+    }
+    if body.content.is_none()
+        && body.category.is_none()
+        && body.published.is_none()
+        && body.color.is_none()
+        && body.items.is_none()
+    {
+        return Err(BadRequestError(
+            "Body must set at least one of content, category, published, color or items"
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Bulk writes with no filter at all only go ahead with `confirm=all`.
+fn check_bulk_confirmation(
+    filter: &NoteListFilter,
+    confirm: Option<&str>,
+    action: &str,
+) -> Result<()> {
+    match confirm {
+        None if filter.is_empty() => Err(BadRequestError(format!(
+            "Refusing to {} every note; pass confirm=all to do so",
+            action
+        ))),
+        Some(confirm) if confirm != "all" => {
+            Err(InvalidQueryError("confirm must be all".to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub async fn bulk_delete_notes_handler(
+    opts: FilterOptions,
+    delete_opts: BulkDeleteOptions,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let collation = requested_collation(&opts, &db).map_err(reject::custom)?;
+    let filter = note_list_filter(&opts, &db).map_err(reject::custom)?;
+    check_bulk_confirmation(&filter, delete_opts.confirm.as_deref(), "delete")
+        .map_err(reject::custom)?;
+
+    let dry_run = delete_opts.dry_run.unwrap_or(false);
+    let deleted_count = if dry_run {
+        db.count_notes(&filter, collation).await
+    } else {
+        db.delete_notes(&filter, collation, delete_opts.permanent.unwrap_or(false))
+            .await
+    }
+    .map_err(reject::custom)?;
+
+    let response_json = BulkDeleteResponse {
+        status: "success".to_string(),
+        deleted_count,
+        dry_run,
+    };
+    Ok(ok_json(&response_json))
+}
+
+pub async fn rename_category_handler(body: RenameCategorySchema, db: DB) -> WebResult<impl Reply> {
+    let (matched_count, modified_count) = db
+        .rename_category(&body.from, &body.to)
+        .await
+        .map_err(reject::custom)?;
+
+    let response_json = BulkUpdateResponse {
+        status: "success".to_string(),
+        matched_count,
+        modified_count,
+    };
+    Ok(ok_json(&response_json))
+}
+
+pub async fn delete_all_notes_handler(confirm: Option<String>, db: DB) -> WebResult<impl Reply> {
+    if confirm.as_deref() != Some("yes") {
+        return Err(reject::custom(PreconditionRequiredError(
+            "Send X-Confirm-Delete: yes to delete every note".to_string(),
+        )));
+    }
 
-    let result_json = db
-        .fetch_notes(limit, page)
+    let deleted_count = db.delete_all_notes().await.map_err(reject::custom)?;
+
+    let response_json = BulkDeleteResponse {
+        status: "success".to_string(),
+        deleted_count,
+        dry_run: false,
+    };
+    Ok(ok_json(&response_json))
+}
+
+pub async fn count_notes_handler(opts: FilterOptions, db: DB) -> WebResult<impl Reply> {
+    let collation = requested_collation(&opts, &db).map_err(reject::custom)?;
+    let filter = note_list_filter(&opts, &db).map_err(reject::custom)?;
+    let count = db
+        .count_notes(&filter, collation)
         .await
-        .map_err(|e| reject::custom(e))?;
+        .map_err(reject::custom)?;
+
+    let response_json = CountResponse {
+        status: "success".to_string(),
+        count,
+    };
+    Ok(json(&response_json))
+}
+
+pub async fn search_notes_handler(
+    page: Page,
+    opts: SearchOptions,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let q = opts.q.as_deref().map(str::trim).unwrap_or_default();
+    if q.is_empty() {
+        return Err(reject::custom(InvalidQueryError(
+            "q must not be empty".to_string(),
+        )));
+    }
+
+    let result_json = db.search_notes(q, &page).await.map_err(reject::custom)?;
 
     Ok(json(&result_json))
 }
 
-pub async fn create_note_handler(body: CreateNoteSchema, db: DB) -> WebResult<impl Reply> {
-    let note = db.create_note(&body).await.map_err(|e| reject::custom(e))?;
+pub async fn trash_notes_handler(page: Page, db: DB) -> WebResult<impl Reply> {
+    let result_json = db.trash_notes(&page).await.map_err(reject::custom)?;
 
-    Ok(with_status(json(&note), StatusCode::CREATED))
+    Ok(ok_json(&result_json))
 }
 
-pub async fn get_note_handler(id: String, db: DB) -> WebResult<impl Reply> {
-    let note = db.get_note(&id).await.map_err(|e| reject::custom(e))?;
+pub async fn empty_trash_handler(db: DB) -> WebResult<impl Reply> {
+    let purged_count = db.purge_trash(None).await.map_err(reject::custom)?;
 
-    let error_response = GenericResponse {
-        status: "fail".to_string(),
-        message: format!("Note with ID: {} not found", id),
+    let response_json = PurgeTrashResponse {
+        status: "success".to_string(),
+        purged_count,
+    };
+    Ok(ok_json(&response_json))
+}
+
+pub async fn suggest_titles_handler(opts: SuggestOptions, db: DB) -> WebResult<impl Reply> {
+    let prefix = opts.prefix.as_deref().map(str::trim).unwrap_or_default();
+    if prefix.chars().count() < MIN_SUGGEST_PREFIX_CHARS {
+        return Err(reject::custom(InvalidQueryError(format!(
+            "prefix must be at least {} characters",
+            MIN_SUGGEST_PREFIX_CHARS
+        ))));
+    }
+    let limit = opts
+        .limit
+        .unwrap_or(DEFAULT_SUGGESTIONS)
+        .clamp(1, MAX_SUGGESTIONS);
+
+    let suggestions = db
+        .suggest_titles(prefix, limit as i64)
+        .await
+        .map_err(reject::custom)?;
+
+    let response_json = SuggestResponse {
+        status: "success".to_string(),
+        results: suggestions.len(),
+        suggestions,
+    };
+    Ok(json(&response_json))
+}
+
+pub async fn list_categories_handler(opts: CategoriesOptions, db: DB) -> WebResult<impl Reply> {
+    let categories = db
+        .list_categories(opts.published)
+        .await
+        .map_err(reject::custom)?;
+
+    let response_json = CategoriesResponse {
+        status: "success".to_string(),
+        results: categories.len(),
+        categories,
     };
+    Ok(json(&response_json))
+}
+
+pub async fn create_note_handler(
+    body: CreateNoteSchema,
+    legacy: LegacyFields,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let note = db.create_note(&body).await.map_err(reject::custom)?;
+
+    let reply = created_with_location(&note, &format!("/api/notes/{}", note.data.note.id));
+    Ok(with_deprecation(reply, &legacy))
+}
+
+/// RFC 6902: on PATCH, the body is a list of operations.
+pub const JSON_PATCH_CONTENT_TYPE: &str = "application/json-patch+json";
+
+/// Whether a Content-Type header names `mime`, ignoring any parameters.
+pub fn has_media_type(content_type: Option<&str>, mime: &str) -> bool {
+    content_type
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|value| value.trim().eq_ignore_ascii_case(mime))
+}
+
+const DEFAULT_BOOTSTRAP_NOTES: &str = include_str!("bootstrap.json");
+
+pub async fn bootstrap_notes_handler(config: Config, db: DB) -> WebResult<impl Reply> {
+    let starters_json = match &config.bootstrap_file {
+        Some(path) => tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| reject::custom(BootstrapError(format!("{}: {}", path, e))))?,
+        None => DEFAULT_BOOTSTRAP_NOTES.to_string(),
+    };
+    let starters: Vec<CreateNoteSchema> = serde_json::from_str(&starters_json)
+        .map_err(|e| reject::custom(BootstrapError(e.to_string())))?;
+
+    let created = db
+        .bootstrap_notes(&starters)
+        .await
+        .map_err(reject::custom)?;
+
+    match created {
+        Some(notes) => Ok(with_status(json(&notes), StatusCode::CREATED)),
+        None => Ok(with_status(
+            json(&BootstrapResponse {
+                status: "success".to_string(),
+                already_bootstrapped: true,
+            }),
+            StatusCode::OK,
+        )),
+    }
+}
+
+pub async fn get_note_handler(id: String, opts: GetNoteOptions, db: DB) -> WebResult<impl Reply> {
+    let include_draft = opts.include_draft.unwrap_or(false);
+    let note = db
+        .get_note(&id, include_draft)
+        .await
+        .map_err(reject::custom)?;
 
     if note.is_none() {
-        return Ok(with_status(json(&error_response), StatusCode::NOT_FOUND));
+        return Ok(not_found_reply("Note", &id));
     }
 
-    Ok(with_status(json(&note), StatusCode::OK))
+    Ok(ok_json(&note))
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn edit_note_handler(
     id: String,
     body: UpdateNoteSchema,
+    legacy: LegacyFields,
+    opts: EditNoteOptions,
+    if_unmodified_since: Option<String>,
+    config: Config,
+    db: DB,
+) -> WebResult<impl Reply> {
+    body.validate_nulls().map_err(reject::custom)?;
+    // Nothing to write; refusing beats silently touching `updatedAt`.
+    if body.is_empty() {
+        return Err(reject::custom(BadRequestError(
+            "Body must set at least one of title, content, category, published, color or items"
+                .to_string(),
+        )));
+    }
+    if config.reject_title_patch && body.title.is_some() {
+        return Err(reject::custom(BadRequestError(format!(
+            "Use POST /api/notes/{}/rename to change a title",
+            id
+        ))));
+    }
+    let not_modified_since =
+        parse_http_date("If-Unmodified-Since", if_unmodified_since).map_err(reject::custom)?;
+    let mut note = db
+        .edit_note(&id, &body, not_modified_since)
+        .await
+        .map_err(reject::custom)?;
+    if note.is_some() && body.title.is_some() && opts.regenerate_slug.unwrap_or(false) {
+        note = db.regenerate_slug(&id).await.map_err(reject::custom)?;
+    }
+
+    if note.is_none() {
+        return Ok(with_deprecation(not_found_reply("Note", &id), &legacy));
+    }
+
+    Ok(with_deprecation(ok_json(&note), &legacy))
+}
+
+pub async fn json_patch_note_handler(
+    id: String,
+    operations: Vec<PatchOperation>,
+    if_unmodified_since: Option<String>,
+    config: Config,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let not_modified_since =
+        parse_http_date("If-Unmodified-Since", if_unmodified_since).map_err(reject::custom)?;
+    let note = db
+        .json_patch_note(
+            &id,
+            &operations,
+            not_modified_since,
+            config.reject_title_patch,
+        )
+        .await
+        .map_err(reject::custom)?;
+
+    if note.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(ok_json(&note))
+}
+
+pub async fn replace_note_handler(
+    id: String,
+    body: CreateNoteSchema,
+    legacy: LegacyFields,
+    if_unmodified_since: Option<String>,
+    config: Config,
     db: DB,
 ) -> WebResult<impl Reply> {
+    let not_modified_since =
+        parse_http_date("If-Unmodified-Since", if_unmodified_since).map_err(reject::custom)?;
     let note = db
-        .edit_note(&id, &body)
+        .replace_note(&id, &body, not_modified_since, config.reject_title_patch)
         .await
-        .map_err(|e| reject::custom(e))?;
+        .map_err(reject::custom)?;
 
-    let error_response = GenericResponse {
-        status: "fail".to_string(),
-        message: format!("Note with ID: {} not found", id),
+    let reply = match note {
+        None => not_found_reply("Note", &id),
+        Some((note, true)) => created_with_location(&note, &format!("/api/notes/{}", id)),
+        Some((note, false)) => ok_json(&note),
     };
+    Ok(with_deprecation(reply, &legacy))
+}
+
+pub async fn delete_note_handler(
+    id: String,
+    opts: DeleteNoteOptions,
+    if_unmodified_since: Option<String>,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let not_modified_since =
+        parse_http_date("If-Unmodified-Since", if_unmodified_since).map_err(reject::custom)?;
+    let result = db
+        .delete_note(&id, not_modified_since, opts.permanent.unwrap_or(false))
+        .await
+        .map_err(reject::custom)?;
+
+    if result.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(no_content())
+}
+
+pub async fn restore_note_handler(id: String, db: DB) -> WebResult<impl Reply> {
+    let note = db.restore_note(&id).await.map_err(reject::custom)?;
 
     if note.is_none() {
-        return Ok(with_status(json(&error_response), StatusCode::NOT_FOUND));
+        return Ok(not_found_reply("Note", &id));
     }
 
-    Ok(with_status(json(&note), StatusCode::OK))
+    Ok(ok_json(&note))
 }
 
-pub async fn delete_note_handler(id: String, db: DB) -> WebResult<impl Reply> {
-    let result = db.delete_note(&id).await.map_err(|e| reject::custom(e))?;
+pub async fn rename_note_handler(
+    id: String,
+    body: RenameNoteSchema,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let result = db
+        .rename_note(&id, &body.title)
+        .await
+        .map_err(reject::custom)?;
+
+    if result.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(ok_json(&result))
+}
 
-    let error_response = GenericResponse {
-        status: "fail".to_string(),
-        message: format!("Note with ID: {} not found", id),
+pub async fn duplicate_note_handler(id: String, db: DB) -> WebResult<impl Reply> {
+    let note = db.duplicate_note(&id).await.map_err(reject::custom)?;
+
+    match &note {
+        Some(copy) => Ok(created_with_location(
+            &note,
+            &format!("/api/notes/{}", copy.data.note.id),
+        )),
+        None => Ok(not_found_reply("Note", &id)),
+    }
+}
+
+pub async fn publish_note_handler(id: String, db: DB) -> WebResult<impl Reply> {
+    set_published_reply(id, true, db).await
+}
+
+pub async fn unpublish_note_handler(id: String, db: DB) -> WebResult<impl Reply> {
+    set_published_reply(id, false, db).await
+}
+
+async fn set_published_reply(id: String, published: bool, db: DB) -> WebResult<impl Reply> {
+    let note = db
+        .set_published(&id, published)
+        .await
+        .map_err(reject::custom)?;
+
+    if note.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(ok_json(&note))
+}
+
+pub async fn archive_note_handler(id: String, db: DB) -> WebResult<impl Reply> {
+    set_archived_reply(id, true, db).await
+}
+
+pub async fn unarchive_note_handler(id: String, db: DB) -> WebResult<impl Reply> {
+    set_archived_reply(id, false, db).await
+}
+
+pub async fn add_tag_handler(id: String, body: AddTagSchema, db: DB) -> WebResult<impl Reply> {
+    let note = db.add_tag(&id, &body.tag).await.map_err(reject::custom)?;
+
+    if note.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(ok_json(&note))
+}
+
+pub async fn add_checklist_item_handler(
+    id: String,
+    body: ChecklistItemSchema,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let note = db
+        .add_checklist_item(&id, &body)
+        .await
+        .map_err(reject::custom)?;
+
+    if note.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(ok_json(&note))
+}
+
+pub async fn update_checklist_item_handler(
+    id: String,
+    index: usize,
+    body: UpdateChecklistItemSchema,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let note = db
+        .update_checklist_item(&id, index, &body)
+        .await
+        .map_err(reject::custom)?;
+
+    if note.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(ok_json(&note))
+}
+
+pub async fn remove_checklist_item_handler(
+    id: String,
+    index: usize,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let note = db
+        .remove_checklist_item(&id, index)
+        .await
+        .map_err(reject::custom)?;
+
+    if note.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(ok_json(&note))
+}
+
+pub async fn remove_tag_handler(id: String, tag: String, db: DB) -> WebResult<impl Reply> {
+    let tag = percent_decode_str(&tag)
+        .decode_utf8()
+        .map_err(|_| reject::custom(BadRequestError("Tag is not valid UTF-8".to_string())))?;
+    let note = db.remove_tag(&id, &tag).await.map_err(reject::custom)?;
+
+    if note.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(ok_json(&note))
+}
+
+pub async fn move_note_handler(id: String, body: MoveNoteSchema, db: DB) -> WebResult<impl Reply> {
+    let parse = |anchor: &str| {
+        ObjectId::from_str(anchor).map_err(|_| reject::custom(InvalidIDError(anchor.to_owned())))
+    };
+    let target = match (&body.before, &body.after) {
+        (Some(before), None) => MoveTarget::Before(parse(before)?),
+        (None, Some(after)) => MoveTarget::After(parse(after)?),
+        _ => {
+            return Err(reject::custom(BadRequestError(
+                "Body must set exactly one of before or after".to_string(),
+            )))
+        }
     };
+    let note = db.move_note(&id, target).await.map_err(reject::custom)?;
+
+    if note.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(ok_json(&note))
+}
+
+pub async fn pin_note_handler(id: String, db: DB) -> WebResult<impl Reply> {
+    set_pinned_reply(id, true, db).await
+}
+
+pub async fn unpin_note_handler(id: String, db: DB) -> WebResult<impl Reply> {
+    set_pinned_reply(id, false, db).await
+}
+
+async fn set_pinned_reply(id: String, pinned: bool, db: DB) -> WebResult<impl Reply> {
+    let note = db.set_pinned(&id, pinned).await.map_err(reject::custom)?;
+
+    if note.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(ok_json(&note))
+}
+
+async fn set_archived_reply(id: String, archived: bool, db: DB) -> WebResult<impl Reply> {
+    let note = db
+        .set_archived(&id, archived)
+        .await
+        .map_err(reject::custom)?;
+
+    if note.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(ok_json(&note))
+}
+
+pub async fn save_draft_handler(
+    id: String,
+    body: SaveDraftSchema,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let draft = db
+        .save_draft(&id, &body.content)
+        .await
+        .map_err(reject::custom)?;
+
+    if draft.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(ok_json(&draft))
+}
+
+pub async fn commit_draft_handler(id: String, db: DB) -> WebResult<impl Reply> {
+    let note = db.commit_draft(&id).await.map_err(reject::custom)?;
+
+    if note.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(ok_json(&note))
+}
+
+pub async fn discard_draft_handler(id: String, db: DB) -> WebResult<impl Reply> {
+    let result = db.discard_draft(&id).await.map_err(reject::custom)?;
 
     if result.is_none() {
-        return Ok(with_status(json(&error_response), StatusCode::NOT_FOUND));
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(no_content())
+}
+
+pub async fn merge_note_handler(
+    target_id: String,
+    opts: MergeNoteOptions,
+    body: MergeNoteSchema,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let strategy = body.strategy.unwrap_or(MergeStrategy::Append);
+    let result = db
+        .merge_notes(&target_id, &body.source_id, strategy, opts.source_fate())
+        .await
+        .map_err(reject::custom)?;
+
+    if result.is_none() {
+        let ids = format!("{} or {}", target_id, body.source_id);
+        return Ok(not_found_reply("Note", &ids));
+    }
+
+    Ok(ok_json(&result))
+}
+
+pub async fn export_site_handler(
+    opts: SiteExportOptions,
+    config: Config,
+    db: DB,
+) -> WebResult<Box<dyn Reply>> {
+    let guard = ExportGuard::acquire().map_err(reject::custom)?;
+
+    if opts.format == Some(SiteExportFormat::Zip) {
+        let (mut zip, body) = ZipStream::new();
+        tokio::task::spawn_blocking(move || {
+            let _guard = guard;
+            let result = site_export::export(&db, &mut zip).and_then(|_| {
+                zip.finish()
+                    .map_err(|e| ExportError(format!("could not finish zip: {}", e)))
+            });
+            if let Err(e) = result {
+                error!(target: LOG_TARGET, "Site export aborted: {:?}", e);
+            }
+        });
+
+        let filename = format!("site-{}.zip", Utc::now().format("%Y%m%d%H%M%S"));
+        let response = Response::builder()
+            .header(header::CONTENT_TYPE, "application/zip")
+            .header(
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            )
+            .body(body)
+            .map_err(|e| reject::custom(ExportError(e.to_string())))?;
+        return Ok(Box::new(response));
+    }
+
+    let report = tokio::task::spawn_blocking(move || {
+        let _guard = guard;
+        site_export::export(&db, &mut DirWriter::new(&config.export_dir))
+    })
+    .await
+    .map_err(|e| reject::custom(ExportError(e.to_string())))?
+    .map_err(reject::custom)?;
+
+    let response_json = SiteExportResponse {
+        status: "success".to_string(),
+        data: report,
+    };
+    Ok(Box::new(json(&response_json)))
+}
+
+pub async fn export_notes_ndjson_handler(db: DB) -> WebResult<impl Reply> {
+    let lines = db.stream_notes().map(|note| {
+        let mut line = serde_json::to_vec(&note?).map_err(|e| ExportError(e.to_string()))?;
+        line.push(b'\n');
+        Ok::<_, Error>(Bytes::from(line))
+    });
+    // The status is already sent by the time an error turns up, so the body
+    // just ends after the last complete line.
+    let body = Body::wrap_stream(lines.take_while(|line| {
+        if let Err(e) = line {
+            error!(target: LOG_TARGET, "NDJSON export aborted: {:?}", e);
+        }
+        futures::future::ready(line.is_ok())
+    }));
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .body(body)
+        .map_err(|e| reject::custom(ExportError(e.to_string())))
+}
+
+pub async fn export_notes_zip_handler(
+    body: ExportNotesSchema,
+    db: DB,
+) -> WebResult<Box<dyn Reply>> {
+    if body.ids.is_empty() || body.ids.len() > MAX_EXPORT_IDS {
+        return Err(reject::custom(BadRequestError(format!(
+            "ids must contain between 1 and {} note ids",
+            MAX_EXPORT_IDS
+        ))));
+    }
+
+    let (mut zip, zip_body) = ZipStream::new();
+    tokio::task::spawn_blocking(move || {
+        let result = note_export::export(&db, &body.ids, &mut zip).and_then(|_| {
+            zip.finish()
+                .map_err(|e| ExportError(format!("could not finish zip: {}", e)))
+        });
+        if let Err(e) = result {
+            error!(target: LOG_TARGET, "Note export aborted: {:?}", e);
+        }
+    });
+
+    let filename = format!("notes-{}.zip", Utc::now().format("%Y%m%d%H%M%S"));
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .body(zip_body)
+        .map_err(|e| reject::custom(ExportError(e.to_string())))?;
+    Ok(Box::new(response))
+}
+
+pub async fn inbound_note_handler(
+    integration_key: String,
+    payload: serde_json::Value,
+    config: Config,
+    limiter: InboundRateLimiter,
+    db: DB,
+) -> WebResult<impl Reply> {
+    // Unknown keys look exactly like a missing route.
+    let integration = InboundIntegration::find(&config.inbound_integrations, &integration_key)
+        .ok_or_else(reject::not_found)?;
+    limiter.check(integration).map_err(reject::custom)?;
+
+    let body = integration.map_payload(&payload).map_err(reject::custom)?;
+    let note = db.create_note(&body).await.map_err(reject::custom)?;
+
+    let response_json = InboundNoteResponse {
+        status: "accepted".to_string(),
+        data: InboundNoteData {
+            id: note.data.note.id,
+        },
+    };
+    Ok(with_status(json(&response_json), StatusCode::ACCEPTED))
+}
+
+pub async fn poll_notes_handler(opts: PollOptions, db: DB) -> WebResult<Box<dyn Reply>> {
+    let _guard = PollGuard::acquire().map_err(reject::custom)?;
+    let since = opts.since_seq.unwrap_or(0);
+    let timeout = opts
+        .timeout_secs
+        .unwrap_or(DEFAULT_POLL_TIMEOUT_SECS)
+        .min(MAX_POLL_TIMEOUT_SECS);
+
+    let result = poll::wait_for_events(&db.events, since, Duration::from_secs(timeout)).await;
+
+    if result.events.is_empty() && result.complete {
+        let reply =
+            warp::reply::with_header(StatusCode::NO_CONTENT, "x-last-seq", since.to_string());
+        return Ok(Box::new(reply));
+    }
+
+    let last_seq = result
+        .events
+        .last()
+        .map_or(db.events.last_seq(), |envelope| envelope.global_seq);
+    let response_json = PollResponse {
+        status: "success".to_string(),
+        data: PollData {
+            events: result.events,
+            last_seq,
+            complete: result.complete,
+        },
+    };
+    Ok(Box::new(json(&response_json)))
+}
+
+pub async fn recount_notes_handler(db: DB) -> WebResult<impl Reply> {
+    let total = db.reconcile_note_count().await.map_err(reject::custom)?;
+
+    let response_json = RecountResponse {
+        status: "success".to_string(),
+        data: RecountData { total },
+    };
+    Ok(json(&response_json))
+}
+
+pub async fn create_reminder_handler(
+    id: String,
+    body: CreateReminderSchema,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let reminder = db
+        .create_reminder(&id, &body)
+        .await
+        .map_err(reject::custom)?;
+
+    let Some(reminder) = reminder else {
+        return Ok(not_found_reply("Note", &id));
+    };
+
+    let response_json = SingleReminderResponse {
+        status: "success".to_string(),
+        data: ReminderData { reminder },
+    };
+    Ok(created_json(&response_json))
+}
+
+pub async fn note_reminders_handler(id: String, page: Page, db: DB) -> WebResult<impl Reply> {
+    let result = db
+        .list_note_reminders(&id, &page)
+        .await
+        .map_err(reject::custom)?;
+
+    Ok(json(&result))
+}
+
+pub async fn upload_attachment_handler(
+    id: String,
+    mut form: FormData,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let part = loop {
+        match form.next().await {
+            Some(Ok(part)) if part.name() == "file" => break part,
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => {
+                return Err(reject::custom(BadRequestError(format!(
+                    "Invalid multipart body: {}",
+                    e
+                ))))
+            }
+            None => {
+                return Err(reject::custom(BadRequestError(
+                    "Multipart body must have a file part".to_string(),
+                )))
+            }
+        }
+    };
+    let filename = part.filename().map(str::to_owned);
+    let content_type = part.content_type().map(str::to_owned);
+    let attachment = db
+        .upload_attachment(
+            &id,
+            filename.as_deref(),
+            content_type.as_deref(),
+            part.stream(),
+        )
+        .await
+        .map_err(reject::custom)?;
+
+    let Some(attachment) = attachment else {
+        return Ok(not_found_reply("Note", &id));
+    };
+
+    let response_json = SingleAttachmentResponse {
+        status: "success".to_string(),
+        data: AttachmentData { attachment },
+    };
+    Ok(created_json(&response_json))
+}
+
+pub async fn note_attachments_handler(id: String, db: DB) -> WebResult<impl Reply> {
+    let attachments = db.list_attachments(&id).await.map_err(reject::custom)?;
+
+    let Some(attachments) = attachments else {
+        return Ok(not_found_reply("Note", &id));
+    };
+
+    let response_json = AttachmentListResponse {
+        status: "success".to_string(),
+        results: attachments.len(),
+        attachments,
+    };
+    Ok(ok_json(&response_json))
+}
+
+pub async fn download_attachment_handler(file_id: String, db: DB) -> WebResult<Box<dyn Reply>> {
+    let attachment = db.open_attachment(&file_id).await.map_err(reject::custom)?;
+
+    let Some((attachment, stream)) = attachment else {
+        return Ok(Box::new(not_found_reply("Attachment", &file_id)));
+    };
+
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, &attachment.contentType)
+        .header(header::CONTENT_LENGTH, attachment.size)
+        .header(
+            header::CONTENT_DISPOSITION,
+            attachments::content_disposition(&attachment.filename),
+        )
+        .body(Body::wrap_stream(attachments::download_body(stream)))
+        .map_err(|e| reject::custom(BadRequestError(e.to_string())))?;
+    Ok(Box::new(response))
+}
+
+pub async fn create_comment_handler(
+    id: String,
+    body: CreateCommentSchema,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let comment = db
+        .create_comment(&id, &body)
+        .await
+        .map_err(reject::custom)?;
+
+    let Some(comment) = comment else {
+        return Ok(not_found_reply("Note", &id));
+    };
+
+    let response_json = SingleCommentResponse {
+        status: "success".to_string(),
+        data: CommentData { comment },
+    };
+    Ok(created_json(&response_json))
+}
+
+pub async fn note_comments_handler(id: String, page: Page, db: DB) -> WebResult<impl Reply> {
+    let result = db
+        .list_note_comments(&id, &page)
+        .await
+        .map_err(reject::custom)?;
+
+    Ok(json(&result))
+}
+
+pub async fn delete_comment_handler(
+    id: String,
+    comment_id: String,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let deleted = db
+        .delete_comment(&id, &comment_id)
+        .await
+        .map_err(reject::custom)?;
+
+    if deleted.is_none() {
+        return Ok(not_found_reply("Comment", &comment_id));
     }
 
-    Ok(with_status(json(&""), StatusCode::NO_CONTENT))
+    Ok(no_content())
+}
+
+pub async fn note_revisions_handler(id: String, page: Page, db: DB) -> WebResult<impl Reply> {
+    let result = db
+        .list_note_revisions(&id, &page)
+        .await
+        .map_err(reject::custom)?;
+
+    Ok(json(&result))
+}
+
+pub async fn restore_revision_handler(id: String, revision: i64, db: DB) -> WebResult<impl Reply> {
+    let note = db
+        .restore_revision(&id, revision)
+        .await
+        .map_err(reject::custom)?;
+
+    if note.is_none() {
+        return Ok(not_found_reply("Note", &id));
+    }
+
+    Ok(ok_json(&note))
+}
+
+pub async fn cancel_reminder_handler(
+    id: String,
+    reminder_id: String,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let reminder = db
+        .cancel_reminder(&id, &reminder_id)
+        .await
+        .map_err(reject::custom)?;
+
+    let Some(reminder) = reminder else {
+        return Ok(not_found_reply("Reminder", &reminder_id));
+    };
+
+    let response_json = SingleReminderResponse {
+        status: "success".to_string(),
+        data: ReminderData { reminder },
+    };
+    Ok(ok_json(&response_json))
+}
+
+pub async fn upcoming_reminders_handler(
+    page: Page,
+    opts: UpcomingRemindersOptions,
+    db: DB,
+) -> WebResult<impl Reply> {
+    let within_hours = opts.within_hours.unwrap_or(24);
+    if within_hours == 0 || within_hours > MAX_UPCOMING_HOURS {
+        return Err(reject::custom(BadRequestError(format!(
+            "within_hours must be between 1 and {}",
+            MAX_UPCOMING_HOURS
+        ))));
+    }
+
+    let result = db
+        .upcoming_reminders(within_hours, &page)
+        .await
+        .map_err(reject::custom)?;
+
+    Ok(json(&result))
+}
+
+pub async fn admin_config_handler(config: Config) -> WebResult<impl Reply> {
+    let response_json = AdminConfigResponse {
+        status: "success".to_string(),
+        data: config.effective(),
+    };
+    Ok(json(&response_json))
+}
+
+const REDACTED_HEADERS: &[&str] = &["authorization", "cookie", "x-admin-token", "x-api-key"];
+const DEV_FAIL_MAX_DELAY_MS: u64 = 30_000;
+
+pub async fn dev_echo_handler(
+    method: Method,
+    path: FullPath,
+    query: String,
+    client_ip: Option<IpAddr>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> WebResult<impl Reply> {
+    let headers = headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if REDACTED_HEADERS.contains(&name.as_str()) {
+                "[redacted]".to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let body = if body.is_empty() {
+        serde_json::Value::Null
+    } else {
+        serde_json::from_slice(&body).unwrap_or_else(|_| {
+            serde_json::Value::String(String::from_utf8_lossy(&body).into_owned())
+        })
+    };
+
+    let response_json = DevEchoResponse {
+        status: "success".to_string(),
+        data: DevEchoData {
+            method: method.to_string(),
+            path: path.as_str().to_string(),
+            query,
+            client_ip: client_ip.map(|ip| ip.to_string()),
+            headers,
+            body,
+        },
+    };
+    Ok(json(&response_json))
+}
+
+/// Always a rejection, rendered by `handle_rejection` like a real one.
+pub async fn dev_fail_handler(opts: DevFailOptions) -> WebResult<std::convert::Infallible> {
+    let code = match StatusCode::from_u16(opts.status.unwrap_or(500)) {
+        Ok(code) if code.is_client_error() || code.is_server_error() => code,
+        _ => {
+            return Err(reject::custom(BadRequestError(
+                "status must be between 400 and 599".to_string(),
+            )))
+        }
+    };
+    let delay = opts.delay_ms.unwrap_or(0).min(DEV_FAIL_MAX_DELAY_MS);
+    tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+
+    Err(reject::custom(Error::SimulatedError {
+        status: code,
+        code: opts.code.unwrap_or(ErrorCode::SimulatedFailure),
+    }))
+}
+
+/// Lists the legacy field names a request used so stragglers can be tracked.
+fn with_deprecation(reply: impl Reply, legacy: &LegacyFields) -> warp::reply::Response {
+    let mut response = reply.into_response();
+    if !legacy.0.is_empty() {
+        if let Ok(value) = header::HeaderValue::from_str(&legacy.0.join(", ")) {
+            response.headers_mut().insert("deprecation", value);
+        }
+    }
+    response
+}
+
+/// Unlike HTTP-date headers, a malformed date query parameter is an error
+/// naming the parameter.
+fn parse_query_datetime(name: &str, value: Option<String>) -> Result<Option<DateTime<Utc>>> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let date = DateTime::parse_from_rfc3339(&value)
+        .map_err(|_| InvalidQueryError(format!("{} must be an RFC 3339 timestamp", name)))?;
+    check_datetime_range(name, date.with_timezone(&Utc)).map(Some)
+}
+
+/// Malformed HTTP-dates are ignored, as if the header had not been sent;
+/// well-formed but absurd ones are rejected.
+fn parse_http_date(name: &str, value: Option<String>) -> Result<Option<DateTime<Utc>>> {
+    match value.and_then(|v| DateTime::parse_from_rfc2822(&v).ok()) {
+        Some(date) => check_datetime_range(name, date.with_timezone(&Utc)).map(Some),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::list_filter;
+    use crate::test_support::{config, offline_db};
+    use chrono::TimeZone;
+    use mongodb::bson::doc;
+
+    fn filter_for(query: &str) -> Result<NoteListFilter> {
+        let config = config();
+        note_list_filter(&FilterOptions::from_query(query)?, &offline_db(&config))
+    }
+
+    fn query_error(query: &str) -> String {
+        match filter_for(query) {
+            Err(InvalidQueryError(message)) => message,
+            other => panic!("{} was accepted: {:?}", query, other.map(|_| ())),
+        }
+    }
+
+    fn day(month: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, month, 1, 0, 0, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn created_range_is_inclusive_on_both_ends() {
+        let filter =
+            filter_for("createdAt_gte=2024-01-01T00:00:00Z&createdAt_lte=2024-02-01T00:00:00Z")
+                .unwrap();
+        assert_eq!(
+            list_filter(&filter, None)
+                .get_document("createdAt")
+                .unwrap(),
+            &doc! {"$gte": day(1), "$lte": day(2)}
+        );
+    }
+
+    #[tokio::test]
+    async fn either_bound_may_come_alone() {
+        let filter = filter_for("createdAt_gte=2024-01-01T00:00:00Z").unwrap();
+        assert_eq!(
+            list_filter(&filter, None)
+                .get_document("createdAt")
+                .unwrap(),
+            &doc! {"$gte": day(1)}
+        );
+        // Offsets are converted to UTC.
+        let filter = filter_for("createdAt_lte=2024-02-01T01:00:00%2B01:00").unwrap();
+        assert_eq!(
+            list_filter(&filter, None)
+                .get_document("createdAt")
+                .unwrap(),
+            &doc! {"$lte": day(2)}
+        );
+        assert!(!list_filter(&filter_for("").unwrap(), None).contains_key("createdAt"));
+    }
+
+    #[tokio::test]
+    async fn bad_bounds_name_the_parameter() {
+        assert_eq!(
+            query_error("createdAt_gte=yesterday"),
+            "createdAt_gte must be an RFC 3339 timestamp"
+        );
+        assert_eq!(
+            query_error("createdAt_lte=2024-02-01"),
+            "createdAt_lte must be an RFC 3339 timestamp"
+        );
+        assert_eq!(
+            query_error("createdAt_gte=2024-02-01T00:00:00Z&createdAt_lte=2024-01-01T00:00:00Z"),
+            "createdAt_gte must not be after createdAt_lte"
+        );
+    }
+
+    #[tokio::test]
+    async fn tags_match_any_and_tags_all_match_every_one() {
+        let any = list_filter(&filter_for("tags=Rust,mongo&category=work").unwrap(), None);
+        assert_eq!(
+            any.get_document("tags").unwrap(),
+            &doc! {"$in": ["rust", "mongo"]}
+        );
+        assert_eq!(any.get_str("category").unwrap(), "work");
+
+        let all = list_filter(&filter_for("tags_all=rust,mongo").unwrap(), None);
+        assert_eq!(
+            all.get_document("tags").unwrap(),
+            &doc! {"$all": ["rust", "mongo"]}
+        );
+
+        assert_eq!(
+            query_error("tags=rust&tags_all=mongo"),
+            "Use either tags or tags_all, not both"
+        );
+    }
+
+    #[test]
+    fn bulk_updates_may_set_only_checklist_items() {
+        let body =
+            |json: serde_json::Value| -> UpdateNoteSchema { serde_json::from_value(json).unwrap() };
+        check_bulk_update(&body(serde_json::json!({"items": [{"text": "Milk"}]}))).unwrap();
+        check_bulk_update(&body(serde_json::json!({"color": null}))).unwrap();
+
+        match check_bulk_update(&body(serde_json::json!({}))) {
+            Err(BadRequestError(message)) => assert_eq!(
+                message,
+                "Body must set at least one of content, category, published, color or items"
+            ),
+            other => panic!("an empty bulk update was accepted: {:?}", other),
+        }
+        assert!(matches!(
+            check_bulk_update(&body(serde_json::json!({"title": "Same"}))),
+            Err(BadRequestError(_))
+        ));
+    }
 }
@@ -0,0 +1,135 @@
+use crate::response::FieldError;
+use crate::secret::Secret;
+use crate::{config::constant_time_eq, error::Error::*, schema::CreateNoteSchema, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Inbound payloads larger than this are refused with 413.
+pub const MAX_INBOUND_BODY_BYTES: u64 = 64 * 1024;
+
+/// Where one note field comes from in an inbound payload: a JSON pointer
+/// (RFC 6901, e.g. `/message/subject`) and the value to use when the
+/// pointer does not resolve.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FieldMapping {
+    pub pointer: String,
+    pub default: Option<String>,
+}
+
+/// One configured integration, e.g. a Zapier zap forwarding emails. The
+/// `key` is the secret last segment of `/api/inbound/:integration_key`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InboundIntegration {
+    pub name: String,
+    pub key: Secret<String>,
+    pub title: FieldMapping,
+    pub content: FieldMapping,
+    pub category: Option<FieldMapping>,
+    pub rate_limit_per_minute: Option<u32>,
+}
+
+impl InboundIntegration {
+    /// Reads the integrations list from a JSON file; panics on a bad file
+    /// like the rest of startup configuration.
+    pub fn load_all(path: &str) -> Vec<Self> {
+        let contents = std::fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Could not read {}: {}", path, e));
+        serde_json::from_str(&contents)
+            .unwrap_or_else(|e| panic!("Could not parse {}: {}", path, e))
+    }
+
+    /// Compares against every key so the response time does not reveal
+    /// how much of a guessed key was right.
+    pub fn find<'a>(integrations: &'a [Self], key: &str) -> Option<&'a Self> {
+        integrations.iter().fold(None, |found, integration| {
+            if constant_time_eq(integration.key.expose(), key) {
+                Some(integration)
+            } else {
+                found
+            }
+        })
+    }
+
+    pub fn map_payload(&self, payload: &Value) -> Result<CreateNoteSchema> {
+        let mut errors = Vec::new();
+        let title = extract(payload, "title", &self.title, &mut errors);
+        let content = extract(payload, "content", &self.content, &mut errors);
+        let category = self
+            .category
+            .as_ref()
+            .and_then(|mapping| extract(payload, "category", mapping, &mut errors));
+
+        match (title, content) {
+            (Some(title), Some(content)) if errors.is_empty() => Ok(CreateNoteSchema {
+                title,
+                content,
+                category,
+                published: None,
+                color: None,
+                tags: Vec::new(),
+                items: Vec::new(),
+            }),
+            _ => Err(ValidationError(errors)),
+        }
+    }
+}
+
+fn extract(
+    payload: &Value,
+    field: &str,
+    mapping: &FieldMapping,
+    errors: &mut Vec<FieldError>,
+) -> Option<String> {
+    let value = match payload.pointer(&mapping.pointer) {
+        Some(Value::String(s)) if !s.trim().is_empty() => Some(s.to_owned()),
+        Some(Value::Number(n)) => Some(n.to_string()),
+        Some(Value::Bool(b)) => Some(b.to_string()),
+        _ => None,
+    };
+
+    let value = value.or_else(|| mapping.default.clone());
+    if value.is_none() {
+        errors.push(FieldError {
+            field: field.to_owned(),
+            code: "POINTER_UNRESOLVED".to_string(),
+            message: format!(
+                "{} did not resolve to a non-empty string, number or boolean",
+                mapping.pointer
+            ),
+        });
+    }
+    value
+}
+
+/// Fixed one-minute windows per integration name.
+#[derive(Clone, Debug, Default)]
+pub struct InboundRateLimiter {
+    windows: Arc<Mutex<HashMap<String, (Instant, u32)>>>,
+}
+
+impl InboundRateLimiter {
+    pub fn check(&self, integration: &InboundIntegration) -> Result<()> {
+        let Some(limit) = integration.rate_limit_per_minute else {
+            return Ok(());
+        };
+
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        let (started, count) = windows.entry(integration.name.clone()).or_insert((now, 0));
+        if now.duration_since(*started) >= Duration::from_secs(60) {
+            *started = now;
+            *count = 0;
+        }
+        if *count >= limit {
+            return Err(RateLimitedError(format!(
+                "Integration '{}' is limited to {} requests per minute",
+                integration.name, limit
+            )));
+        }
+        *count += 1;
+        Ok(())
+    }
+}
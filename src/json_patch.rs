@@ -0,0 +1,164 @@
+use crate::{
+    db::{live_note, DB},
+    error::{Error, Error::*},
+    model::NoteModel,
+    response::{FieldError, SingleNoteResponse},
+    schema::{nullable, CreateNoteSchema, UpdateNoteSchema},
+    Result,
+};
+use chrono::{DateTime, Utc};
+use mongodb::bson::{doc, oid::ObjectId};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::str::FromStr;
+
+/// Fields a JSON Patch may touch, as `/name` paths.
+const PATCHABLE_FIELDS: &[&str] = &["title", "content", "category", "published", "color"];
+
+/// One RFC 6902 operation. Kept loose so an unsupported `op` gets its own
+/// error instead of a generic body parse failure.
+#[derive(Deserialize, Debug)]
+pub struct PatchOperation {
+    pub op: String,
+    pub path: String,
+    /// `Some(None)` for an explicit `"value": null`.
+    #[serde(default, deserialize_with = "nullable")]
+    pub value: Option<Option<Value>>,
+}
+
+impl DB {
+    /// Applies `add`/`remove`/`replace`/`test` operations to the stored note
+    /// and writes whichever fields came out different through `edit_note`.
+    pub async fn json_patch_note(
+        &self,
+        id: &str,
+        operations: &[PatchOperation],
+        not_modified_since: Option<DateTime<Utc>>,
+        keep_title: bool,
+    ) -> Result<Option<SingleNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+        let Some(note) = self
+            .note_collection
+            .find_one(live_note(oid), None)
+            .await
+            .map_err(MongoQueryError)?
+        else {
+            return Ok(None);
+        };
+
+        let update = apply_patch(&note, operations)?;
+        if keep_title && update.title.is_some() {
+            return Err(BadRequestError(format!(
+                "Use POST /api/notes/{}/rename to change a title",
+                id
+            )));
+        }
+        self.edit_note(id, &update, not_modified_since).await
+    }
+}
+
+/// Runs the operations against the note's patchable fields and returns the
+/// changes as an update: `Some(None)` for a removed field.
+fn apply_patch(note: &NoteModel, operations: &[PatchOperation]) -> Result<UpdateNoteSchema> {
+    let original = patchable_fields(note);
+    let mut patched = original.clone();
+
+    for operation in operations {
+        let field = field_for_path(&operation.path)?;
+        let value = || {
+            operation
+                .value
+                .clone()
+                .map(Option::unwrap_or_default)
+                .ok_or_else(|| {
+                    BadRequestError(format!("'{}' operation needs a value", operation.op))
+                })
+        };
+        match operation.op.as_str() {
+            "add" => {
+                patched.insert(field.to_string(), value()?);
+            }
+            "replace" => {
+                if !patched.contains_key(field) {
+                    return Err(missing_field(&operation.path));
+                }
+                patched.insert(field.to_string(), value()?);
+            }
+            "remove" => {
+                if patched.remove(field).is_none() {
+                    return Err(missing_field(&operation.path));
+                }
+            }
+            "test" => {
+                if patched.get(field) != Some(&value()?) {
+                    return Err(ConflictError(format!("test failed for {}", operation.path)));
+                }
+            }
+            op => {
+                return Err(BadRequestError(format!(
+                    "unsupported JSON Patch operation '{}'; use add, remove, replace or test",
+                    op
+                )))
+            }
+        }
+    }
+
+    let result: CreateNoteSchema =
+        serde_json::from_value(Value::Object(patched.clone())).map_err(|e| {
+            ValidationError(vec![FieldError {
+                field: "note".to_string(),
+                code: "INVALID_NOTE".to_string(),
+                message: format!("patched note is not valid: {}", e),
+            }])
+        })?;
+
+    let changed = |field: &str| original.get(field) != patched.get(field);
+    Ok(UpdateNoteSchema {
+        title: changed("title").then_some(Some(result.title)),
+        content: changed("content").then_some(Some(result.content)),
+        category: changed("category").then_some(result.category),
+        published: changed("published").then_some(result.published),
+        color: changed("color").then_some(result.color),
+        items: None,
+    })
+}
+
+fn patchable_fields(note: &NoteModel) -> Map<String, Value> {
+    let mut fields = Map::new();
+    fields.insert("title".to_string(), Value::from(note.title.clone()));
+    fields.insert("content".to_string(), Value::from(note.content.clone()));
+    if let Some(category) = &note.category {
+        fields.insert("category".to_string(), Value::from(category.clone()));
+    }
+    if let Some(published) = note.published {
+        fields.insert("published".to_string(), Value::from(published));
+    }
+    if let Some(color) = &note.color {
+        fields.insert("color".to_string(), Value::from(color.clone()));
+    }
+    fields
+}
+
+fn field_for_path(path: &str) -> Result<&'static str> {
+    path.strip_prefix('/')
+        .and_then(|name| PATCHABLE_FIELDS.iter().find(|field| **field == name))
+        .copied()
+        .ok_or_else(|| {
+            ValidationError(vec![FieldError {
+                field: path.to_owned(),
+                code: "UNKNOWN_PATH".to_string(),
+                message: format!(
+                    "{} is not a patchable path; use one of /title, /content, /category, /published, /color",
+                    path
+                ),
+            }])
+        })
+}
+
+fn missing_field(path: &str) -> Error {
+    ValidationError(vec![FieldError {
+        field: path.to_owned(),
+        code: "PATH_NOT_FOUND".to_string(),
+        message: format!("{} is not set on this note", path),
+    }])
+}
@@ -1,15 +1,61 @@
+mod archive;
+mod archiving;
+mod attachments;
+mod batch;
+mod bulk;
+mod checklist;
+mod client_ip;
+mod collation;
+mod color;
+mod comments;
+mod config;
+mod counters;
+mod cursor;
 mod db;
+mod duplicate;
 mod error;
+mod events;
+#[cfg(test)]
+mod fuzz;
 mod handler;
+mod inbound;
+mod json_patch;
 mod model;
+mod monitoring;
+mod normalize;
+mod note_export;
+mod pinning;
+mod poll;
+mod positions;
+mod preview;
+mod publish;
+mod query_parser;
+mod reading_time;
+mod reminders;
+mod rename;
+mod replies;
 mod response;
+mod revisions;
+mod routes;
 mod schema;
+mod search;
+mod secret;
+mod setup;
+mod signing;
+mod site_export;
+mod slug;
+mod tags;
+#[cfg(test)]
+mod test_support;
+mod trash;
+mod validation;
+mod warmup;
 
+use config::Config;
 use db::DB;
 use dotenv::dotenv;
-use schema::FilterOptions;
-use std::convert::Infallible;
-use warp::{http::Method, Filter, Rejection};
+use tokio::sync::watch;
+use warp::Rejection;
 
 type Result<T> = std::result::Result<T, error::Error>;
 type WebResult<T> = std::result::Result<T, Rejection>;
@@ -21,57 +67,51 @@ async fn main() -> Result<()> {
     }
     pretty_env_logger::init();
     dotenv().ok();
-    let db = DB::init().await?;
+    let config = Config::init();
+    let db = DB::init(&config).await?;
 
-    let cors = warp::cors()
-        .allow_methods(&[Method::GET, Method::POST, Method::PATCH, Method::DELETE])
-        .allow_origins(vec!["http://localhost:3000"])
-        .allow_headers(vec!["content-type"])
-        .allow_credentials(true);
-
-    let note_router = warp::path!("api" / "notes");
-    let note_router_id = warp::path!("api" / "notes" / String);
-    let health_checker = warp::path!("api" / "healthchecker")
-        .and(warp::get())
-        .and_then(handler::health_checker_handler);
-
-    let note_routes = note_router
-        .and(warp::post())
-        .and(warp::body::json())
-        .and(with_db(db.clone()))
-        .and_then(handler::create_note_handler)
-        .or(note_router
-            .and(warp::get())
-            .and(warp::query::<FilterOptions>())
-            .and(with_db(db.clone()))
-            .and_then(handler::notes_list_handler));
-
-    let note_routes_id = note_router_id
-        .and(warp::patch())
-        .and(warp::body::json())
-        .and(with_db(db.clone()))
-        .and_then(handler::edit_note_handler)
-        .or(note_router_id
-            .and(warp::get())
-            .and(with_db(db.clone()))
-            .and_then(handler::get_note_handler))
-        .or(note_router_id
-            .and(warp::delete())
-            .and(with_db(db.clone()))
-            .and_then(handler::delete_note_handler));
+    tokio::spawn(counters::reconcile_periodically(
+        db.clone(),
+        config.counter_reconcile_interval,
+    ));
+    if let Some(webhook_url) = &config.reminder_webhook_url {
+        tokio::spawn(reminders::run_scheduler(
+            db.clone(),
+            webhook_url.expose().clone(),
+        ));
+    }
+    if config.warmup_enabled {
+        tokio::spawn(warmup::run(
+            db.clone(),
+            config.min_pool_size.unwrap_or(4),
+            config.warmup_notes,
+            config.warmup_deadline,
+        ));
+    } else {
+        warmup::mark_ready();
+    }
 
-    let routes = note_routes
-        .with(warp::log("api"))
-        .or(note_routes_id)
-        .or(health_checker)
-        .with(cors)
-        .recover(error::handle_rejection);
+    let (stop_tasks, stopping) = watch::channel(false);
+    let purge = (config.trash_retention_days > 0).then(|| {
+        tokio::spawn(trash::purge_periodically(
+            db.clone(),
+            config.trash_retention_days,
+            stopping,
+        ))
+    });
 
+    let (_, server) = warp::serve(routes::routes(db, config)).bind_with_graceful_shutdown(
+        ([0, 0, 0, 0], 8000),
+        async {
+            tokio::signal::ctrl_c().await.ok();
+        },
+    );
     println!("🚀 Server started successfully");
-    warp::serve(routes).run(([0, 0, 0, 0], 8000)).await;
-    Ok(())
-}
+    server.await;
 
-fn with_db(db: DB) -> impl Filter<Extract = (DB,), Error = Infallible> + Clone {
-    warp::any().map(move || db.clone())
+    stop_tasks.send(true).ok();
+    if let Some(purge) = purge {
+        purge.await.ok();
+    }
+    Ok(())
 }
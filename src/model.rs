@@ -1,3 +1,5 @@
+use crate::checklist::ChecklistItem;
+use crate::preview::NotePreview;
 use chrono::prelude::*;
 use mongodb::bson::{self, oid::ObjectId};
 use serde::{Deserialize, Serialize};
@@ -11,8 +13,119 @@ pub struct NoteModel {
     pub content: String,
     pub category: Option<String>,
     pub published: Option<bool>,
+    /// A name from `color::NAMED_COLORS` or `#rrggbb`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub createdAt: DateTime<Utc>,
     #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
     pub updatedAt: DateTime<Utc>,
+    /// Unique; set on create and backfilled by schema setup.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slug: Option<String>,
+    /// When the note was first published; kept when it is unpublished.
+    #[serde(
+        default,
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub publishedAt: Option<DateTime<Utc>>,
+    /// Missing on notes written before archiving existed.
+    #[serde(default)]
+    pub archived: bool,
+    /// Missing on notes written before pinning existed.
+    #[serde(default)]
+    pub pinned: bool,
+    /// Missing on notes written before tags existed.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<ChecklistItem>,
+    /// Manual order, ascending; see `positions`.
+    #[serde(default)]
+    pub position: i64,
+    /// Set while the note is in the trash.
+    #[serde(
+        default,
+        with = "bson::serde_helpers::chrono_datetime_as_bson_datetime_optional",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub deletedAt: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub draft: Option<NoteDraftModel>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<NotePreview>,
+    /// How many revisions edits, replacements and merges have recorded for
+    /// the note.
+    #[serde(default)]
+    pub revision: i64,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NoteDraftModel {
+    pub content: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub savedAt: DateTime<Utc>,
+}
+
+/// The editable fields of a note as they were before an edit.
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NoteRevisionModel {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub noteId: ObjectId,
+    /// Counts up from 1 per note.
+    pub revision: i64,
+    pub title: String,
+    pub content: String,
+    pub category: Option<String>,
+    pub published: Option<bool>,
+    /// When this version of the note was saved.
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub updatedAt: DateTime<Utc>,
+    /// When the edit that replaced it happened.
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub createdAt: DateTime<Utc>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CommentModel {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub noteId: ObjectId,
+    pub author: String,
+    pub body: String,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub createdAt: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReminderState {
+    Pending,
+    Sent,
+    Cancelled,
+    /// Delivery failed `MAX_DELIVERY_ATTEMPTS` times.
+    Dead,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReminderModel {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub noteId: ObjectId,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub at: DateTime<Utc>,
+    pub message: String,
+    pub state: ReminderState,
+    #[serde(default)]
+    pub attempts: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lastError: Option<String>,
+    #[serde(with = "bson::serde_helpers::chrono_datetime_as_bson_datetime")]
+    pub createdAt: DateTime<Utc>,
 }
@@ -0,0 +1,176 @@
+use log::{info, warn};
+use mongodb::event::cmap::{
+    CmapEventHandler, ConnectionCheckoutFailedEvent, PoolClearedEvent, PoolReadyEvent,
+};
+use mongodb::event::sdam::{
+    SdamEventHandler, ServerClosedEvent, ServerOpeningEvent, TopologyDescriptionChangedEvent,
+};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::time::{Duration, Instant};
+
+const LOG_TARGET: &str = "api::mongodb";
+
+/// How long the health check reports `degraded` after a pool was cleared.
+const DEGRADED_WINDOW: Duration = Duration::from_secs(30);
+
+/// Driver topology and connection-pool events, logged and counted. The
+/// handlers run on the driver's own tasks, so they only touch atomics; the
+/// log macros skip formatting when the level is disabled.
+#[derive(Debug)]
+pub struct MongoMonitor {
+    started: Instant,
+    servers_opened: AtomicU64,
+    servers_closed: AtomicU64,
+    topology_changes: AtomicU64,
+    pool_clears: AtomicU64,
+    checkout_failures: AtomicU64,
+    /// Milliseconds after `started` of the last pool clear, plus one so
+    /// that zero means "never".
+    last_pool_clear_ms: AtomicU64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MongoHealth {
+    pub degraded: bool,
+    pub servers_opened: u64,
+    pub servers_closed: u64,
+    pub topology_changes: u64,
+    pub pool_clears: u64,
+    pub checkout_failures: u64,
+}
+
+impl MongoMonitor {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            servers_opened: AtomicU64::new(0),
+            servers_closed: AtomicU64::new(0),
+            topology_changes: AtomicU64::new(0),
+            pool_clears: AtomicU64::new(0),
+            checkout_failures: AtomicU64::new(0),
+            last_pool_clear_ms: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        match self.last_pool_clear_ms.load(Relaxed) {
+            0 => false,
+            cleared => {
+                let since_clear = self.elapsed_ms().saturating_sub(cleared - 1);
+                since_clear < DEGRADED_WINDOW.as_millis() as u64
+            }
+        }
+    }
+
+    pub fn health(&self) -> MongoHealth {
+        MongoHealth {
+            degraded: self.is_degraded(),
+            servers_opened: self.servers_opened.load(Relaxed),
+            servers_closed: self.servers_closed.load(Relaxed),
+            topology_changes: self.topology_changes.load(Relaxed),
+            pool_clears: self.pool_clears.load(Relaxed),
+            checkout_failures: self.checkout_failures.load(Relaxed),
+        }
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+}
+
+impl SdamEventHandler for MongoMonitor {
+    fn handle_server_opening_event(&self, event: ServerOpeningEvent) {
+        self.servers_opened.fetch_add(1, Relaxed);
+        info!(target: LOG_TARGET, "server opened: {}", event.address);
+    }
+
+    fn handle_server_closed_event(&self, event: ServerClosedEvent) {
+        self.servers_closed.fetch_add(1, Relaxed);
+        warn!(target: LOG_TARGET, "server closed: {}", event.address);
+    }
+
+    fn handle_topology_description_changed_event(&self, event: TopologyDescriptionChangedEvent) {
+        let previous = event.previous_description.topology_type();
+        let current = event.new_description.topology_type();
+        // Descriptions change on every server state update; only a change
+        // of topology type (e.g. a replica set losing its primary) matters.
+        if previous != current {
+            self.topology_changes.fetch_add(1, Relaxed);
+            warn!(
+                target: LOG_TARGET,
+                "topology changed: {:?} -> {:?}", previous, current
+            );
+        }
+    }
+}
+
+impl CmapEventHandler for MongoMonitor {
+    fn handle_pool_cleared_event(&self, event: PoolClearedEvent) {
+        self.pool_clears.fetch_add(1, Relaxed);
+        self.last_pool_clear_ms
+            .store(self.elapsed_ms() + 1, Relaxed);
+        warn!(target: LOG_TARGET, "connection pool cleared: {}", event.address);
+    }
+
+    fn handle_pool_ready_event(&self, event: PoolReadyEvent) {
+        info!(target: LOG_TARGET, "connection pool ready: {}", event.address);
+    }
+
+    fn handle_connection_checkout_failed_event(&self, event: ConnectionCheckoutFailedEvent) {
+        self.checkout_failures.fetch_add(1, Relaxed);
+        warn!(
+            target: LOG_TARGET,
+            "connection checkout failed: {} ({:?})", event.address, event.reason
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use serde_json::json;
+
+    /// The driver's events cannot be built directly, but deserialize.
+    fn event<T: DeserializeOwned>(fields: serde_json::Value) -> T {
+        serde_json::from_value(fields).unwrap()
+    }
+
+    #[test]
+    fn a_container_restart_is_counted_and_degrades_health() {
+        let monitor = MongoMonitor::new();
+        assert!(!monitor.health().degraded);
+
+        // What the driver reports while the server restarts.
+        monitor.handle_pool_cleared_event(event(json!({"serviceId": null})));
+        monitor.handle_server_closed_event(event(json!({"address": "mongo:27017"})));
+        monitor.handle_server_opening_event(event(json!({"address": "mongo:27017"})));
+        monitor.handle_pool_ready_event(event(json!({})));
+
+        let health = monitor.health();
+        assert!(health.degraded);
+        assert_eq!(
+            (
+                health.servers_closed,
+                health.servers_opened,
+                health.pool_clears,
+                health.checkout_failures,
+                health.topology_changes,
+            ),
+            (1, 1, 1, 0, 0)
+        );
+    }
+
+    #[test]
+    fn degraded_ends_after_the_window() {
+        let monitor = MongoMonitor::new();
+        monitor.handle_pool_cleared_event(event(json!({"serviceId": null})));
+        assert!(monitor.is_degraded());
+        // As if the clear had happened a window ago.
+        monitor.last_pool_clear_ms.store(1, Relaxed);
+        let started = Instant::now() - DEGRADED_WINDOW - Duration::from_millis(1);
+        let monitor = MongoMonitor { started, ..monitor };
+        assert!(!monitor.is_degraded());
+    }
+}
@@ -0,0 +1,110 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Turns a title into the form used for uniqueness and lookups. Every path
+/// that compares titles goes through `normalize`, so storage, the unique
+/// index and queries cannot disagree.
+///
+/// Steps, in order:
+/// 1. NFKC (optional): full-width `ＡＢＣ` becomes `ABC`, ligatures split.
+/// 2. Unicode default case folding (optional): `Straße` and `STRASSE` both
+///    become `strasse`. Folding is locale-independent, so Turkish `I` folds
+///    to `i`, not dotless `ı`, and `İ` to `i` plus a combining dot.
+/// 3. Trim and collapse every run of whitespace to a single space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TitleNormalizer {
+    pub case_fold: bool,
+    pub nfkc: bool,
+}
+
+impl TitleNormalizer {
+    pub fn normalize(&self, title: &str) -> String {
+        let mut value: String = if self.nfkc {
+            title.nfkc().collect()
+        } else {
+            title.to_string()
+        };
+        if self.case_fold {
+            value = caseless::default_case_fold_str(&value);
+        }
+        tidy_title(&value)
+    }
+
+    /// Identifies the settings, so setup can tell when stored
+    /// `title_normalized` values were produced with different ones.
+    pub fn key(&self) -> String {
+        format!("fold={},nfkc={}", self.case_fold, self.nfkc)
+    }
+}
+
+/// The form a title is stored in: trimmed, with every run of whitespace
+/// collapsed to a single space. Case and width are kept.
+pub fn tidy_title(title: &str) -> String {
+    title.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL: TitleNormalizer = TitleNormalizer {
+        case_fold: true,
+        nfkc: true,
+    };
+
+    #[test]
+    fn german_sharp_s_folds_to_ss() {
+        assert_eq!(FULL.normalize("Straße"), "strasse");
+        assert_eq!(FULL.normalize("STRASSE"), "strasse");
+        assert_eq!(FULL.normalize("ẞ"), "ss");
+    }
+
+    #[test]
+    fn turkish_i_folds_without_locale() {
+        assert_eq!(FULL.normalize("I"), "i");
+        assert_eq!(FULL.normalize("ı"), "ı");
+        assert_eq!(FULL.normalize("İ"), "i\u{307}");
+        assert_ne!(FULL.normalize("ISTANBUL"), FULL.normalize("ıstanbul"));
+    }
+
+    #[test]
+    fn full_width_and_ligatures_become_ascii() {
+        assert_eq!(FULL.normalize("ＡＢＣ １２３"), "abc 123");
+        assert_eq!(FULL.normalize("ﬁle"), "file");
+        // Without NFKC the width is kept, folding still lowercases.
+        let fold_only = TitleNormalizer {
+            case_fold: true,
+            nfkc: false,
+        };
+        assert_eq!(fold_only.normalize("ＡＢＣ"), "ａｂｃ");
+    }
+
+    #[test]
+    fn whitespace_is_collapsed_in_every_mode() {
+        let off = TitleNormalizer {
+            case_fold: false,
+            nfkc: false,
+        };
+        for normalizer in [FULL, off] {
+            assert_eq!(normalizer.normalize("  a \t\n b  "), tidy_title("a b"));
+        }
+        assert_eq!(off.normalize("  Straße  Nord "), "Straße Nord");
+        // NFKC turns the ideographic space into a plain one.
+        assert_eq!(FULL.normalize("a\u{3000}b"), "a b");
+    }
+
+    #[test]
+    fn tidy_title_keeps_case_and_width() {
+        assert_eq!(tidy_title("  ＡＢＣ   Straße "), "ＡＢＣ Straße");
+    }
+
+    #[test]
+    fn key_tells_settings_apart() {
+        let keys: Vec<String> = [(false, false), (false, true), (true, false), (true, true)]
+            .into_iter()
+            .map(|(case_fold, nfkc)| TitleNormalizer { case_fold, nfkc }.key())
+            .collect();
+        for (i, key) in keys.iter().enumerate() {
+            assert!(!keys[i + 1..].contains(key), "{} repeats", key);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::archive::ZipStream;
+use crate::response::SiteExportError;
+use crate::{db::DB, error::Error::*, model::NoteModel, Result};
+use chrono::{DateTime, Utc};
+use mongodb::bson::{self, doc, oid::ObjectId};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
+use tokio::runtime::Handle;
+
+pub const MAX_EXPORT_IDS: usize = 500;
+const MAX_SLUG_LEN: usize = 80;
+
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+struct Manifest<'a> {
+    generatedAt: DateTime<Utc>,
+    files: &'a BTreeMap<String, String>,
+    errors: &'a [SiteExportError],
+}
+
+/// Writes the requested notes into `zip` as markdown files, streaming them
+/// in `_id` order straight from the cursor. Ids that are malformed or do not
+/// exist end up under `errors` in `manifest.json`. Must run on a blocking
+/// thread.
+pub fn export(db: &DB, ids: &[String], zip: &mut ZipStream) -> Result<()> {
+    let handle = Handle::current();
+    let mut errors: Vec<SiteExportError> = Vec::new();
+    let mut wanted: Vec<ObjectId> = Vec::new();
+    let mut seen: HashSet<&str> = HashSet::new();
+
+    for id in ids {
+        if !seen.insert(id.as_str()) {
+            continue;
+        }
+        match ObjectId::from_str(id) {
+            Ok(oid) => wanted.push(oid),
+            Err(_) => errors.push(SiteExportError {
+                id: id.to_owned(),
+                message: "invalid id".to_string(),
+            }),
+        }
+    }
+
+    let mut cursor = db.notes_by_ids_cursor(&wanted);
+    let mut files: BTreeMap<String, String> = BTreeMap::new();
+    let mut found: HashSet<ObjectId> = HashSet::new();
+
+    while let Some(doc) = handle.block_on(cursor.next()) {
+        let doc = doc?;
+        let id = doc.get_object_id("_id").map_err(MongoDataError)?;
+        found.insert(id);
+
+        let note: NoteModel = match bson::from_document(doc) {
+            Ok(note) => note,
+            Err(e) => {
+                errors.push(SiteExportError {
+                    id: id.to_hex(),
+                    message: format!("could not read note: {}", e),
+                });
+                continue;
+            }
+        };
+
+        let file = unique_filename(&slugify(&note.title), &files);
+        zip.add_file(&file, render_markdown_file(&note).as_bytes())
+            .map_err(|e| ExportError(format!("could not write {}: {}", file, e)))?;
+        files.insert(file, id.to_hex());
+    }
+
+    for oid in wanted.iter().filter(|oid| !found.contains(oid)) {
+        errors.push(SiteExportError {
+            id: oid.to_hex(),
+            message: "note not found".to_string(),
+        });
+    }
+
+    let manifest = serde_json::to_vec_pretty(&Manifest {
+        generatedAt: Utc::now(),
+        files: &files,
+        errors: &errors,
+    })
+    .map_err(|e| ExportError(e.to_string()))?;
+    zip.add_file("manifest.json", &manifest)
+        .map_err(|e| ExportError(format!("could not write manifest.json: {}", e)))
+}
+
+fn render_markdown_file(note: &NoteModel) -> String {
+    // JSON strings are valid double-quoted YAML scalars, which keeps titles
+    // containing colons, quotes or newlines from breaking the front matter.
+    let quote = |value: &str| serde_json::to_string(value).unwrap_or_default();
+
+    format!(
+        "---\nid: {}\ntitle: {}\ncategory: {}\npublished: {}\ncreatedAt: {}\nupdatedAt: {}\n---\n\n{}\n",
+        note.id.to_hex(),
+        quote(&note.title),
+        quote(note.category.as_deref().unwrap_or("")),
+        note.published.unwrap_or(false),
+        note.createdAt.to_rfc3339(),
+        note.updatedAt.to_rfc3339(),
+        note.content
+    )
+}
+
+/// Lowercase ASCII letters and digits joined by single dashes, so the name is
+/// safe on every filesystem a zip might be extracted on.
+fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars() {
+        if c.is_ascii_alphanumeric() {
+            if slug.len() >= MAX_SLUG_LEN {
+                break;
+            }
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "note".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+fn unique_filename(slug: &str, taken: &BTreeMap<String, String>) -> String {
+    let mut file = format!("{}.md", slug);
+    let mut suffix = 2;
+    while taken.contains_key(&file) {
+        file = format!("{}-{}.md", slug, suffix);
+        suffix += 1;
+    }
+    file
+}
@@ -0,0 +1,67 @@
+use crate::{
+    db::{live_note, DB},
+    error::Error::*,
+    events::NoteEvent,
+    response::{NoteData, SingleNoteResponse},
+    Result,
+};
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use std::str::FromStr;
+
+impl DB {
+    /// Pins or unpins a note. Pinned notes come first in lists, whatever
+    /// else they are sorted by. A note already in the requested state is
+    /// returned as it is, without events.
+    pub async fn set_pinned(&self, id: &str, pinned: bool) -> Result<Option<SingleNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+
+        let filter = doc! {"_id": oid, "deletedAt": null, "pinned": {"$ne": pinned}};
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let changed = self
+            .note_collection
+            .find_one_and_update(filter, doc! {"$set": {"pinned": pinned}}, options)
+            .await
+            .map_err(MongoQueryError)?;
+
+        let note = match changed {
+            Some(note) => Some((note, true)),
+            None => self
+                .note_collection
+                .find_one(live_note(oid), None)
+                .await
+                .map_err(MongoQueryError)?
+                .map(|note| (note, false)),
+        };
+        let Some((note, changed)) = note else {
+            return Ok(None);
+        };
+
+        let note_response = SingleNoteResponse {
+            status: "success".to_string(),
+            data: NoteData {
+                note: self.doc_to_note(&note)?,
+                draft: None,
+            },
+        };
+
+        if changed {
+            self.events
+                .publish(NoteEvent::changed(&note_response, &["pinned"]));
+        }
+
+        Ok(Some(note_response))
+    }
+
+    /// Whether the note is pinned; a note that is gone counts as unpinned.
+    pub(crate) async fn is_pinned(&self, oid: ObjectId) -> Result<bool> {
+        let note = self
+            .collection
+            .find_one(doc! {"_id": oid}, None)
+            .await
+            .map_err(MongoQueryError)?;
+        Ok(note.is_some_and(|note| note.get_bool("pinned") == Ok(true)))
+    }
+}
@@ -0,0 +1,130 @@
+use crate::events::{EventBus, NoteEventEnvelope};
+use crate::{error::Error::*, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::time::Instant;
+
+pub const DEFAULT_POLL_TIMEOUT_SECS: u64 = 25;
+pub const MAX_POLL_TIMEOUT_SECS: u64 = 60;
+const MAX_POLL_WAITERS: usize = 1000;
+
+static POLL_WAITERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Held by each waiting long-poll request; beyond `MAX_POLL_WAITERS`
+/// requests are turned away instead of queueing.
+pub struct PollGuard;
+
+impl PollGuard {
+    pub fn acquire() -> Result<Self> {
+        POLL_WAITERS
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |waiters| {
+                (waiters < MAX_POLL_WAITERS).then_some(waiters + 1)
+            })
+            .map_err(|_| UnavailableError("Too many clients are polling".to_string()))?;
+        Ok(PollGuard)
+    }
+}
+
+impl Drop for PollGuard {
+    fn drop(&mut self) {
+        POLL_WAITERS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+pub struct PollResult {
+    pub events: Vec<NoteEventEnvelope>,
+    /// False when events after `since` were dropped from the buffer before
+    /// this poll, so the client should refetch instead of applying a delta.
+    pub complete: bool,
+}
+
+/// Returns the events after `since` as soon as there are any, or an empty
+/// batch once `timeout` passes. Waits on the broadcast channel, not a timer.
+pub async fn wait_for_events(bus: &EventBus, since: u64, timeout: Duration) -> PollResult {
+    let deadline = Instant::now() + timeout;
+    let mut subscription = bus.subscribe_since(since);
+    if !subscription.backlog.is_empty() || !subscription.complete {
+        return PollResult {
+            events: subscription.backlog,
+            complete: subscription.complete,
+        };
+    }
+
+    let mut events = Vec::new();
+    loop {
+        match tokio::time::timeout_at(deadline, subscription.receiver.recv()).await {
+            Ok(Ok(envelope)) => {
+                if envelope.global_seq > since {
+                    events.push(envelope);
+                    break;
+                }
+            }
+            // Fell behind a burst: start over from the buffer.
+            Ok(Err(RecvError::Lagged(_))) => {
+                let subscription = bus.subscribe_since(since);
+                return PollResult {
+                    events: subscription.backlog,
+                    complete: subscription.complete,
+                };
+            }
+            Ok(Err(RecvError::Closed)) | Err(_) => {
+                return PollResult {
+                    events,
+                    complete: true,
+                }
+            }
+        }
+    }
+
+    // Hand over whatever else is already queued with the first event.
+    loop {
+        match subscription.receiver.try_recv() {
+            Ok(envelope) => events.push(envelope),
+            Err(TryRecvError::Lagged(_)) => continue,
+            Err(_) => break,
+        }
+    }
+    PollResult {
+        events,
+        complete: true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::NoteEvent;
+
+    const ID: &str = "6630f0c2a1b2c3d4e5f60718";
+
+    #[tokio::test]
+    async fn a_published_event_wakes_the_poll() {
+        let bus = EventBus::new();
+        let since = bus.last_seq();
+        let publisher = bus.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            publisher.publish(NoteEvent::deleted(ID));
+        });
+
+        let started = Instant::now();
+        let result = wait_for_events(&bus, since, Duration::from_secs(30)).await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(result.complete);
+        assert_eq!(result.events.len(), 1);
+        assert_eq!(result.events[0].event.note_id(), ID);
+    }
+
+    #[tokio::test]
+    async fn buffered_events_return_at_once_and_silence_times_out() {
+        let bus = EventBus::new();
+        bus.publish(NoteEvent::deleted(ID));
+        let result = wait_for_events(&bus, 0, Duration::from_secs(30)).await;
+        assert_eq!(result.events.len(), 1);
+
+        let result = wait_for_events(&bus, bus.last_seq(), Duration::from_millis(50)).await;
+        assert!(result.events.is_empty());
+        assert!(result.complete);
+    }
+}
@@ -0,0 +1,224 @@
+use crate::{
+    db::{live_note, DB},
+    error::Error::*,
+    events::NoteEvent,
+    replies::not_found_message,
+    response::{NoteData, SingleNoteResponse},
+    Result,
+};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::options::{FindOneAndUpdateOptions, FindOneOptions, FindOptions, ReturnDocument};
+use mongodb::ClientSession;
+use std::str::FromStr;
+
+/// Space left between neighbouring positions, so that most moves fit a note
+/// in between without touching any other.
+pub const POSITION_GAP: i64 = 1024;
+
+/// Updates sent per `update` command while renumbering.
+const RENUMBER_BATCH: usize = 1000;
+
+/// Where `DB::move_note` puts the note.
+#[derive(Debug, Clone, Copy)]
+pub enum MoveTarget {
+    Before(ObjectId),
+    After(ObjectId),
+}
+
+impl DB {
+    /// The position a new note gets: one gap after the last note.
+    pub(crate) async fn next_position(&self) -> Result<i64> {
+        let options = FindOneOptions::builder()
+            .projection(doc! {"position": 1})
+            .sort(doc! {"position": -1})
+            .build();
+        let last = self
+            .collection
+            .find_one(doc! {"position": {"$exists": true}}, options)
+            .await
+            .map_err(MongoQueryError)?;
+        Ok(last
+            .and_then(|note| note.get_i64("position").ok())
+            .unwrap_or(0)
+            + POSITION_GAP)
+    }
+
+    /// Moves the note right before or after another one. The note gets the
+    /// midpoint between its new neighbours; when they sit next to each other
+    /// every note is renumbered first. Both happen inside a transaction when
+    /// the deployment supports them, so a concurrent move sees either all of
+    /// a renumbering or none of it.
+    pub async fn move_note(
+        &self,
+        id: &str,
+        target: MoveTarget,
+    ) -> Result<Option<SingleNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+        let anchor_id = match target {
+            MoveTarget::Before(anchor_id) | MoveTarget::After(anchor_id) => anchor_id,
+        };
+        if anchor_id == oid {
+            return Err(BadRequestError(
+                "A note cannot be moved relative to itself".to_string(),
+            ));
+        }
+
+        let mut session = self.client.start_session(None).await?;
+        if self.supports_transactions {
+            session.start_transaction(None).await?;
+        }
+
+        let exists = self
+            .collection
+            .find_one_with_session(live_note(oid), None, &mut session)
+            .await
+            .map_err(MongoQueryError)?
+            .is_some();
+        if !exists {
+            return Ok(None);
+        }
+
+        let mut position = self.position_between(oid, target, &mut session).await?;
+        if position.is_none() {
+            self.renumber_positions(oid, &mut session).await?;
+            position = self.position_between(oid, target, &mut session).await?;
+        }
+        let position = position
+            .ok_or_else(|| ConflictError("Note positions changed while renumbering".to_string()))?;
+
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let moved = self
+            .note_collection
+            .find_one_and_update_with_session(
+                live_note(oid),
+                doc! {"$set": {"position": position}},
+                options,
+                &mut session,
+            )
+            .await
+            .map_err(MongoQueryError)?
+            .ok_or_else(|| ConflictError("Note was deleted during move".to_string()))?;
+
+        if self.supports_transactions {
+            session.commit_transaction().await?;
+        }
+
+        let note_response = SingleNoteResponse {
+            status: "success".to_string(),
+            data: NoteData {
+                note: self.doc_to_note(&moved)?,
+                draft: None,
+            },
+        };
+        self.events
+            .publish(NoteEvent::changed(&note_response, &["position"]));
+
+        Ok(Some(note_response))
+    }
+
+    /// A free position between the anchor and its neighbour on the side the
+    /// note goes to, leaving the moved note itself out. `None` when the two
+    /// are adjacent.
+    async fn position_between(
+        &self,
+        oid: ObjectId,
+        target: MoveTarget,
+        session: &mut ClientSession,
+    ) -> Result<Option<i64>> {
+        let (anchor_id, before) = match target {
+            MoveTarget::Before(anchor_id) => (anchor_id, true),
+            MoveTarget::After(anchor_id) => (anchor_id, false),
+        };
+        let anchor = self
+            .collection
+            .find_one_with_session(live_note(anchor_id), None, session)
+            .await
+            .map_err(MongoQueryError)?
+            .ok_or_else(|| NotFoundError(not_found_message("Note", &anchor_id.to_hex())))?;
+        let anchor_position = anchor.get_i64("position").unwrap_or_default();
+
+        // Lists order by position and then `_id`, so a tied note on the
+        // right side of the anchor's `_id` is its neighbour too.
+        let (cmp, direction) = if before { ("$lt", -1) } else { ("$gt", 1) };
+        let filter = doc! {
+            "_id": {"$ne": oid},
+            "$or": [
+                {"position": {cmp: anchor_position}},
+                {"position": anchor_position, "_id": {cmp: anchor_id}},
+            ],
+        };
+        let options = FindOneOptions::builder()
+            .projection(doc! {"position": 1})
+            .sort(doc! {"position": direction, "_id": direction})
+            .build();
+        let neighbour = self
+            .collection
+            .find_one_with_session(filter, options, session)
+            .await
+            .map_err(MongoQueryError)?
+            .map(|note| note.get_i64("position").unwrap_or_default());
+
+        let position = match neighbour {
+            None if before => anchor_position - POSITION_GAP,
+            None => anchor_position + POSITION_GAP,
+            Some(neighbour) => anchor_position + (neighbour - anchor_position) / 2,
+        };
+        Ok((position != anchor_position && Some(position) != neighbour).then_some(position))
+    }
+
+    /// Spreads every note but `skip` out to multiples of `POSITION_GAP`,
+    /// keeping their order, with batched `update` commands rather than one
+    /// round trip per note.
+    async fn renumber_positions(&self, skip: ObjectId, session: &mut ClientSession) -> Result<()> {
+        let options = FindOptions::builder()
+            .projection(doc! {"_id": 1})
+            .sort(doc! {"position": 1, "_id": 1})
+            .build();
+        let mut cursor = self
+            .collection
+            .find_with_session(doc! {"_id": {"$ne": skip}}, options, session)
+            .await
+            .map_err(MongoQueryError)?;
+        let mut ids = Vec::new();
+        while let Some(note) = cursor.next(session).await {
+            if let Ok(id) = note.map_err(MongoQueryError)?.get_object_id("_id") {
+                ids.push(id);
+            }
+        }
+        drop(cursor);
+
+        let namespace = self.collection.namespace();
+        let database = self.client.database(&namespace.db);
+        for (batch, chunk) in ids.chunks(RENUMBER_BATCH).enumerate() {
+            let updates: Vec<Document> = chunk
+                .iter()
+                .enumerate()
+                .map(|(index, id)| {
+                    let position = (batch * RENUMBER_BATCH + index + 1) as i64 * POSITION_GAP;
+                    doc! {"q": {"_id": id}, "u": {"$set": {"position": position}}}
+                })
+                .collect();
+            let command = doc! {
+                "update": &namespace.coll,
+                "updates": updates,
+                "ordered": false,
+            };
+            let reply = database
+                .run_command_with_session(command, None, session)
+                .await
+                .map_err(MongoQueryError)?;
+            // Write errors come back in a successful reply.
+            if let Ok(errors) = reply.get_array("writeErrors") {
+                if let Some(Bson::Document(error)) = errors.first() {
+                    return Err(ConflictError(format!(
+                        "Renumbering note positions failed: {}",
+                        error.get_str("errmsg").unwrap_or("unknown error")
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+use pulldown_cmark::{Event, Parser, Tag};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Longer URLs are treated as if they were not there.
+pub const MAX_PREVIEW_URL_LEN: usize = 2048;
+
+/// What a card needs to show a note without parsing its markdown: the first
+/// image and the domain of the first link. Stored as `preview` on the note.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NotePreview {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_link_domain: Option<String>,
+}
+
+/// `None` when the content has neither a usable image nor a usable link.
+/// Reference-style links are resolved by the parser, and anything inside
+/// code spans or blocks is never an image or link to begin with.
+pub fn extract(markdown: &str) -> Option<NotePreview> {
+    let mut image_url = None;
+    let mut first_link_domain = None;
+
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(Tag::Image { dest_url, .. }) if image_url.is_none() => {
+                image_url = web_url(&dest_url).map(|url| url.to_string());
+            }
+            Event::Start(Tag::Link { dest_url, .. }) if first_link_domain.is_none() => {
+                first_link_domain = web_url(&dest_url)
+                    .and_then(|url| url.host_str().map(|host| host.to_ascii_lowercase()));
+            }
+            _ => {}
+        }
+        if image_url.is_some() && first_link_domain.is_some() {
+            break;
+        }
+    }
+
+    (image_url.is_some() || first_link_domain.is_some()).then_some(NotePreview {
+        image_url,
+        first_link_domain,
+    })
+}
+
+/// Absolute http(s) URLs only; relative paths and other schemes mean
+/// nothing to a client rendering the card.
+fn web_url(raw: &str) -> Option<Url> {
+    if raw.len() > MAX_PREVIEW_URL_LEN {
+        return None;
+    }
+    let url = Url::parse(raw.trim()).ok()?;
+    matches!(url.scheme(), "http" | "https").then_some(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preview(image_url: Option<&str>, first_link_domain: Option<&str>) -> Option<NotePreview> {
+        Some(NotePreview {
+            image_url: image_url.map(str::to_string),
+            first_link_domain: first_link_domain.map(str::to_string),
+        })
+    }
+
+    #[test]
+    fn reference_style_links_and_images_resolve() {
+        let markdown = "See [the docs][docs] and ![chart][img].\n\n\
+                        [docs]: https://Docs.Example.com/guide\n\
+                        [img]: https://cdn.example.com/chart.png";
+        assert_eq!(
+            extract(markdown),
+            preview(
+                Some("https://cdn.example.com/chart.png"),
+                Some("docs.example.com")
+            )
+        );
+        // A reference without a definition is plain text.
+        assert_eq!(extract("See [the docs][missing]."), None);
+    }
+
+    #[test]
+    fn code_is_never_a_link() {
+        assert_eq!(
+            extract("Use `[x](https://inline.example.com)` literally."),
+            None
+        );
+        let fenced = "```md\n![shot](https://fenced.example.com/a.png)\n[x](https://fenced.example.com)\n```\n\
+                      Then [read on](https://after.example.com).";
+        assert_eq!(extract(fenced), preview(None, Some("after.example.com")));
+        assert_eq!(
+            extract("    [indented](https://indented.example.com)"),
+            None
+        );
+    }
+
+    #[test]
+    fn headings_alone_give_no_preview() {
+        assert_eq!(extract("# Title\n\n## Section\n\n### More"), None);
+        assert_eq!(
+            extract("# [Linked heading](https://heading.example.com)"),
+            preview(None, Some("heading.example.com"))
+        );
+    }
+
+    #[test]
+    fn only_absolute_web_urls_count() {
+        let markdown = "![local](/img/a.png) ![data](data:image/png;base64,AAAA) \
+                        [mail](mailto:me@example.com) [rel](../notes) \
+                        ![ok](http://img.example.com/b.png) [ok](https://link.example.com)";
+        assert_eq!(
+            extract(markdown),
+            preview(
+                Some("http://img.example.com/b.png"),
+                Some("link.example.com")
+            )
+        );
+        let long = format!(
+            "[long](https://example.com/{})",
+            "a".repeat(MAX_PREVIEW_URL_LEN)
+        );
+        assert_eq!(extract(&long), None);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{
+    db::{live_note, DB},
+    error::Error::*,
+    events::NoteEvent,
+    response::{NoteData, SingleNoteResponse},
+    schema::UpdateNoteSchema,
+    Result,
+};
+use chrono::Utc;
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use std::str::FromStr;
+
+impl DB {
+    /// Sets `published`, stamping `publishedAt` when the note goes live.
+    /// Unpublishing keeps the last stamp. A note already in the requested
+    /// state is returned as it is, without events.
+    pub async fn set_published(
+        &self,
+        id: &str,
+        published: bool,
+    ) -> Result<Option<SingleNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+
+        let filter = doc! {"_id": oid, "deletedAt": null, "published": {"$ne": published}};
+        let mut set = doc! {"published": published};
+        if published {
+            set.insert("publishedAt", Utc::now());
+        }
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let changed = self
+            .note_collection
+            .find_one_and_update(filter, doc! {"$set": set}, options)
+            .await
+            .map_err(MongoQueryError)?;
+
+        let note = match changed {
+            Some(note) => Some((note, true)),
+            None => self
+                .note_collection
+                .find_one(live_note(oid), None)
+                .await
+                .map_err(MongoQueryError)?
+                .map(|note| (note, false)),
+        };
+        let Some((note, changed)) = note else {
+            return Ok(None);
+        };
+
+        let note_response = SingleNoteResponse {
+            status: "success".to_string(),
+            data: NoteData {
+                note: self.doc_to_note(&note)?,
+                draft: None,
+            },
+        };
+
+        if changed {
+            let body = UpdateNoteSchema {
+                published: Some(Some(published)),
+                ..Default::default()
+            };
+            self.events
+                .publish(NoteEvent::updated(&note_response, &body));
+            self.events.publish(NoteEvent::published(id, published));
+        }
+
+        Ok(Some(note_response))
+    }
+
+    /// Stamps `publishedAt` on those of `ids` that are published now. Called
+    /// after a write with the notes it may have taken live, which the write
+    /// itself cannot tell apart without a pipeline update.
+    pub(crate) async fn stamp_published(&self, ids: &[ObjectId]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        self.collection
+            .update_many(
+                doc! {"_id": {"$in": ids}, "published": true},
+                doc! {"$set": {"publishedAt": Utc::now()}},
+                None,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+        Ok(())
+    }
+}
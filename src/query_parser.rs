@@ -0,0 +1,207 @@
+use crate::{error::Error::*, Result};
+
+/// What a `?q=` term is matched against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryField {
+    /// Case-insensitive substring of the title.
+    Title,
+    /// Exact category.
+    Category,
+    Published(bool),
+    /// Bare terms: case-insensitive substring of the content.
+    Content,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryTerm {
+    pub field: QueryField,
+    /// Unquoted and not yet regex-escaped; unused for `Published`.
+    pub value: String,
+    /// A leading `-`: notes matching the term are left out.
+    pub negated: bool,
+}
+
+/// Parses `title:meeting category:"team work" -published:true standup`.
+///
+/// Terms are separated by whitespace outside double quotes; every term must
+/// match. A `field:` prefix must name a known field, and an unterminated
+/// quote runs to the end of the input.
+pub fn parse_query(q: &str) -> Result<Vec<QueryTerm>> {
+    tokenize(q)
+        .into_iter()
+        .map(|token| parse_term(&token))
+        .collect()
+}
+
+fn parse_term(token: &Token) -> Result<QueryTerm> {
+    let (negated, raw) = match token.raw.strip_prefix('-') {
+        Some(rest) if !rest.is_empty() => (true, rest),
+        _ => (false, token.raw.as_str()),
+    };
+    let field = match token.field.as_deref() {
+        None => QueryField::Content,
+        Some("title") => QueryField::Title,
+        Some("category") => QueryField::Category,
+        Some("published") => match token.value.as_str() {
+            "true" => QueryField::Published(true),
+            "false" => QueryField::Published(false),
+            _ => {
+                return Err(InvalidQueryError(format!(
+                    "'{}' must be published:true or published:false",
+                    token.raw
+                )))
+            }
+        },
+        Some(_) => {
+            return Err(InvalidQueryError(format!(
+                "unknown search field in '{}'; valid fields are title, category, published",
+                token.raw
+            )))
+        }
+    };
+    let value = match field {
+        QueryField::Content => unquote(raw),
+        _ => token.value.clone(),
+    };
+    Ok(QueryTerm {
+        field,
+        value,
+        negated,
+    })
+}
+
+struct Token {
+    /// As written, for error messages and bare terms.
+    raw: String,
+    /// The text before the first unquoted `:`, minus any leading `-`.
+    field: Option<String>,
+    /// The unquoted text after that `:`.
+    value: String,
+}
+
+fn tokenize(q: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut raw = String::new();
+    let mut in_quotes = false;
+    for c in q.chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                raw.push(c);
+            }
+            c if c.is_whitespace() && !in_quotes => {
+                if !raw.is_empty() {
+                    tokens.push(split_token(std::mem::take(&mut raw)));
+                }
+            }
+            c => raw.push(c),
+        }
+    }
+    if !raw.is_empty() {
+        tokens.push(split_token(raw));
+    }
+    tokens
+}
+
+fn split_token(raw: String) -> Token {
+    let body = raw.strip_prefix('-').unwrap_or(&raw);
+    let colon = body
+        .find(['"', ':'])
+        .filter(|&at| body[at..].starts_with(':'));
+    match colon {
+        Some(at) => Token {
+            field: Some(body[..at].to_string()),
+            value: unquote(&body[at + 1..]),
+            raw,
+        },
+        None => Token {
+            field: None,
+            value: String::new(),
+            raw,
+        },
+    }
+}
+
+fn unquote(value: &str) -> String {
+    value.replace('"', "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(field: QueryField, value: &str, negated: bool) -> QueryTerm {
+        QueryTerm {
+            field,
+            value: value.to_string(),
+            negated,
+        }
+    }
+
+    fn rejected(q: &str) -> String {
+        match parse_query(q) {
+            Err(InvalidQueryError(message)) => message,
+            other => panic!("{:?} was accepted: {:?}", q, other),
+        }
+    }
+
+    #[test]
+    fn fields_and_bare_terms() {
+        assert_eq!(
+            parse_query("title:meeting category:work published:true standup").unwrap(),
+            [
+                term(QueryField::Title, "meeting", false),
+                term(QueryField::Category, "work", false),
+                term(QueryField::Published(true), "true", false),
+                term(QueryField::Content, "standup", false),
+            ]
+        );
+        assert!(parse_query("   ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn quoted_phrases_keep_their_spaces() {
+        assert_eq!(
+            parse_query(r#"title:"weekly standup" "two words""#).unwrap(),
+            [
+                term(QueryField::Title, "weekly standup", false),
+                term(QueryField::Content, "two words", false),
+            ]
+        );
+        // A colon inside quotes does not start a field.
+        assert_eq!(
+            parse_query(r#""ratio 1:2""#).unwrap(),
+            [term(QueryField::Content, "ratio 1:2", false)]
+        );
+        // An unterminated quote runs to the end.
+        assert_eq!(
+            parse_query(r#"category:"team work"#).unwrap(),
+            [term(QueryField::Category, "team work", false)]
+        );
+    }
+
+    #[test]
+    fn leading_dash_negates() {
+        assert_eq!(
+            parse_query("-category:personal -draft -").unwrap(),
+            [
+                term(QueryField::Category, "personal", true),
+                term(QueryField::Content, "draft", true),
+                // A lone dash is a search for a dash.
+                term(QueryField::Content, "-", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_fields_echo_the_token() {
+        assert_eq!(
+            rejected("title:ok colour:red"),
+            "unknown search field in 'colour:red'; valid fields are title, category, published"
+        );
+        assert_eq!(
+            rejected("-published:maybe"),
+            "'-published:maybe' must be published:true or published:false"
+        );
+    }
+}
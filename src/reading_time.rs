@@ -0,0 +1,13 @@
+/// Average silent reading speed the estimate assumes.
+pub const WORDS_PER_MINUTE: usize = 200;
+
+/// Runs of anything but Unicode whitespace, so punctuation and emoji count
+/// as part of the word they are attached to.
+pub fn word_count(content: &str) -> usize {
+    content.split_whitespace().count()
+}
+
+/// Whole minutes, rounded up; even an empty note takes a minute.
+pub fn reading_time_minutes(words: usize) -> usize {
+    words.div_ceil(WORDS_PER_MINUTE).max(1)
+}
@@ -0,0 +1,338 @@
+use crate::{
+    db::DB,
+    error::Error::*,
+    model::{ReminderModel, ReminderState},
+    replies::not_found_message,
+    response::{FieldError, PageTotal, Paginated, ReminderResponse, TotalSource},
+    schema::{CreateReminderSchema, Page},
+    validation::check_datetime_range,
+    Result,
+};
+use chrono::{DateTime, Duration, Utc};
+use futures::StreamExt;
+use log::{error, warn};
+use mongodb::bson::{self, doc, oid::ObjectId, Document};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use serde::Serialize;
+use std::str::FromStr;
+
+const LOG_TARGET: &str = "api::reminders";
+
+/// A claimed reminder is left alone by other instances for this long.
+const CLAIM_SECS: i64 = 60;
+const MAX_DELIVERY_ATTEMPTS: i32 = 5;
+const SCHEDULER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+pub const MAX_UPCOMING_HOURS: u32 = 24 * 30;
+
+impl DB {
+    pub async fn create_reminder(
+        &self,
+        note_id: &str,
+        body: &CreateReminderSchema,
+    ) -> Result<Option<ReminderResponse>> {
+        let note_oid =
+            ObjectId::from_str(note_id).map_err(|_| InvalidIDError(note_id.to_owned()))?;
+        let at = check_datetime_range("at", body.at)?;
+        if at <= Utc::now() {
+            return Err(ValidationError(vec![FieldError {
+                field: "at".to_string(),
+                code: "IN_THE_PAST".to_string(),
+                message: "Reminder time must be in the future".to_string(),
+            }]));
+        }
+
+        let note = self
+            .collection
+            .find_one(doc! {"_id": note_oid}, None)
+            .await
+            .map_err(MongoQueryError)?;
+        if note.is_none() {
+            return Ok(None);
+        }
+
+        let reminder = ReminderModel {
+            id: ObjectId::new(),
+            noteId: note_oid,
+            at,
+            message: body.message.to_owned(),
+            state: ReminderState::Pending,
+            attempts: 0,
+            lastError: None,
+            createdAt: Utc::now(),
+        };
+        self.reminder_collection
+            .insert_one(&reminder, None)
+            .await
+            .map_err(MongoQueryError)?;
+
+        Ok(Some(reminder_to_response(&reminder)))
+    }
+
+    pub async fn list_note_reminders(
+        &self,
+        note_id: &str,
+        page: &Page,
+    ) -> Result<Paginated<ReminderResponse>> {
+        let note_oid =
+            ObjectId::from_str(note_id).map_err(|_| InvalidIDError(note_id.to_owned()))?;
+        let note = self
+            .collection
+            .find_one(doc! {"_id": note_oid}, None)
+            .await
+            .map_err(MongoQueryError)?;
+        if note.is_none() {
+            return Err(NotFoundError(not_found_message("Note", note_id)));
+        }
+        self.reminder_page(doc! {"noteId": note_oid}, page).await
+    }
+
+    /// Pending reminders across all notes due within the next `within_hours`.
+    pub async fn upcoming_reminders(
+        &self,
+        within_hours: u32,
+        page: &Page,
+    ) -> Result<Paginated<ReminderResponse>> {
+        let until = Utc::now() + Duration::hours(within_hours as i64);
+        self.reminder_page(doc! {"state": "pending", "at": {"$lte": until}}, page)
+            .await
+    }
+
+    /// Cancels a pending reminder. `None` if there is no such reminder on
+    /// the note; a conflict if it was already sent or cancelled.
+    pub async fn cancel_reminder(
+        &self,
+        note_id: &str,
+        reminder_id: &str,
+    ) -> Result<Option<ReminderResponse>> {
+        let note_oid =
+            ObjectId::from_str(note_id).map_err(|_| InvalidIDError(note_id.to_owned()))?;
+        let reminder_oid =
+            ObjectId::from_str(reminder_id).map_err(|_| InvalidIDError(reminder_id.to_owned()))?;
+
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let cancelled = self
+            .reminder_collection
+            .find_one_and_update(
+                doc! {"_id": reminder_oid, "noteId": note_oid, "state": "pending"},
+                doc! {"$set": {"state": "cancelled"}},
+                options,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+        if let Some(reminder) = cancelled {
+            return Ok(Some(reminder_to_response(&reminder)));
+        }
+
+        let existing = self
+            .reminder_collection
+            .find_one(doc! {"_id": reminder_oid, "noteId": note_oid}, None)
+            .await
+            .map_err(MongoQueryError)?;
+        match existing {
+            Some(reminder) => Err(ConflictError(format!(
+                "Reminder is already {}",
+                bson::to_bson(&reminder.state)
+                    .ok()
+                    .and_then(|state| state.as_str().map(str::to_owned))
+                    .unwrap_or_default()
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Called when a note goes away. The note is already deleted, so a
+    /// failure is only logged; the scheduler cancels orphans it meets.
+    pub async fn cancel_note_reminders(&self, note_oid: ObjectId) {
+        self.cancel_reminders_of(&[note_oid]).await;
+    }
+
+    /// `cancel_note_reminders` for many notes in one write.
+    pub async fn cancel_reminders_of(&self, note_oids: &[ObjectId]) {
+        let result = self
+            .reminder_collection
+            .update_many(
+                doc! {"noteId": {"$in": note_oids}, "state": "pending"},
+                doc! {"$set": {"state": "cancelled"}},
+                None,
+            )
+            .await;
+        if let Err(e) = result {
+            warn!(target: LOG_TARGET, "Could not cancel reminders of {:?}: {:?}", note_oids, e);
+        }
+    }
+
+    async fn reminder_page(
+        &self,
+        filter: Document,
+        page: &Page,
+    ) -> Result<Paginated<ReminderResponse>> {
+        let total = self
+            .reminder_collection
+            .count_documents(filter.clone(), None)
+            .await
+            .map_err(MongoQueryError)?;
+        let options = FindOptions::builder()
+            .sort(doc! {"at": 1, "_id": 1})
+            .skip(page.skip())
+            .limit(page.limit as i64)
+            .build();
+        let mut cursor = self
+            .reminder_collection
+            .find(filter, options)
+            .await
+            .map_err(MongoQueryError)?;
+
+        let mut reminders = Vec::new();
+        while let Some(reminder) = cursor.next().await {
+            reminders.push(reminder_to_response(&reminder.map_err(MongoQueryError)?));
+        }
+        Ok(Paginated::new(
+            reminders,
+            page,
+            PageTotal {
+                value: Some(total),
+                source: TotalSource::Count,
+            },
+        ))
+    }
+
+    /// Atomically takes one due reminder for `holder`. The claim expires, so
+    /// a reminder held by a crashed instance is picked up again later.
+    async fn claim_due_reminder(&self, holder: &str) -> Result<Option<ReminderModel>> {
+        let now = Utc::now();
+        let filter = doc! {
+            "state": "pending",
+            "at": {"$lte": now},
+            "$or": [{"claimedUntil": null}, {"claimedUntil": {"$lt": now}}],
+        };
+        let update = doc! {
+            "$set": {"claimedBy": holder, "claimedUntil": now + Duration::seconds(CLAIM_SECS)},
+            "$inc": {"attempts": 1},
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .sort(doc! {"at": 1})
+            .return_document(ReturnDocument::After)
+            .build();
+
+        self.reminder_collection
+            .find_one_and_update(filter, update, options)
+            .await
+            .map_err(MongoQueryError)
+    }
+
+    async fn finish_reminder(&self, reminder: &ReminderModel, holder: &str, update: Document) {
+        let result = self
+            .reminder_collection
+            .update_one(doc! {"_id": reminder.id, "claimedBy": holder}, update, None)
+            .await;
+        if let Err(e) = result {
+            error!(target: LOG_TARGET, "Could not update reminder {}: {:?}", reminder.id, e);
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+struct ReminderWebhookPayload<'a> {
+    reminderId: String,
+    noteId: String,
+    noteTitle: &'a str,
+    message: &'a str,
+    at: DateTime<Utc>,
+}
+
+/// Delivers due reminders to `webhook_url`. Delivery is at-least-once: a
+/// reminder is marked sent only after the webhook answered 2xx, and failed
+/// deliveries are retried with growing delays until they go to `dead`.
+pub async fn run_scheduler(db: DB, webhook_url: String) {
+    let holder = ObjectId::new().to_hex();
+    let client = reqwest::Client::new();
+
+    loop {
+        loop {
+            let reminder = match db.claim_due_reminder(&holder).await {
+                Ok(Some(reminder)) => reminder,
+                Ok(None) => break,
+                Err(e) => {
+                    error!(target: LOG_TARGET, "Could not claim reminders: {:?}", e);
+                    break;
+                }
+            };
+            deliver(&db, &client, &webhook_url, &holder, reminder).await;
+        }
+        tokio::time::sleep(SCHEDULER_INTERVAL).await;
+    }
+}
+
+async fn deliver(
+    db: &DB,
+    client: &reqwest::Client,
+    webhook_url: &str,
+    holder: &str,
+    reminder: ReminderModel,
+) {
+    let note = match db
+        .collection
+        .find_one(doc! {"_id": reminder.noteId}, None)
+        .await
+    {
+        Ok(note) => note,
+        Err(e) => {
+            error!(target: LOG_TARGET, "Could not load note for reminder {}: {:?}", reminder.id, e);
+            return;
+        }
+    };
+    let Some(note) = note else {
+        let update =
+            doc! {"$set": {"state": "cancelled"}, "$unset": {"claimedBy": "", "claimedUntil": ""}};
+        db.finish_reminder(&reminder, holder, update).await;
+        return;
+    };
+
+    let payload = ReminderWebhookPayload {
+        reminderId: reminder.id.to_hex(),
+        noteId: reminder.noteId.to_hex(),
+        noteTitle: note.get_str("title").unwrap_or(""),
+        message: &reminder.message,
+        at: reminder.at,
+    };
+    let result = client
+        .post(webhook_url)
+        .timeout(std::time::Duration::from_secs(10))
+        .json(&payload)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+
+    let update = match result {
+        Ok(_) => doc! {
+            "$set": {"state": "sent", "sentAt": Utc::now()},
+            "$unset": {"claimedBy": "", "claimedUntil": "", "lastError": ""},
+        },
+        Err(e) if reminder.attempts >= MAX_DELIVERY_ATTEMPTS => doc! {
+            "$set": {"state": "dead", "lastError": e.to_string()},
+            "$unset": {"claimedBy": "", "claimedUntil": ""},
+        },
+        // Keeping the claim until the backoff has passed delays the retry.
+        Err(e) => doc! {"$set": {
+            "lastError": e.to_string(),
+            "claimedUntil": Utc::now() + Duration::seconds(30 * (reminder.attempts as i64).pow(2)),
+        }},
+    };
+    db.finish_reminder(&reminder, holder, update).await;
+}
+
+fn reminder_to_response(reminder: &ReminderModel) -> ReminderResponse {
+    ReminderResponse {
+        id: reminder.id.to_hex(),
+        noteId: reminder.noteId.to_hex(),
+        at: reminder.at,
+        message: reminder.message.to_owned(),
+        state: reminder.state,
+        attempts: reminder.attempts,
+        lastError: reminder.lastError.to_owned(),
+        createdAt: reminder.createdAt,
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{
+    db::{escape_regex, live_note, DB},
+    error::{title_write_error, Error::*},
+    events::NoteEvent,
+    normalize::tidy_title,
+    response::{RenameNoteData, RenameNoteResponse},
+    validation, Result,
+};
+use chrono::Utc;
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use std::str::FromStr;
+
+impl DB {
+    /// Renames a note and rewrites `[[Old Title]]` links in every other note
+    /// to the new title, inside a transaction when the deployment supports
+    /// them. The title update goes first, so a title that is already taken
+    /// fails with a duplicate error before any linking note is touched.
+    pub async fn rename_note(&self, id: &str, title: &str) -> Result<Option<RenameNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+        validation::check_note_fields(Some(title), None, None, self.max_content_length)?;
+        let title = tidy_title(title);
+        let title = title.as_str();
+
+        let mut session = self.client.start_session(None).await?;
+        if self.supports_transactions {
+            session.start_transaction(None).await?;
+        }
+
+        let Some(note) = self
+            .note_collection
+            .find_one_with_session(live_note(oid), None, &mut session)
+            .await
+            .map_err(MongoQueryError)?
+        else {
+            return Ok(None);
+        };
+        let old_title = note.title;
+
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let update = doc! {"$set": {
+            "title": title,
+            "title_normalized": self.title_normalizer.normalize(title),
+            "updatedAt": Utc::now(),
+        }};
+        let renamed = self
+            .note_collection
+            .find_one_and_update_with_session(live_note(oid), update, options, &mut session)
+            .await
+            .map_err(|e| title_write_error(e, title))?
+            .ok_or_else(|| ConflictError("Note was deleted during rename".to_string()))?;
+
+        let mut linking = Vec::new();
+        if old_title != title {
+            let old_link = format!("[[{}]]", old_title);
+            let new_link = format!("[[{}]]", title);
+            let options = FindOptions::builder()
+                .projection(doc! {"content": 1})
+                .build();
+            let filter = doc! {
+                "_id": {"$ne": oid},
+                "content": {"$regex": escape_regex(&old_link)},
+            };
+            let mut cursor = self
+                .collection
+                .find_with_session(filter, options, &mut session)
+                .await
+                .map_err(MongoQueryError)?;
+            let mut rewrites = Vec::new();
+            while let Some(linking_note) = cursor.next(&mut session).await {
+                let linking_note = linking_note.map_err(MongoQueryError)?;
+                let (Ok(linking_id), Ok(content)) = (
+                    linking_note.get_object_id("_id"),
+                    linking_note.get_str("content"),
+                ) else {
+                    continue;
+                };
+                rewrites.push((linking_id, content.replace(&old_link, &new_link)));
+            }
+            drop(cursor);
+
+            for (linking_id, content) in rewrites {
+                let options = FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build();
+                let update = doc! {"$set": {"content": content, "updatedAt": Utc::now()}};
+                let updated = self
+                    .note_collection
+                    .find_one_and_update_with_session(
+                        doc! {"_id": linking_id},
+                        update,
+                        options,
+                        &mut session,
+                    )
+                    .await
+                    .map_err(MongoQueryError)?;
+                if let Some(updated) = updated {
+                    linking.push(self.doc_to_note(&updated)?);
+                }
+            }
+        }
+
+        if self.supports_transactions {
+            session.commit_transaction().await?;
+        }
+
+        let note = self.doc_to_note(&renamed)?;
+        self.events.publish(NoteEvent::Updated {
+            note: note.clone(),
+            changed_fields: vec!["title".to_string()],
+        });
+        for linking_note in &linking {
+            self.events.publish(NoteEvent::Updated {
+                note: linking_note.clone(),
+                changed_fields: vec!["content".to_string()],
+            });
+        }
+
+        Ok(Some(RenameNoteResponse {
+            status: "success".to_string(),
+            data: RenameNoteData {
+                note,
+                previous_title: old_title,
+                rewritten_links: linking.len(),
+            },
+        }))
+    }
+}
@@ -0,0 +1,178 @@
+use crate::response::{GenericResponse, PageInfo};
+use serde::Serialize;
+use warp::http::{header, HeaderValue, StatusCode};
+use warp::reply::{json, with_status, Reply, Response};
+
+/// The one message every "no such resource" response uses, whether it comes
+/// from a handler or from `Error::NotFoundError` via `handle_rejection`.
+pub fn not_found_message(resource: &str, id: &str) -> String {
+    format!("{} with ID: {} not found", resource, id)
+}
+
+pub fn not_found_body(message: String) -> GenericResponse {
+    GenericResponse {
+        status: "fail".to_string(),
+        message,
+    }
+}
+
+pub fn not_found_reply(resource: &str, id: &str) -> Response {
+    with_status(
+        json(&not_found_body(not_found_message(resource, id))),
+        StatusCode::NOT_FOUND,
+    )
+    .into_response()
+}
+
+pub fn ok_json<T: Serialize>(body: &T) -> Response {
+    with_status(json(body), StatusCode::OK).into_response()
+}
+
+pub fn created_json<T: Serialize>(body: &T) -> Response {
+    with_status(json(body), StatusCode::CREATED).into_response()
+}
+
+pub fn created_with_location<T: Serialize>(body: &T, location: &str) -> Response {
+    let mut response = created_json(body);
+    if let Ok(value) = HeaderValue::from_str(location) {
+        response.headers_mut().insert(header::LOCATION, value);
+    }
+    response
+}
+
+/// RFC 5988 `Link` header value for a list served at `path`. The request's
+/// other parameters are kept so filters carry over; `page`, `limit` and
+/// `after` are replaced. With `cursor` (an `?after=` list) only `next` is
+/// given, as cursor pages have no numbers.
+pub fn pagination_links(
+    path: &str,
+    raw_query: &str,
+    page_info: &PageInfo,
+    cursor: Option<&str>,
+) -> String {
+    let kept: Vec<(String, String)> = url::form_urlencoded::parse(raw_query.as_bytes())
+        .into_owned()
+        .filter(|(key, _)| !matches!(key.as_str(), "page" | "limit" | "after"))
+        .collect();
+    let limit = page_info.limit.to_string();
+    let link = |rel: &str, position: (&str, String)| {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(&kept)
+            .append_pair(position.0, &position.1)
+            .append_pair("limit", &limit)
+            .finish();
+        format!("<{}?{}>; rel=\"{}\"", path, query, rel)
+    };
+
+    let mut links = Vec::new();
+    if let Some(cursor) = cursor {
+        links.push(link("next", ("after", cursor.to_string())));
+        return links.join(", ");
+    }
+    links.push(link("first", ("page", "1".to_string())));
+    if page_info.page > 1 {
+        links.push(link("prev", ("page", (page_info.page - 1).to_string())));
+    }
+    if page_info.has_more {
+        links.push(link("next", ("page", (page_info.page + 1).to_string())));
+    }
+    if let Some(total_pages) = page_info.total_pages {
+        links.push(link("last", ("page", total_pages.max(1).to_string())));
+    }
+    links.join(", ")
+}
+
+pub fn no_content() -> Response {
+    with_status(json(&""), StatusCode::NO_CONTENT).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{handle_rejection, Error, ErrorContext};
+    use serde_json::json;
+
+    const ID: &str = "6630f0c2a1b2c3d4e5f60718";
+
+    async fn body(response: Response) -> serde_json::Value {
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn not_found_snapshot() -> serde_json::Value {
+        json!({
+            "status": "fail",
+            "message": "Note with ID: 6630f0c2a1b2c3d4e5f60718 not found"
+        })
+    }
+
+    #[tokio::test]
+    async fn not_found_reply_snapshot() {
+        let response = not_found_reply("Note", ID);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body(response).await, not_found_snapshot());
+    }
+
+    #[tokio::test]
+    async fn not_found_error_renders_the_same_body() {
+        let rejection = warp::reject::custom(Error::NotFoundError(not_found_message("Note", ID)));
+        let context = ErrorContext {
+            debug: true,
+            request_id: "test".to_string(),
+            method: "GET".to_string(),
+            path: format!("/api/notes/{}", ID),
+        };
+        let response = handle_rejection(rejection, context)
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(body(response).await, not_found_snapshot());
+    }
+
+    #[tokio::test]
+    async fn bad_request_error_snapshot() {
+        let rejection = warp::reject::custom(Error::BadRequestError("title is empty".to_string()));
+        let context = ErrorContext {
+            debug: false,
+            request_id: "test".to_string(),
+            method: "POST".to_string(),
+            path: "/api/notes".to_string(),
+        };
+        let response = handle_rejection(rejection, context)
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body(response).await,
+            json!({"status": "fail", "message": "title is empty", "code": "BAD_REQUEST"})
+        );
+    }
+
+    /// Not-found bodies are only ever built here, so they cannot drift.
+    #[test]
+    fn no_inline_not_found_bodies() {
+        let src = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+        for entry in std::fs::read_dir(src).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_str().unwrap().to_string();
+            if !name.ends_with(".rs") || ["replies.rs", "response.rs"].contains(&name.as_str()) {
+                continue;
+            }
+            let source = std::fs::read_to_string(&path).unwrap();
+            assert!(
+                !source.contains("GenericResponse {"),
+                "{} builds a GenericResponse inline",
+                name
+            );
+            assert!(
+                !source.contains("with ID:"),
+                "{} words a not-found message itself",
+                name
+            );
+        }
+    }
+}
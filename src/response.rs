@@ -1,5 +1,14 @@
+use crate::checklist::ChecklistItem;
+use crate::collation::CollationSpec;
+use crate::error::ErrorCode;
+use crate::events::NoteEventEnvelope;
+use crate::model::ReminderState;
+use crate::monitoring::MongoHealth;
+use crate::preview::NotePreview;
+use crate::schema::Page;
 use chrono::{DateTime, Utc};
 use serde::Serialize;
+use std::collections::BTreeMap;
 
 #[derive(Serialize)]
 pub struct GenericResponse {
@@ -7,21 +16,98 @@ pub struct GenericResponse {
     pub message: String,
 }
 
-#[allow(non_snake_case)]
+/// Error envelope; `debug` only appears on 5xx responses when
+/// `Config::debug_errors` allows it.
+#[derive(Serialize, Debug)]
+pub struct ErrorResponse {
+    pub status: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<ErrorCode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug: Option<ErrorDebug>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ErrorDebug {
+    pub error: String,
+    pub request_id: String,
+    pub route: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HealthResponse {
+    pub status: String,
+    pub message: String,
+    /// False while the optional warm-up after startup is still running.
+    pub ready: bool,
+    pub mongodb: MongoHealth,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FieldError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
 #[derive(Serialize, Debug)]
+pub struct ValidationErrorResponse {
+    pub status: String,
+    pub message: String,
+    pub errors: Vec<FieldError>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+pub struct PreconditionFailedResponse {
+    pub status: String,
+    pub message: String,
+    pub updatedAt: DateTime<Utc>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug, Clone)]
 pub struct NoteResponse {
     pub id: String,
     pub title: String,
     pub content: String,
     pub category: String,
     pub published: bool,
+    pub color: Option<String>,
     pub createdAt: DateTime<Utc>,
     pub updatedAt: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slug: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub publishedAt: Option<DateTime<Utc>>,
+    pub archived: bool,
+    pub pinned: bool,
+    pub tags: Vec<String>,
+    pub items: Vec<ChecklistItem>,
+    pub position: i64,
+    /// Computed from `content`, see `reading_time`.
+    pub word_count: usize,
+    pub reading_time_minutes: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deletedAt: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<NotePreview>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug)]
+pub struct DraftResponse {
+    pub content: String,
+    pub savedAt: DateTime<Utc>,
+    pub draft_newer_than_content: bool,
 }
 
 #[derive(Serialize, Debug)]
 pub struct NoteData {
     pub note: NoteResponse,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub draft: Option<DraftResponse>,
 }
 
 #[derive(Serialize, Debug)]
@@ -30,9 +116,550 @@ pub struct SingleNoteResponse {
     pub data: NoteData,
 }
 
+#[derive(Serialize, Debug)]
+pub struct DraftData {
+    pub draft: DraftResponse,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SingleDraftResponse {
+    pub status: String,
+    pub data: DraftData,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MergeSourceResponse {
+    pub id: String,
+    /// Moved to the trash, where it can be restored from.
+    pub trashed: bool,
+    /// Deleted for good.
+    pub deleted: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MergeNoteData {
+    pub note: NoteResponse,
+    pub source: MergeSourceResponse,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MergeNoteResponse {
+    pub status: String,
+    pub data: MergeNoteData,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TitleSuggestion {
+    pub id: String,
+    pub title: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SuggestResponse {
+    pub status: String,
+    pub results: usize,
+    pub suggestions: Vec<TitleSuggestion>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RenameNoteData {
+    pub note: NoteResponse,
+    pub previous_title: String,
+    /// Other notes whose `[[Title]]` links were rewritten.
+    pub rewritten_links: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RenameNoteResponse {
+    pub status: String,
+    pub data: RenameNoteData,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BootstrapResponse {
+    pub status: String,
+    pub already_bootstrapped: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PageInfo {
+    pub page: u64,
+    pub limit: u64,
+    pub total: Option<u64>,
+    pub total_pages: Option<u64>,
+    pub has_more: bool,
+    pub total_estimated: bool,
+    pub total_source: TotalSource,
+}
+
+/// Where a list total came from, so clients can tell exact from "≈".
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TotalSource {
+    /// The maintained counter; exact up to drift fixed by reconciliation.
+    Counter,
+    /// A `count_documents` run for this request.
+    Count,
+    /// The count timed out; `total` is null.
+    Unavailable,
+    /// The request asked not to count; `total` is null.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PageTotal {
+    pub value: Option<u64>,
+    pub source: TotalSource,
+}
+
+impl PageInfo {
+    pub fn new(page: &Page, items: usize, total: PageTotal) -> Self {
+        let has_more = match total.value {
+            Some(total) => page.page.saturating_mul(page.limit) < total,
+            // Without a total, a full page is the best hint there is more.
+            None => items as u64 == page.limit,
+        };
+        Self {
+            page: page.page,
+            limit: page.limit,
+            total: total.value,
+            total_pages: total.value.map(|total| total.div_ceil(page.limit)),
+            has_more,
+            total_estimated: total.source != TotalSource::Count,
+            total_source: total.source,
+        }
+    }
+}
+
+/// The response shape of every list endpoint.
+#[derive(Serialize, Debug)]
+pub struct Paginated<T> {
+    pub status: String,
+    pub items: Vec<T>,
+    #[serde(flatten)]
+    pub page_info: PageInfo,
+}
+
+impl<T> Paginated<T> {
+    pub fn new(items: Vec<T>, page: &Page, total: PageTotal) -> Self {
+        Self {
+            status: "success".to_string(),
+            page_info: PageInfo::new(page, items.len(), total),
+            items,
+        }
+    }
+}
+
+/// Deprecated shape of `GET /api/notes`: `Paginated` with the items under
+/// `notes` and their count under `results`, kept until clients have moved
+/// to `items`.
 #[derive(Serialize, Debug)]
 pub struct NoteListResponse {
     pub status: String,
     pub results: usize,
-    pub notes: Vec<NoteResponse>,
+    #[serde(flatten)]
+    pub page_info: Option<PageInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collation: Option<CollationSpec>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub facets: Option<NoteFacets>,
+    /// Set for `?updatedSince=` requests: the watermark for the next sync.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_time: Option<DateTime<Utc>>,
+    /// `?after=` value for the next page; null when there is none or the
+    /// list is explicitly sorted.
+    pub next_cursor: Option<String>,
+    /// Whether another page follows, known without counting.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub has_next: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub has_prev: Option<bool>,
+    pub notes: Vec<ListedNote>,
+}
+
+impl<T: Into<ListedNote>> From<Paginated<T>> for NoteListResponse {
+    fn from(page: Paginated<T>) -> Self {
+        Self {
+            status: page.status,
+            results: page.items.len(),
+            page_info: Some(page.page_info),
+            collation: None,
+            facets: None,
+            server_time: None,
+            next_cursor: None,
+            has_next: None,
+            has_prev: None,
+            notes: page.items.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// `NoteListResponse` for an explicit set of ids, plus the ids that matched
+/// no note.
+#[derive(Serialize, Debug)]
+pub struct BatchGetResponse {
+    #[serde(flatten)]
+    pub list: NoteListResponse,
+    pub missing: Vec<String>,
+}
+
+/// Only the fields a `?fields=` list request asked for; `id` is always
+/// there.
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug, Default)]
+pub struct PartialNoteResponse {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub published: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub createdAt: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updatedAt: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slug: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub publishedAt: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archived: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pinned: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub items: Option<Vec<ChecklistItem>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<i64>,
+    /// Only when `content` was selected.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub word_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reading_time_minutes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deletedAt: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<NotePreview>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum ListedNote {
+    Full(NoteResponse),
+    Partial(PartialNoteResponse),
+}
+
+impl ListedNote {
+    pub fn id(&self) -> &str {
+        match self {
+            ListedNote::Full(note) => &note.id,
+            ListedNote::Partial(note) => &note.id,
+        }
+    }
+}
+
+impl From<NoteResponse> for ListedNote {
+    fn from(note: NoteResponse) -> Self {
+        ListedNote::Full(note)
+    }
+}
+
+impl From<PartialNoteResponse> for ListedNote {
+    fn from(note: PartialNoteResponse) -> Self {
+        ListedNote::Partial(note)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct CategoryFacet {
+    pub value: String,
+    pub label: String,
+    pub count: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PublishedFacet {
+    pub value: bool,
+    pub count: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CountResponse {
+    pub status: String,
+    pub count: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BulkUpdateResponse {
+    pub status: String,
+    pub matched_count: u64,
+    pub modified_count: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BulkDeleteResponse {
+    pub status: String,
+    /// With `dry_run`, what would have been deleted.
+    pub deleted_count: u64,
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PurgeTrashResponse {
+    pub status: String,
+    pub purged_count: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BulkCreateResponse {
+    pub status: String,
+    pub created: usize,
+    pub failed: usize,
+    /// One entry per submitted item, in submission order.
+    pub results: Vec<BulkCreateResult>,
+}
+
+/// Either `id` or `error` is set.
+#[derive(Serialize, Debug)]
+pub struct BulkCreateResult {
+    pub index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<BulkCreateError>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BulkCreateError {
+    pub code: String,
+    pub message: String,
+}
+
+impl BulkCreateResult {
+    pub fn created(index: usize, id: String) -> Self {
+        BulkCreateResult {
+            index,
+            id: Some(id),
+            error: None,
+        }
+    }
+
+    pub fn failed(index: usize, code: &str, message: String) -> Self {
+        BulkCreateResult {
+            index,
+            id: None,
+            error: Some(BulkCreateError {
+                code: code.to_string(),
+                message,
+            }),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct CategoriesResponse {
+    pub status: String,
+    pub results: usize,
+    pub categories: Vec<CategoryFacet>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NoteFacets {
+    pub category: Vec<CategoryFacet>,
+    pub published: Vec<PublishedFacet>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InboundNoteData {
+    pub id: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct InboundNoteResponse {
+    pub status: String,
+    pub data: InboundNoteData,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PollData {
+    pub events: Vec<NoteEventEnvelope>,
+    pub last_seq: u64,
+    pub complete: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PollResponse {
+    pub status: String,
+    pub data: PollData,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug)]
+pub struct ReminderResponse {
+    pub id: String,
+    pub noteId: String,
+    pub at: DateTime<Utc>,
+    pub message: String,
+    pub state: ReminderState,
+    pub attempts: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lastError: Option<String>,
+    pub createdAt: DateTime<Utc>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug)]
+pub struct NoteRevisionResponse {
+    pub id: String,
+    pub noteId: String,
+    pub revision: i64,
+    pub title: String,
+    pub content: String,
+    pub category: String,
+    pub published: bool,
+    pub updatedAt: DateTime<Utc>,
+    pub createdAt: DateTime<Utc>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug)]
+pub struct CommentResponse {
+    pub id: String,
+    pub noteId: String,
+    pub author: String,
+    pub body: String,
+    pub createdAt: DateTime<Utc>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Debug)]
+pub struct AttachmentResponse {
+    pub id: String,
+    pub noteId: String,
+    pub filename: String,
+    pub size: u64,
+    pub contentType: String,
+    pub uploadedAt: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AttachmentData {
+    pub attachment: AttachmentResponse,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SingleAttachmentResponse {
+    pub status: String,
+    pub data: AttachmentData,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AttachmentListResponse {
+    pub status: String,
+    pub results: usize,
+    pub attachments: Vec<AttachmentResponse>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CommentData {
+    pub comment: CommentResponse,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SingleCommentResponse {
+    pub status: String,
+    pub data: CommentData,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReminderData {
+    pub reminder: ReminderResponse,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SingleReminderResponse {
+    pub status: String,
+    pub data: ReminderData,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigSource {
+    Env,
+    File,
+    Default,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConfigEntry {
+    pub value: serde_json::Value,
+    pub source: ConfigSource,
+}
+
+impl ConfigEntry {
+    pub fn new<T: Serialize>(value: T, source: ConfigSource) -> Self {
+        Self {
+            value: serde_json::to_value(value).unwrap_or(serde_json::Value::Null),
+            source,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct AdminConfigResponse {
+    pub status: String,
+    pub data: BTreeMap<&'static str, ConfigEntry>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RecountData {
+    pub total: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RecountResponse {
+    pub status: String,
+    pub data: RecountData,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SiteExportError {
+    pub id: String,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SiteExportReport {
+    pub exported: usize,
+    pub skipped: usize,
+    pub total_bytes: u64,
+    pub errors: Vec<SiteExportError>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SiteExportResponse {
+    pub status: String,
+    pub data: SiteExportReport,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DevEchoData {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub client_ip: Option<String>,
+    pub headers: BTreeMap<String, String>,
+    pub body: serde_json::Value,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DevEchoResponse {
+    pub status: String,
+    pub data: DevEchoData,
 }
@@ -0,0 +1,403 @@
+use crate::{
+    db::{live_note, DB},
+    error::{title_write_error, Error::*},
+    events::NoteEvent,
+    model::{NoteModel, NoteRevisionModel},
+    normalize::tidy_title,
+    replies::not_found_message,
+    response::{
+        NoteData, NoteRevisionResponse, PageTotal, Paginated, SingleNoteResponse, TotalSource,
+    },
+    schema::{Page, UpdateNoteSchema},
+    Result,
+};
+use chrono::Utc;
+use futures::StreamExt;
+use log::{error, warn};
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use mongodb::ClientSession;
+use std::str::FromStr;
+
+const LOG_TARGET: &str = "api::revisions";
+
+impl DB {
+    /// Stores `previous`, the note as it was before an edit, as its next
+    /// revision and drops whatever falls outside `max_note_revisions`. The
+    /// edit has already happened, so failures are only logged.
+    pub(crate) async fn record_revision(&self, previous: &NoteModel) {
+        if self.max_note_revisions == 0 {
+            return;
+        }
+
+        let snapshot = revision_snapshot(previous);
+        if let Err(e) = self.revision_collection.insert_one(&snapshot, None).await {
+            error!(
+                target: LOG_TARGET,
+                "Could not record revision {} of {}: {:?}",
+                snapshot.revision, previous.id, e
+            );
+            return;
+        }
+
+        if let Err(e) = self
+            .revision_collection
+            .delete_many(self.trimmed_revisions(&snapshot), None)
+            .await
+        {
+            warn!(target: LOG_TARGET, "Could not trim revisions of {}: {:?}", previous.id, e);
+        }
+    }
+
+    /// `record_revision` as part of the transaction of `session`. A failure
+    /// is returned, so the edit it belongs to is abandoned with it.
+    pub(crate) async fn record_revision_with_session(
+        &self,
+        previous: &NoteModel,
+        session: &mut ClientSession,
+    ) -> Result<()> {
+        if self.max_note_revisions == 0 {
+            return Ok(());
+        }
+
+        let snapshot = revision_snapshot(previous);
+        self.revision_collection
+            .insert_one_with_session(&snapshot, None, session)
+            .await
+            .map_err(MongoQueryError)?;
+        self.revision_collection
+            .delete_many_with_session(self.trimmed_revisions(&snapshot), None, session)
+            .await
+            .map_err(MongoQueryError)?;
+        Ok(())
+    }
+
+    /// Filter for the revisions of `newest`'s note that no longer fit in
+    /// `max_note_revisions` once `newest` is stored.
+    fn trimmed_revisions(&self, newest: &NoteRevisionModel) -> Document {
+        let oldest_kept = newest.revision - self.max_note_revisions as i64 + 1;
+        doc! {"noteId": newest.noteId, "revision": {"$lt": oldest_kept}}
+    }
+
+    /// Called once notes are gone for good. The notes are already deleted,
+    /// so a failure is only logged.
+    pub async fn delete_revisions_of(&self, note_oids: &[ObjectId]) {
+        let result = self
+            .revision_collection
+            .delete_many(doc! {"noteId": {"$in": note_oids}}, None)
+            .await;
+        if let Err(e) = result {
+            warn!(target: LOG_TARGET, "Could not delete revisions of {:?}: {:?}", note_oids, e);
+        }
+    }
+
+    /// Puts the fields of revision `revision` back on the note. The state
+    /// it replaces becomes a revision of its own, so a restore can be
+    /// undone by restoring that one. `None` if the note does not exist; an
+    /// unknown revision is a `NotFoundError`.
+    pub async fn restore_revision(
+        &self,
+        note_id: &str,
+        revision: i64,
+    ) -> Result<Option<SingleNoteResponse>> {
+        let note_oid =
+            ObjectId::from_str(note_id).map_err(|_| InvalidIDError(note_id.to_owned()))?;
+        let Some(snapshot) = self
+            .revision_collection
+            .find_one(doc! {"noteId": note_oid, "revision": revision}, None)
+            .await
+            .map_err(MongoQueryError)?
+        else {
+            let note = self
+                .collection
+                .find_one(live_note(note_oid), None)
+                .await
+                .map_err(MongoQueryError)?;
+            return match note {
+                Some(_) => Err(NotFoundError(not_found_message(
+                    "Revision",
+                    &revision.to_string(),
+                ))),
+                None => Ok(None),
+            };
+        };
+
+        let title = snapshot.title;
+        let body = UpdateNoteSchema {
+            title: Some(Some(title.clone())),
+            content: Some(Some(snapshot.content)),
+            category: Some(snapshot.category),
+            published: Some(snapshot.published),
+            ..UpdateNoteSchema::default()
+        };
+        let mut update = self.note_update(&body)?;
+        // Title and content are always set, so `$set` is there.
+        if let Ok(set) = update.get_document_mut("$set") {
+            set.insert("updatedAt", Utc::now());
+        }
+        update.insert("$inc", doc! {"revision": 1});
+
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::Before)
+            .build();
+        let previous = self
+            .note_collection
+            .find_one_and_update(live_note(note_oid), update, options)
+            .await
+            // Another note may have taken the title since.
+            .map_err(|e| title_write_error(e, &tidy_title(&title)))?;
+        let Some(previous) = previous else {
+            return Ok(None);
+        };
+        self.record_revision(&previous).await;
+        if body.published == Some(Some(true)) && previous.published != Some(true) {
+            self.stamp_published(&[note_oid]).await?;
+        }
+
+        let Some(note) = self
+            .note_collection
+            .find_one(live_note(note_oid), None)
+            .await
+            .map_err(MongoQueryError)?
+        else {
+            return Ok(None);
+        };
+        let note_response = SingleNoteResponse {
+            status: "success".to_string(),
+            data: NoteData {
+                note: self.doc_to_note(&note)?,
+                draft: None,
+            },
+        };
+
+        self.events
+            .publish(NoteEvent::updated(&note_response, &body));
+        if previous.published != note.published {
+            self.events.publish(NoteEvent::published(
+                note_id,
+                note.published.unwrap_or(false),
+            ));
+        }
+
+        Ok(Some(note_response))
+    }
+
+    /// The note's revisions, newest first.
+    pub async fn list_note_revisions(
+        &self,
+        note_id: &str,
+        page: &Page,
+    ) -> Result<Paginated<NoteRevisionResponse>> {
+        let note_oid =
+            ObjectId::from_str(note_id).map_err(|_| InvalidIDError(note_id.to_owned()))?;
+        let note = self
+            .collection
+            .find_one(live_note(note_oid), None)
+            .await
+            .map_err(MongoQueryError)?;
+        if note.is_none() {
+            return Err(NotFoundError(not_found_message("Note", note_id)));
+        }
+
+        let filter = doc! {"noteId": note_oid};
+        let total = self
+            .revision_collection
+            .count_documents(filter.clone(), None)
+            .await
+            .map_err(MongoQueryError)?;
+        let options = FindOptions::builder()
+            .sort(doc! {"revision": -1})
+            .skip(page.skip())
+            .limit(page.limit as i64)
+            .build();
+        let mut cursor = self
+            .revision_collection
+            .find(filter, options)
+            .await
+            .map_err(MongoQueryError)?;
+
+        let mut revisions = Vec::new();
+        while let Some(revision) = cursor.next().await {
+            revisions.push(revision_to_response(&revision.map_err(MongoQueryError)?));
+        }
+        Ok(Paginated::new(
+            revisions,
+            page,
+            PageTotal {
+                value: Some(total),
+                source: TotalSource::Count,
+            },
+        ))
+    }
+}
+
+/// `previous`, the note as it was before an edit, as its next revision.
+fn revision_snapshot(previous: &NoteModel) -> NoteRevisionModel {
+    NoteRevisionModel {
+        id: ObjectId::new(),
+        noteId: previous.id,
+        revision: previous.revision + 1,
+        title: previous.title.to_owned(),
+        content: previous.content.to_owned(),
+        category: previous.category.to_owned(),
+        published: previous.published,
+        updatedAt: previous.updatedAt,
+        createdAt: Utc::now(),
+    }
+}
+
+fn revision_to_response(revision: &NoteRevisionModel) -> NoteRevisionResponse {
+    NoteRevisionResponse {
+        id: revision.id.to_hex(),
+        noteId: revision.noteId.to_hex(),
+        revision: revision.revision,
+        title: revision.title.to_owned(),
+        content: revision.content.to_owned(),
+        category: revision.category.to_owned().unwrap_or_default(),
+        published: revision.published.unwrap_or_default(),
+        updatedAt: revision.updatedAt,
+        createdAt: revision.createdAt,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::CreateNoteSchema;
+    use crate::test_support::{config, drop_live_db, live_db};
+
+    fn content(content: &str) -> UpdateNoteSchema {
+        UpdateNoteSchema {
+            content: Some(Some(content.to_string())),
+            ..UpdateNoteSchema::default()
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn restoring_the_restore_round_trips() {
+        let db = live_db(&config()).await;
+        let body: CreateNoteSchema =
+            serde_json::from_value(serde_json::json!({"title": "Plan", "content": "first"}))
+                .unwrap();
+        let id = db.create_note(&body).await.unwrap().data.note.id;
+        db.edit_note(&id, &content("second"), None).await.unwrap();
+
+        // Revision 1 holds "first"; restoring it saves "second" as revision 2.
+        let restored = db.restore_revision(&id, 1).await.unwrap().unwrap();
+        assert_eq!(restored.data.note.content, "first");
+        let undone = db.restore_revision(&id, 2).await.unwrap().unwrap();
+        assert_eq!(undone.data.note.content, "second");
+
+        assert!(matches!(
+            db.restore_revision(&id, 99).await,
+            Err(NotFoundError(_))
+        ));
+        let missing = ObjectId::new().to_hex();
+        assert!(db.restore_revision(&missing, 1).await.unwrap().is_none());
+        drop_live_db(&db).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn a_revision_whose_title_was_taken_is_a_duplicate() {
+        let db = live_db(&config()).await;
+        let body: CreateNoteSchema =
+            serde_json::from_value(serde_json::json!({"title": "Plan", "content": "steps"}))
+                .unwrap();
+        let id = db.create_note(&body).await.unwrap().data.note.id;
+        let rename = UpdateNoteSchema {
+            title: Some(Some("Old plan".to_string())),
+            ..UpdateNoteSchema::default()
+        };
+        db.edit_note(&id, &rename, None).await.unwrap();
+        db.create_note(&body).await.unwrap();
+
+        match db.restore_revision(&id, 1).await {
+            Err(MongoDuplicateError { value, .. }) => assert_eq!(value, "Plan"),
+            other => panic!("expected a duplicate error, got {:?}", other.map(|_| ())),
+        }
+        drop_live_db(&db).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn a_merge_records_the_target_only_when_it_happens() {
+        use crate::schema::{MergeSourceFate, MergeStrategy};
+
+        let db = live_db(&config()).await;
+        let note = |title: &str, content: &str| -> CreateNoteSchema {
+            serde_json::from_value(serde_json::json!({"title": title, "content": content})).unwrap()
+        };
+        let target = db.create_note(&note("Plan", "steps")).await.unwrap();
+        let target = target.data.note.id;
+        let source = db.create_note(&note("Ideas", "more")).await.unwrap();
+        let source = source.data.note.id;
+
+        let missing = ObjectId::new().to_hex();
+        let merge = db.merge_notes(
+            &target,
+            &missing,
+            MergeStrategy::Append,
+            MergeSourceFate::Keep,
+        );
+        assert!(merge.await.unwrap().is_none());
+        assert!(matches!(
+            db.restore_revision(&target, 1).await,
+            Err(NotFoundError(_))
+        ));
+
+        let merge = db.merge_notes(
+            &target,
+            &source,
+            MergeStrategy::Append,
+            MergeSourceFate::Keep,
+        );
+        assert!(merge
+            .await
+            .unwrap()
+            .unwrap()
+            .data
+            .note
+            .content
+            .contains("more"));
+        let undone = db.restore_revision(&target, 1).await.unwrap().unwrap();
+        assert_eq!(undone.data.note.content, "steps");
+        drop_live_db(&db).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn a_replacement_can_be_undone() {
+        let db = live_db(&config()).await;
+        let note = |content: &str| -> CreateNoteSchema {
+            serde_json::from_value(serde_json::json!({"title": "Plan", "content": content}))
+                .unwrap()
+        };
+        let id = db.create_note(&note("first")).await.unwrap().data.note.id;
+        let (replaced, created) = db
+            .replace_note(&id, &note("second"), None, false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!created);
+        assert_eq!(replaced.data.note.content, "second");
+
+        let undone = db.restore_revision(&id, 1).await.unwrap().unwrap();
+        assert_eq!(undone.data.note.content, "first");
+
+        // A note a PUT creates has no earlier state to keep.
+        let fresh = ObjectId::new().to_hex();
+        let (_, created) = db
+            .replace_note(&fresh, &note("new"), None, false)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(created);
+        assert!(matches!(
+            db.restore_revision(&fresh, 1).await,
+            Err(NotFoundError(_))
+        ));
+        drop_live_db(&db).await;
+    }
+}
@@ -0,0 +1,890 @@
+use crate::attachments::MULTIPART_OVERHEAD_BYTES;
+use crate::client_ip::with_client_ip;
+use crate::config::{
+    with_admin_token, with_config, with_dangerous_endpoints, with_dev_tools, with_error_context,
+    Config,
+};
+use crate::error::ErrorContext;
+use crate::inbound::{InboundRateLimiter, MAX_INBOUND_BODY_BYTES};
+use crate::schema::{
+    normalize_legacy_fields, parse_query_string, BulkDeleteOptions, BulkUpdateOptions,
+    CategoriesOptions, DeleteNoteOptions, DevFailOptions, EditNoteOptions, FilterOptions,
+    GetNoteOptions, LegacyFields, MergeNoteOptions, Page, PageParams, PollOptions, SearchOptions,
+    SiteExportOptions, SuggestOptions, UpcomingRemindersOptions,
+};
+use crate::signing::{signed, signed_headers, verified_body, RequestVerifier};
+use crate::{db::DB, error, error::Error::BadRequestError, handler};
+use serde::de::DeserializeOwned;
+use std::convert::Infallible;
+use warp::{http::Method, hyper::body::Bytes, reject, Filter, Rejection, Reply};
+
+/// Every literal segment registered directly under `/api/notes/`.
+///
+/// Routes for these segments are registered before the `:id` routes, and
+/// `note_id()` additionally refuses to treat any of them as an id, so
+/// `/api/notes/search` can never be parsed as a (bad) note id no matter how
+/// the `or` chain below is ordered. `note_literal()` panics at startup for a
+/// segment missing from this list.
+pub const NOTE_LITERAL_SEGMENTS: &[&str] = &[
+    "all",
+    "batch-get",
+    "bootstrap",
+    "bulk",
+    "categories",
+    "count",
+    "export",
+    "export-zip",
+    "poll",
+    "search",
+    "suggest",
+    "trash",
+];
+
+pub fn routes(
+    db: DB,
+    config: Config,
+) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+    let cors = warp::cors()
+        .allow_methods(&[
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ])
+        .allow_origins(vec!["http://localhost:3000"])
+        .allow_headers(vec![
+            "content-type",
+            "if-unmodified-since",
+            "x-admin-token",
+            "x-signature",
+        ])
+        .expose_headers(vec!["deprecation", "link", "x-last-seq"])
+        .allow_credentials(true);
+
+    // Every route that changes notes checks X-Signature on the way in.
+    let verifier = RequestVerifier::new(&config);
+
+    let note_router = warp::path!("api" / "notes");
+    let note_router_id = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path::end());
+    let health_checker = warp::path!("api" / "healthchecker")
+        .and(warp::get())
+        .and(with_db(db.clone()))
+        .and_then(handler::health_checker_handler);
+
+    let note_literal_routes = note_literal("bootstrap")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(with_admin_token(config.clone()))
+        .and(with_config(config.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::bootstrap_notes_handler)
+        .or(note_literal("bulk")
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(json_body(verifier.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::bulk_create_notes_handler))
+        .or(note_literal("batch-get")
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_db(db.clone()))
+            .and_then(handler::batch_get_notes_handler))
+        .or(note_literal("export")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(with_db(db.clone()))
+            .and_then(handler::export_notes_ndjson_handler))
+        .or(note_literal("export-zip")
+            .and(warp::path::end())
+            .and(warp::post())
+            .and(warp::body::json())
+            .and(with_db(db.clone()))
+            .and_then(handler::export_notes_zip_handler))
+        .or(note_literal("poll")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(query::<PollOptions>())
+            .and(with_db(db.clone()))
+            .and_then(handler::poll_notes_handler))
+        .or(note_literal("search")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(page_params(config.max_page_limit))
+            .and(query::<SearchOptions>())
+            .and(with_db(db.clone()))
+            .and_then(handler::search_notes_handler))
+        .or(note_literal("trash")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(page_params(config.max_page_limit))
+            .and(with_db(db.clone()))
+            .and_then(handler::trash_notes_handler))
+        .or(note_literal("all")
+            .and(warp::path::end())
+            .and(warp::delete())
+            .and(with_dangerous_endpoints(config.clone()))
+            .and(signed(verifier.clone()))
+            .and(warp::header::optional::<String>("x-confirm-delete"))
+            .and(with_db(db.clone()))
+            .and_then(handler::delete_all_notes_handler))
+        .or(note_literal("trash")
+            .and(warp::path::end())
+            .and(warp::delete())
+            .and(signed(verifier.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::empty_trash_handler))
+        .or(note_literal("suggest")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(query::<SuggestOptions>())
+            .and(with_db(db.clone()))
+            .and_then(handler::suggest_titles_handler))
+        .or(note_literal("categories")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(query::<CategoriesOptions>())
+            .and(with_db(db.clone()))
+            .and_then(handler::list_categories_handler))
+        .or(note_literal("count")
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(filter_options())
+            .and(with_db(db.clone()))
+            .and_then(handler::count_notes_handler));
+
+    let note_routes = note_router
+        .and(warp::post())
+        .and(json_body_with_legacy_fields(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::create_note_handler)
+        .or(note_router
+            .and(warp::get())
+            .and(page_params(config.max_page_limit))
+            .and(filter_options())
+            .and(raw_query())
+            .and(with_db(db.clone()))
+            .and_then(handler::notes_list_handler))
+        .or(note_router
+            .and(warp::patch())
+            .and(json_body_with_legacy_fields(verifier.clone()))
+            .and(filter_options())
+            .and(query::<BulkUpdateOptions>())
+            .and(with_db(db.clone()))
+            .and_then(handler::bulk_update_notes_handler))
+        .or(note_router
+            .and(warp::delete())
+            .and(signed(verifier.clone()))
+            .and(filter_options())
+            .and(query::<BulkDeleteOptions>())
+            .and(with_db(db.clone()))
+            .and_then(handler::bulk_delete_notes_handler));
+
+    let note_routes_id = note_router_id
+        .clone()
+        .and(warp::patch())
+        .and(content_type(handler::JSON_PATCH_CONTENT_TYPE))
+        .and(json_body(verifier.clone()))
+        .and(warp::header::optional::<String>("if-unmodified-since"))
+        .and(with_config(config.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::json_patch_note_handler)
+        .or(note_router_id
+            .clone()
+            .and(warp::patch())
+            .and(json_body_with_legacy_fields(verifier.clone()))
+            .and(query::<EditNoteOptions>())
+            .and(warp::header::optional::<String>("if-unmodified-since"))
+            .and(with_config(config.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::edit_note_handler))
+        .or(note_router_id
+            .clone()
+            .and(warp::put())
+            .and(json_body_with_legacy_fields(verifier.clone()))
+            .and(warp::header::optional::<String>("if-unmodified-since"))
+            .and(with_config(config.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::replace_note_handler))
+        .or(note_router_id
+            .clone()
+            .and(warp::get())
+            .and(query::<GetNoteOptions>())
+            .and(with_db(db.clone()))
+            .and_then(handler::get_note_handler))
+        .or(note_router_id
+            .and(warp::delete())
+            .and(signed(verifier.clone()))
+            .and(query::<DeleteNoteOptions>())
+            .and(warp::header::optional::<String>("if-unmodified-since"))
+            .and(with_db(db.clone()))
+            .and_then(handler::delete_note_handler));
+
+    let note_router_draft = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path!("draft"));
+    let draft_routes = note_router_draft
+        .clone()
+        .and(warp::put())
+        .and(json_body(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::save_draft_handler)
+        .or(note_router_draft
+            .and(warp::delete())
+            .and(signed(verifier.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::discard_draft_handler))
+        .or(warp::path!("api" / "notes" / ..)
+            .and(note_id())
+            .and(warp::path!("draft" / "commit"))
+            .and(warp::post())
+            .and(signed(verifier.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::commit_draft_handler));
+
+    let merge_routes = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path!("merge"))
+        .and(warp::post())
+        .and(query::<MergeNoteOptions>())
+        .and(json_body(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::merge_note_handler);
+
+    let rename_routes = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path!("rename"))
+        .and(warp::post())
+        .and(json_body(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::rename_note_handler);
+
+    let restore_routes = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path!("restore"))
+        .and(warp::post())
+        .and(signed(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::restore_note_handler);
+
+    let duplicate_routes = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path!("duplicate"))
+        .and(warp::post())
+        .and(signed(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::duplicate_note_handler);
+
+    let publish_routes = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path!("publish"))
+        .and(warp::post())
+        .and(signed(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::publish_note_handler)
+        .or(warp::path!("api" / "notes" / ..)
+            .and(note_id())
+            .and(warp::path!("unpublish"))
+            .and(warp::post())
+            .and(signed(verifier.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::unpublish_note_handler));
+
+    let archive_routes = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path!("archive"))
+        .and(warp::post())
+        .and(signed(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::archive_note_handler)
+        .or(warp::path!("api" / "notes" / ..)
+            .and(note_id())
+            .and(warp::path!("unarchive"))
+            .and(warp::post())
+            .and(signed(verifier.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::unarchive_note_handler));
+
+    let pin_routes = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path!("pin"))
+        .and(warp::post())
+        .and(signed(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::pin_note_handler)
+        .or(warp::path!("api" / "notes" / ..)
+            .and(note_id())
+            .and(warp::path!("unpin"))
+            .and(warp::post())
+            .and(signed(verifier.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::unpin_note_handler));
+
+    let move_routes = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path!("move"))
+        .and(warp::post())
+        .and(json_body(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::move_note_handler);
+
+    let checklist_routes = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path!("items"))
+        .and(warp::post())
+        .and(json_body(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::add_checklist_item_handler)
+        .or(warp::path!("api" / "notes" / ..)
+            .and(note_id())
+            .and(warp::path!("items" / usize))
+            .and(warp::patch())
+            .and(json_body(verifier.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::update_checklist_item_handler))
+        .or(warp::path!("api" / "notes" / ..)
+            .and(note_id())
+            .and(warp::path!("items" / usize))
+            .and(warp::delete())
+            .and(signed(verifier.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::remove_checklist_item_handler));
+
+    let tag_routes = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path!("tags"))
+        .and(warp::post())
+        .and(json_body(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::add_tag_handler)
+        .or(warp::path!("api" / "notes" / ..)
+            .and(note_id())
+            .and(warp::path("tags"))
+            .and(warp::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::delete())
+            .and(signed(verifier.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::remove_tag_handler));
+
+    let revision_routes = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path!("revisions"))
+        .and(warp::get())
+        .and(page_params(config.max_page_limit))
+        .and(with_db(db.clone()))
+        .and_then(handler::note_revisions_handler)
+        .or(warp::path!("api" / "notes" / ..)
+            .and(note_id())
+            .and(warp::path!("revisions" / i64 / "restore"))
+            .and(warp::post())
+            .and(signed(verifier.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::restore_revision_handler));
+
+    let attachment_routes = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path!("attachments"))
+        .and(warp::post())
+        .and(signed_headers(verifier.clone()))
+        .and(
+            warp::multipart::form()
+                .max_length(config.max_attachment_bytes + MULTIPART_OVERHEAD_BYTES),
+        )
+        .and(with_db(db.clone()))
+        .and_then(handler::upload_attachment_handler)
+        .or(warp::path!("api" / "notes" / ..)
+            .and(note_id())
+            .and(warp::path!("attachments"))
+            .and(warp::get())
+            .and(with_db(db.clone()))
+            .and_then(handler::note_attachments_handler))
+        .or(warp::path!("api" / "attachments" / String)
+            .and(warp::get())
+            .and(with_db(db.clone()))
+            .and_then(handler::download_attachment_handler));
+
+    let comment_routes = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path!("comments"))
+        .and(warp::post())
+        .and(json_body(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::create_comment_handler)
+        .or(warp::path!("api" / "notes" / ..)
+            .and(note_id())
+            .and(warp::path!("comments"))
+            .and(warp::get())
+            .and(page_params(config.max_page_limit))
+            .and(with_db(db.clone()))
+            .and_then(handler::note_comments_handler))
+        .or(warp::path!("api" / "notes" / ..)
+            .and(note_id())
+            .and(warp::path!("comments" / String))
+            .and(warp::delete())
+            .and(signed(verifier.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::delete_comment_handler));
+
+    let category_routes = warp::path!("api" / "categories" / "rename")
+        .and(warp::post())
+        .and(json_body(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::rename_category_handler);
+
+    let note_router_reminders = warp::path!("api" / "notes" / ..)
+        .and(note_id())
+        .and(warp::path("reminders"));
+    let reminder_routes = note_router_reminders
+        .clone()
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(json_body(verifier.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::create_reminder_handler)
+        .or(note_router_reminders
+            .clone()
+            .and(warp::path::end())
+            .and(warp::get())
+            .and(page_params(config.max_page_limit))
+            .and(with_db(db.clone()))
+            .and_then(handler::note_reminders_handler))
+        .or(note_router_reminders
+            .and(warp::path::param::<String>())
+            .and(warp::path::end())
+            .and(warp::delete())
+            .and(signed(verifier))
+            .and(with_db(db.clone()))
+            .and_then(handler::cancel_reminder_handler))
+        .or(warp::path!("api" / "reminders" / "upcoming")
+            .and(warp::get())
+            .and(page_params(config.max_page_limit))
+            .and(query::<UpcomingRemindersOptions>())
+            .and(with_db(db.clone()))
+            .and_then(handler::upcoming_reminders_handler));
+
+    let admin_routes = warp::path!("api" / "admin" / "export-site")
+        .and(warp::post())
+        .and(with_admin_token(config.clone()))
+        .and(query::<SiteExportOptions>())
+        .and(with_config(config.clone()))
+        .and(with_db(db.clone()))
+        .and_then(handler::export_site_handler)
+        .or(warp::path!("api" / "admin" / "recount-notes")
+            .and(warp::post())
+            .and(with_admin_token(config.clone()))
+            .and(with_db(db.clone()))
+            .and_then(handler::recount_notes_handler))
+        .or(warp::path!("api" / "admin" / "config")
+            .and(warp::get())
+            .and(with_admin_token(config.clone()))
+            .and(with_config(config.clone()))
+            .and_then(handler::admin_config_handler));
+
+    let inbound_routes = warp::path!("api" / "inbound" / String)
+        .and(warp::post())
+        .and(warp::body::content_length_limit(MAX_INBOUND_BODY_BYTES))
+        .and(warp::body::json())
+        .and(with_config(config.clone()))
+        .and(with_inbound_rate_limiter(InboundRateLimiter::default()))
+        .and(with_db(db.clone()))
+        .and_then(handler::inbound_note_handler);
+
+    let dev_routes = warp::path!("api" / "dev" / "echo")
+        .and(with_dev_tools(config.clone()))
+        .and(warp::method())
+        .and(warp::path::full())
+        .and(raw_query())
+        .and(with_client_ip(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::body::bytes())
+        .and_then(handler::dev_echo_handler)
+        .or(warp::path!("api" / "dev" / "fail")
+            .and(with_dev_tools(config.clone()))
+            .and(query::<DevFailOptions>())
+            .and_then(handler::dev_fail_handler));
+
+    // Boxed in two halves: as one nested `Or` type the chain is too deep
+    // for the compiler to lay out.
+    let note_api = note_routes
+        .with(warp::log("api"))
+        .or(note_literal_routes)
+        .or(note_routes_id)
+        .or(draft_routes)
+        .or(merge_routes)
+        .or(rename_routes)
+        .or(duplicate_routes)
+        .or(restore_routes)
+        .or(publish_routes)
+        .or(archive_routes)
+        .or(pin_routes)
+        .or(move_routes)
+        .map(|reply| Box::new(reply) as Box<dyn Reply>)
+        .boxed();
+    let other_api = tag_routes
+        .or(checklist_routes)
+        .or(revision_routes)
+        .or(comment_routes)
+        .or(attachment_routes)
+        .or(category_routes)
+        .or(reminder_routes)
+        .or(admin_routes)
+        .or(inbound_routes)
+        .or(dev_routes)
+        .or(health_checker)
+        .map(|reply| Box::new(reply) as Box<dyn Reply>)
+        .boxed();
+    let api = note_api
+        .or(other_api)
+        .unify()
+        .with(cors)
+        .map(|reply| Ok::<Box<dyn Reply>, Rejection>(Box::new(reply)));
+
+    // Like `.recover(handle_rejection)`, except that the request details the
+    // error body may need are extracted before the routes run.
+    with_error_context(config)
+        .and(api.or_else(|rejection| async move { Ok::<_, Infallible>((Err(rejection),)) }))
+        .and_then(
+            |ctx: ErrorContext, result: Result<Box<dyn Reply>, Rejection>| async move {
+                match result {
+                    Ok(reply) => Ok(reply),
+                    Err(rejection) => error::handle_rejection(rejection, ctx).await,
+                }
+            },
+        )
+}
+
+/// `/api/notes/<segment>` for a literal segment listed in
+/// `NOTE_LITERAL_SEGMENTS`.
+fn note_literal(segment: &'static str) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    assert!(
+        NOTE_LITERAL_SEGMENTS.contains(&segment),
+        "/api/notes/{} must be listed in NOTE_LITERAL_SEGMENTS",
+        segment
+    );
+    warp::path!("api" / "notes" / ..).and(warp::path(segment))
+}
+
+/// The `:id` segment of `/api/notes/:id`, refusing the literal segments.
+fn note_id() -> impl Filter<Extract = (String,), Error = Rejection> + Clone {
+    warp::path::param::<String>().and_then(|id: String| async move {
+        if NOTE_LITERAL_SEGMENTS.contains(&id.as_str()) {
+            return Err(reject::not_found());
+        }
+        Ok(id)
+    })
+}
+
+/// `?page=&limit=` for list endpoints, validated into a `Page`. Every list
+/// route takes this so paging limits and errors stay identical.
+fn page_params(max_limit: u64) -> impl Filter<Extract = (Page,), Error = Rejection> + Clone {
+    query::<PageParams>().and_then(move |params: PageParams| async move {
+        params.validate(max_limit).map_err(reject::custom)
+    })
+}
+
+/// The query string, empty rather than a rejection when there is none.
+fn raw_query() -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+    warp::query::raw().or(warp::any().map(String::new)).unify()
+}
+
+/// `warp::query` with the parameter-naming errors of `parse_query_string`.
+/// A request without a query string deserializes from an empty one.
+fn query<T: DeserializeOwned + Send>() -> impl Filter<Extract = (T,), Error = Rejection> + Clone {
+    raw_query()
+        .and_then(|raw: String| async move { parse_query_string(&raw).map_err(reject::custom) })
+}
+
+/// `FilterOptions` for the notes list. `warp::query` refuses repeated
+/// parameters, so the raw query string goes through `from_query` instead.
+fn filter_options() -> impl Filter<Extract = (FilterOptions,), Error = Rejection> + Clone {
+    raw_query().and_then(|raw: String| async move {
+        FilterOptions::from_query(&raw).map_err(reject::custom)
+    })
+}
+
+/// Signed JSON body. The signature covers the raw bytes, so the body is
+/// read once and parsed here rather than with `warp::body::json`.
+fn json_body<T: DeserializeOwned + Send>(
+    verifier: RequestVerifier,
+) -> impl Filter<Extract = (T,), Error = Rejection> + Clone {
+    verified_body(verifier).and_then(|body: Bytes| async move {
+        serde_json::from_slice::<T>(&body)
+            .map_err(|_| reject::custom(BadRequestError("Invalid Body".to_string())))
+    })
+}
+
+/// JSON body accepting the legacy field names in `schema::LEGACY_FIELDS`,
+/// along with which of them were used.
+fn json_body_with_legacy_fields<T: DeserializeOwned + Send>(
+    verifier: RequestVerifier,
+) -> impl Filter<Extract = (T, LegacyFields), Error = Rejection> + Clone {
+    json_body::<serde_json::Value>(verifier)
+        .and_then(|mut value: serde_json::Value| async move {
+            let legacy = normalize_legacy_fields(&mut value).map_err(reject::custom)?;
+            let body = serde_json::from_value::<T>(value)
+                .map_err(|_| reject::custom(BadRequestError("Invalid Body".to_string())))?;
+            Ok::<_, Rejection>((body, legacy))
+        })
+        .untuple_one()
+}
+
+/// Passes only requests whose Content-Type is `mime`, so a route can be
+/// tried ahead of the general one for the same method and path.
+fn content_type(mime: &'static str) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("content-type")
+        .and_then(move |value: Option<String>| async move {
+            if handler::has_media_type(value.as_deref(), mime) {
+                Ok(())
+            } else {
+                Err(reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+fn with_inbound_rate_limiter(
+    limiter: InboundRateLimiter,
+) -> impl Filter<Extract = (InboundRateLimiter,), Error = Infallible> + Clone {
+    warp::any().map(move || limiter.clone())
+}
+
+fn with_db(db: DB) -> impl Filter<Extract = (DB,), Error = Infallible> + Clone {
+    warp::any().map(move || db.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{block_on, config, offline_db};
+    use mongodb::bson::oid::ObjectId;
+    use std::time::Duration;
+    use warp::test::request;
+
+    /// How `InvalidIDError` answers: a 400 whose message is the bad id.
+    fn is_invalid_id(status: u16, body: &[u8], id: &str) -> bool {
+        let message = serde_json::from_slice::<serde_json::Value>(body)
+            .ok()
+            .and_then(|body| body["message"].as_str().map(str::to_owned));
+        status == 400 && message.as_deref() == Some(id)
+    }
+
+    #[test]
+    fn literal_segments_never_reach_the_id_routes() {
+        block_on(async {
+            let config = config();
+            let routes = routes(offline_db(&config), config);
+            for segment in NOTE_LITERAL_SEGMENTS {
+                for method in ["GET", "POST", "PUT", "PATCH", "DELETE"] {
+                    let reply = request()
+                        .method(method)
+                        .path(&format!("/api/notes/{}", segment))
+                        .header("content-type", "application/json")
+                        .body("{}")
+                        .reply(&routes);
+                    // Long-polling routes may still be waiting; they are not
+                    // id routes either.
+                    let Ok(response) = tokio::time::timeout(Duration::from_secs(2), reply).await
+                    else {
+                        continue;
+                    };
+                    assert!(
+                        !is_invalid_id(response.status().as_u16(), response.body(), segment),
+                        "{} /api/notes/{} was parsed as a note id",
+                        method,
+                        segment
+                    );
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn a_random_object_id_reaches_the_id_handler() {
+        block_on(async {
+            let config = config();
+            let routes = routes(offline_db(&config), config);
+            for _ in 0..3 {
+                let id = ObjectId::new().to_hex();
+                let response = request()
+                    .method("GET")
+                    .path(&format!("/api/notes/{}", id))
+                    .reply(&routes)
+                    .await;
+                // The handler accepted the id and went to the (absent) server.
+                let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+                assert_eq!(response.status(), 500, "{}", body);
+                assert_eq!(body["message"], "Error during mongodb query");
+            }
+
+            // Neither an ObjectId nor a slug.
+            let response = request()
+                .method("GET")
+                .path("/api/notes/Not_An_Id")
+                .reply(&routes)
+                .await;
+            assert!(is_invalid_id(
+                response.status().as_u16(),
+                response.body(),
+                "Not_An_Id"
+            ));
+        })
+    }
+
+    #[test]
+    fn dev_routes_are_absent_unless_enabled() {
+        block_on(async {
+            let mut config = config();
+            config.dev_tools_enabled = false;
+            let routes = routes(offline_db(&config), config);
+            for path in ["/api/dev/echo", "/api/dev/fail?status=503"] {
+                let response = request().path(path).reply(&routes).await;
+                assert_eq!(response.status(), 404, "{}", path);
+            }
+        })
+    }
+
+    #[test]
+    fn dev_fail_answers_with_the_error_envelope() {
+        block_on(async {
+            let mut config = config();
+            config.dev_tools_enabled = true;
+            let routes = routes(offline_db(&config), config);
+
+            let response = request()
+                .path("/api/dev/fail?status=503&code=MAINTENANCE_MODE")
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), 503);
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(body["status"], "error");
+            assert_eq!(body["message"], "Simulated failure");
+            assert_eq!(body["code"], "MAINTENANCE_MODE");
+
+            let response = request()
+                .path("/api/dev/fail?status=429")
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), 429);
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(body["status"], "fail");
+            assert_eq!(body["code"], "SIMULATED_FAILURE");
+
+            let response = request()
+                .path("/api/dev/echo")
+                .header("x-admin-token", "secret")
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), 200);
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(body["data"]["headers"]["x-admin-token"], "[redacted]");
+        })
+    }
+
+    #[test]
+    fn dev_fail_refuses_unknown_codes_and_statuses() {
+        block_on(async {
+            let mut config = config();
+            config.dev_tools_enabled = true;
+            let routes = routes(offline_db(&config), config);
+            for query in ["code=NOT_A_CODE", "status=200", "status=600"] {
+                let response = request()
+                    .path(&format!("/api/dev/fail?{}", query))
+                    .reply(&routes)
+                    .await;
+                assert_eq!(response.status(), 400, "{}", query);
+            }
+        })
+    }
+
+    #[test]
+    fn an_empty_patch_names_the_updatable_fields() {
+        block_on(async {
+            let config = config();
+            let routes = routes(offline_db(&config), config);
+            let path = format!("/api/notes/{}", ObjectId::new());
+
+            let response = request()
+                .method("PATCH")
+                .path(&path)
+                .json(&serde_json::json!({}))
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), 400);
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            let message = body["message"].as_str().unwrap();
+            for field in [
+                "title",
+                "content",
+                "category",
+                "published",
+                "color",
+                "items",
+            ] {
+                assert!(
+                    message.contains(field),
+                    "{} is not named: {}",
+                    field,
+                    message
+                );
+            }
+
+            // Only nulls still changes something: the fields are cleared.
+            let response = request()
+                .method("PATCH")
+                .path(&path)
+                .json(&serde_json::json!({"category": null, "color": null}))
+                .reply(&routes)
+                .await;
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(body["message"], "Error during mongodb query");
+        })
+    }
+
+    #[test]
+    fn delete_all_is_absent_unless_enabled() {
+        block_on(async {
+            let mut config = config();
+            config.dangerous_endpoints_enabled = false;
+            let routes = routes(offline_db(&config), config);
+            let response = request()
+                .method("DELETE")
+                .path("/api/notes/all")
+                .header("x-confirm-delete", "yes")
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), 404);
+        })
+    }
+
+    #[test]
+    fn delete_all_wants_confirmation() {
+        block_on(async {
+            let mut config = config();
+            config.dangerous_endpoints_enabled = true;
+            let routes = routes(offline_db(&config), config);
+
+            for confirm in [None, Some("no"), Some("YES")] {
+                let mut delete = request().method("DELETE").path("/api/notes/all");
+                if let Some(confirm) = confirm {
+                    delete = delete.header("x-confirm-delete", confirm);
+                }
+                let response = delete.reply(&routes).await;
+                assert_eq!(response.status(), 428, "{:?}", confirm);
+            }
+
+            // Confirmed, it goes through to the (absent) database.
+            let response = request()
+                .method("DELETE")
+                .path("/api/notes/all")
+                .header("x-confirm-delete", "yes")
+                .reply(&routes)
+                .await;
+            let body: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+            assert_eq!(body["message"], "Error during mongodb query");
+        })
+    }
+}
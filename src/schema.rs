@@ -1,34 +1,1037 @@
-use serde::{Deserialize, Serialize};
+use crate::{
+    collation::CollationSpec,
+    error::{
+        Error::{BadRequestError, InvalidQueryError, ValidationError},
+        ErrorCode,
+    },
+    query_parser::QueryTerm,
+    response::FieldError,
+    validation, Result,
+};
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+
+/// Field names sent by clients of the old Node service, mapped to the
+/// canonical names. Keep in sync with the `alias` attributes below.
+/// `created_at`/`createdAt` need no mapping: they are server-assigned and
+/// ignored on input under either name.
+pub const LEGACY_FIELDS: &[(&str, &str)] = &[("isPublished", "published"), ("body", "content")];
+
+/// Legacy field names a request body used, reported back in a
+/// `Deprecation` header.
+#[derive(Debug, Default)]
+pub struct LegacyFields(pub Vec<&'static str>);
+
+/// Renames legacy keys in a JSON object to their canonical names. A body
+/// carrying both names with different values is rejected.
+pub fn normalize_legacy_fields(value: &mut serde_json::Value) -> Result<LegacyFields> {
+    let mut used = LegacyFields::default();
+    let Some(object) = value.as_object_mut() else {
+        return Ok(used);
+    };
+
+    for (legacy, canonical) in LEGACY_FIELDS {
+        let Some(legacy_value) = object.remove(*legacy) else {
+            continue;
+        };
+        used.0.push(*legacy);
+
+        match object.get(*canonical) {
+            Some(canonical_value) if *canonical_value != legacy_value => {
+                return Err(BadRequestError(format!(
+                    "Conflicting values for '{}' and its legacy name '{}'",
+                    canonical, legacy
+                )));
+            }
+            Some(_) => {}
+            None => {
+                object.insert(canonical.to_string(), legacy_value);
+            }
+        }
+    }
+
+    Ok(used)
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteSortField {
+    #[serde(rename = "title")]
+    Title,
+    #[serde(rename = "createdAt")]
+    CreatedAt,
+    #[serde(rename = "updatedAt")]
+    UpdatedAt,
+    #[serde(rename = "category")]
+    Category,
+    #[serde(rename = "position")]
+    Position,
+}
+
+impl NoteSortField {
+    pub fn key(&self) -> &'static str {
+        match self {
+            NoteSortField::Title => "title",
+            NoteSortField::CreatedAt => "createdAt",
+            NoteSortField::UpdatedAt => "updatedAt",
+            NoteSortField::Category => "category",
+            NoteSortField::Position => "position",
+        }
+    }
+
+    fn parse(field: &str) -> Option<Self> {
+        match field {
+            "title" => Some(NoteSortField::Title),
+            "createdAt" => Some(NoteSortField::CreatedAt),
+            "updatedAt" => Some(NoteSortField::UpdatedAt),
+            "category" => Some(NoteSortField::Category),
+            "position" => Some(NoteSortField::Position),
+            _ => None,
+        }
+    }
+}
+
+/// A field a list request may select with `?fields=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteField {
+    Id,
+    Title,
+    Content,
+    Category,
+    Published,
+    Color,
+    CreatedAt,
+    UpdatedAt,
+    Slug,
+    PublishedAt,
+    Archived,
+    Pinned,
+    Tags,
+    Items,
+    Position,
+    DeletedAt,
+    Preview,
+}
+
+impl NoteField {
+    const ALL: &'static [(&'static str, NoteField)] = &[
+        ("id", NoteField::Id),
+        ("title", NoteField::Title),
+        ("content", NoteField::Content),
+        ("category", NoteField::Category),
+        ("published", NoteField::Published),
+        ("color", NoteField::Color),
+        ("createdAt", NoteField::CreatedAt),
+        ("updatedAt", NoteField::UpdatedAt),
+        ("slug", NoteField::Slug),
+        ("publishedAt", NoteField::PublishedAt),
+        ("archived", NoteField::Archived),
+        ("pinned", NoteField::Pinned),
+        ("tags", NoteField::Tags),
+        ("items", NoteField::Items),
+        ("position", NoteField::Position),
+        ("deletedAt", NoteField::DeletedAt),
+        ("preview", NoteField::Preview),
+    ];
+
+    /// The stored field it is read from.
+    pub fn key(&self) -> &'static str {
+        match self {
+            NoteField::Id => "_id",
+            NoteField::Title => "title",
+            NoteField::Content => "content",
+            NoteField::Category => "category",
+            NoteField::Published => "published",
+            NoteField::Color => "color",
+            NoteField::CreatedAt => "createdAt",
+            NoteField::UpdatedAt => "updatedAt",
+            NoteField::Slug => "slug",
+            NoteField::PublishedAt => "publishedAt",
+            NoteField::Archived => "archived",
+            NoteField::Pinned => "pinned",
+            NoteField::Tags => "tags",
+            NoteField::Items => "items",
+            NoteField::Position => "position",
+            NoteField::DeletedAt => "deletedAt",
+            NoteField::Preview => "preview",
+        }
+    }
+}
+
+/// Parses `?fields=id,title,updatedAt`.
+fn parse_fields(value: &str) -> Result<Vec<NoteField>> {
+    let mut fields = Vec::new();
+    for name in value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+    {
+        let field = NoteField::ALL
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, field)| *field)
+            .ok_or_else(|| {
+                let valid: Vec<&str> = NoteField::ALL.iter().map(|(known, _)| *known).collect();
+                InvalidQueryError(format!(
+                    "unknown field '{}'; valid fields are {}",
+                    name,
+                    valid.join(", ")
+                ))
+            })?;
+        if !fields.contains(&field) {
+            fields.push(field);
+        }
+    }
+    Ok(fields)
+}
+
+/// Which fields of each note a list request returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NoteProjection {
+    /// `?fields=`: these and `id`.
+    Only(Vec<NoteField>),
+    /// `?exclude=`: everything but these.
+    Except(Vec<NoteField>),
+}
+
+/// Mongo projections are either inclusive or exclusive, so `fields` and
+/// `exclude` cannot be combined, and `id` is always returned.
+pub fn parse_projection(
+    fields: Option<&str>,
+    exclude: Option<&str>,
+) -> Result<Option<NoteProjection>> {
+    match (fields, exclude) {
+        (Some(_), Some(_)) => Err(InvalidQueryError(
+            "fields and exclude cannot be combined".to_string(),
+        )),
+        (Some(fields), None) => {
+            parse_fields(fields).map(|fields| Some(NoteProjection::Only(fields)))
+        }
+        (None, Some(exclude)) => {
+            let excluded = parse_fields(exclude)?;
+            if excluded.contains(&NoteField::Id) {
+                return Err(InvalidQueryError("id cannot be excluded".to_string()));
+            }
+            Ok(Some(NoteProjection::Except(excluded)))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// Everything one notes list request asks for. The list and search
+/// endpoints both build one and hand it to `DB::fetch_notes`, so counting,
+/// paging and iteration work the same for both.
+#[derive(Debug, Clone)]
+pub struct NotesQuery {
+    pub filter: NoteListFilter,
+    pub order: ListOrder,
+    pub page: Page,
+    /// The default collation when `None`. Ignored for text searches, which
+    /// only run under simple binary comparison.
+    pub collation: Option<CollationSpec>,
+    pub extras: ListExtras,
+    pub projection: Option<NoteProjection>,
+}
+
+/// Optional parts of a list response, each costing a query of its own.
+#[derive(Debug, Clone, Copy)]
+pub struct ListExtras {
+    pub facets: bool,
+    pub total: bool,
+}
+
+/// How a notes list is ordered, and where it starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListOrder {
+    /// Newest first; pages can be continued with `After`.
+    Default,
+    /// An explicit `sort`/`sort_by`.
+    Sorted(Vec<SortKey>),
+    /// `?after=`: `_id` descending, starting below this id.
+    After(ObjectId),
+    /// Best `text` matches first.
+    Relevance,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: NoteSortField,
+    pub descending: bool,
+}
+
+/// Parses `?sort=category:asc,createdAt:desc`; the direction defaults to
+/// `asc` and each field may appear once.
+pub fn parse_sort(value: &str) -> Result<Vec<SortKey>> {
+    let mut keys: Vec<SortKey> = Vec::new();
+    for part in value.split(',').map(str::trim) {
+        let (field, direction) = part.split_once(':').unwrap_or((part, "asc"));
+        let field = NoteSortField::parse(field).ok_or_else(|| {
+            InvalidQueryError(format!(
+                "cannot sort by '{}'; use title, createdAt, updatedAt, category or position",
+                field
+            ))
+        })?;
+        let descending = match direction {
+            "asc" => false,
+            "desc" => true,
+            _ => {
+                return Err(InvalidQueryError(format!(
+                    "sort direction must be asc or desc, not '{}'",
+                    direction
+                )))
+            }
+        };
+        if keys.iter().any(|key| key.field == field) {
+            return Err(InvalidQueryError(format!(
+                "'{}' appears more than once in sort",
+                field.key()
+            )));
+        }
+        keys.push(SortKey { field, descending });
+    }
+    Ok(keys)
+}
+
+pub const DEFAULT_PAGE_LIMIT: u64 = 10;
+/// Largest `limit` unless `MAX_PAGE_LIMIT` says otherwise.
+pub const DEFAULT_MAX_PAGE_LIMIT: u64 = 100;
+/// Deepest `page`: Mongo walks and discards every skipped note, so deeper
+/// pages have to be reached with `?after=` instead.
+pub const MAX_PAGE: u64 = 1000;
+
+/// Deserializes a query string as `warp::query` would, except that a bad
+/// value becomes an `InvalidQueryError` naming the parameter instead of an
+/// opaque rejection.
+pub fn parse_query_string<T: DeserializeOwned>(raw: &str) -> Result<T> {
+    let deserializer =
+        serde_urlencoded::Deserializer::new(url::form_urlencoded::parse(raw.as_bytes()));
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let param = e.path().to_string();
+        let message = e.inner().to_string();
+        // serde_urlencoded passes on the std parse errors, which never say
+        // what was expected.
+        if message.contains("invalid digit") || message.contains("number too large") {
+            InvalidQueryError(format!("{} must be a non-negative integer", param))
+        } else if message.contains("`true` or `false`") {
+            InvalidQueryError(format!("{} must be true or false", param))
+        } else {
+            InvalidQueryError(format!("invalid value for {}: {}", param, message))
+        }
+    })
+}
+
+/// `?page=&limit=` as sent by the client; every list endpoint extracts this
+/// and turns it into a `Page` with `validate`.
+#[derive(Deserialize, Debug)]
+pub struct PageParams {
+    pub page: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+impl PageParams {
+    pub fn validate(&self, max_limit: u64) -> Result<Page> {
+        let page = self.page.unwrap_or(1);
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_LIMIT.min(max_limit));
+        if page == 0 {
+            return Err(InvalidQueryError("page must be 1 or greater".to_string()));
+        }
+        if page > MAX_PAGE {
+            return Err(InvalidQueryError(format!(
+                "page must be at most {}; use cursor pagination with after= for deeper pages",
+                MAX_PAGE
+            )));
+        }
+        if limit == 0 || limit > max_limit {
+            return Err(InvalidQueryError(format!(
+                "limit must be between 1 and {}",
+                max_limit
+            )));
+        }
+        Ok(Page { page, limit })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Page {
+    pub page: u64,
+    pub limit: u64,
+}
+
+impl Page {
+    pub fn skip(&self) -> u64 {
+        (self.page - 1).saturating_mul(self.limit)
+    }
+}
 
 #[derive(Deserialize, Debug)]
 pub struct FilterOptions {
-    pub page: Option<usize>,
-    pub limit: Option<usize>,
+    /// Ascending single-field sort; superseded by `sort`.
+    pub sort_by: Option<NoteSortField>,
+    pub sort: Option<String>,
+    pub collation: Option<String>,
+    pub strength: Option<u8>,
+    pub title: Option<String>,
+    /// Filled by `from_query` from every `category` parameter.
+    #[serde(skip)]
+    pub categories: Vec<String>,
+    pub published: Option<bool>,
+    /// `true` for only archived notes, `all` for both; see `ArchivedFilter`.
+    pub archived: Option<String>,
+    pub pinned: Option<bool>,
+    pub color: Option<String>,
+    /// Comma-separated; notes with any of these tags.
+    pub tags: Option<String>,
+    /// Comma-separated; notes with every one of these tags.
+    pub tags_all: Option<String>,
+    /// Case-insensitive substring of title or content.
+    pub search: Option<String>,
+    /// Structured search, see `query_parser::parse_query`.
+    pub q: Option<String>,
+    pub include_facets: Option<bool>,
+    /// `false` skips counting; `has_next` still says whether more follow.
+    pub include_total: Option<bool>,
+    /// Comma-separated `NoteField` names; all fields when absent.
+    pub fields: Option<String>,
+    /// Comma-separated `NoteField` names to leave out.
+    pub exclude: Option<String>,
+    /// `next_cursor` of the previous page.
+    pub after: Option<String>,
+    /// Only read to refuse combining `page` with `after`; paging itself
+    /// comes from `PageParams`.
+    pub page: Option<u64>,
+    /// RFC 3339; parsed by the handler so errors can name the parameter.
+    #[serde(rename = "createdAt_gte")]
+    pub created_at_gte: Option<String>,
+    #[serde(rename = "createdAt_lte")]
+    pub created_at_lte: Option<String>,
+    /// RFC 3339 watermark for incremental sync. Notes moved to the trash
+    /// since then come back as tombstones with `deletedAt` set; permanent
+    /// deletions are not synced.
+    #[serde(rename = "updatedSince")]
+    pub updated_since: Option<String>,
+}
+
+impl FilterOptions {
+    /// Parses a list query string. `category` may repeat and may hold a
+    /// comma-separated list; the items are trimmed and deduplicated. A lone
+    /// empty `?category=` still means "notes without a category".
+    pub fn from_query(raw: &str) -> Result<Self> {
+        let (categories, rest): (Vec<_>, Vec<_>) =
+            url::form_urlencoded::parse(raw.as_bytes()).partition(|(key, _)| key == "category");
+        let rest = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(rest)
+            .finish();
+        let mut opts: Self = parse_query_string(&rest)?;
+
+        for (_, value) in &categories {
+            let value = value.trim();
+            if value.is_empty() && categories.len() == 1 {
+                opts.categories.push(String::new());
+            }
+            for item in value.split(',').map(str::trim) {
+                if !item.is_empty() && !opts.categories.iter().any(|c| c == item) {
+                    opts.categories.push(item.to_string());
+                }
+            }
+        }
+        Ok(opts)
+    }
+}
+
+/// The filtering part of a list request, i.e. everything that narrows the
+/// result set as opposed to paging or ordering it.
+#[derive(Debug, Clone, Default)]
+pub struct NoteListFilter {
+    /// Matched against `title_normalized`, so already normalized.
+    pub title: Option<String>,
+    /// Any of these; empty means no category filter.
+    pub categories: Vec<String>,
+    pub published: Option<bool>,
+    /// Matched literally, so not yet regex-escaped.
+    pub search: Option<String>,
+    /// Inclusive `createdAt` bounds; either may be open.
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    /// Inclusive lower bound on `updatedAt`.
+    pub updated_since: Option<DateTime<Utc>>,
+    /// Parsed `?q=`; every term must match.
+    pub query: Vec<QueryTerm>,
+    /// Full-text `$text` search; needs the text index and rules out facets.
+    pub text: Option<String>,
+    /// Any of these ids; empty means no id filter.
+    pub ids: Vec<ObjectId>,
+    /// Any of these tags, already normalized; empty means no tag filter.
+    pub tags: Vec<String>,
+    /// Every one of these tags, already normalized.
+    pub tags_all: Vec<String>,
+    pub archived: ArchivedFilter,
+    pub pinned: Option<bool>,
+    /// Already normalized.
+    pub color: Option<String>,
+    pub deleted: DeletedFilter,
+}
+
+/// Notes in the trash only show up in the trash view and as sync
+/// tombstones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeletedFilter {
+    #[default]
+    Exclude,
+    Only,
+    Include,
+}
+
+/// `?archived=`: archived notes are hidden unless asked for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArchivedFilter {
+    #[default]
+    Exclude,
+    Only,
+    All,
+}
+
+impl ArchivedFilter {
+    pub fn parse(value: Option<&str>) -> Result<Self> {
+        match value {
+            None | Some("false") => Ok(ArchivedFilter::Exclude),
+            Some("true") => Ok(ArchivedFilter::Only),
+            Some("all") => Ok(ArchivedFilter::All),
+            Some(_) => Err(InvalidQueryError(
+                "archived must be true, false or all".to_string(),
+            )),
+        }
+    }
+}
+
+impl NoteListFilter {
+    /// Whether this filter matches every note.
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.categories.is_empty()
+            && self.published.is_none()
+            && self.search.is_none()
+            && self.created_from.is_none()
+            && self.created_to.is_none()
+            && self.updated_since.is_none()
+            && self.query.is_empty()
+            && self.text.is_none()
+            && self.ids.is_empty()
+            && self.pinned.is_none()
+            && self.color.is_none()
+            && self.tags.is_empty()
+            && self.tags_all.is_empty()
+            && self.archived != ArchivedFilter::Only
+            && self.deleted != DeletedFilter::Only
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BulkUpdateOptions {
+    /// Must be `all` to update with no filter at all.
+    pub confirm: Option<String>,
+    /// Comma-separated note ids to restrict the update to.
+    pub ids: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DeleteNoteOptions {
+    /// Delete outright instead of moving to the trash.
+    pub permanent: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BulkDeleteOptions {
+    /// Must be `all` to delete with no filter at all.
+    pub confirm: Option<String>,
+    /// Delete outright instead of moving to the trash.
+    pub permanent: Option<bool>,
+    /// Only count what would be deleted.
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SearchOptions {
+    pub q: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CategoriesOptions {
+    pub published: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SuggestOptions {
+    pub prefix: Option<String>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PollOptions {
+    pub since_seq: Option<u64>,
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetNoteOptions {
+    pub include_draft: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EditNoteOptions {
+    /// Derive the slug again from the new title.
+    pub regenerate_slug: Option<bool>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SiteExportFormat {
+    Files,
+    Zip,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SiteExportOptions {
+    pub format: Option<SiteExportFormat>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExportNotesSchema {
+    pub ids: Vec<String>,
 }
 
 #[derive(Deserialize, Debug)]
-pub struct ParamOptions {
-    pub id: String,
+pub struct DevFailOptions {
+    pub status: Option<u16>,
+    pub delay_ms: Option<u64>,
+    pub code: Option<ErrorCode>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CreateNoteSchema {
     pub title: String,
+    #[serde(alias = "body")]
     pub content: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub category: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "isPublished", skip_serializing_if = "Option::is_none")]
     pub published: Option<bool>,
+    /// Checked by `color::normalize_color` when stored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    /// Stored normalized, see `tags::normalize_tags`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Positioned in the order given, see `checklist::checklist_items`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub items: Vec<ChecklistItemSchema>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// `None` for an absent key, `Some(None)` for an explicit `null`.
+#[derive(Deserialize, Debug, Default)]
 pub struct UpdateNoteSchema {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub title: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub content: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub category: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub published: Option<bool>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub title: Option<Option<String>>,
+    #[serde(alias = "body", default, deserialize_with = "nullable")]
+    pub content: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub category: Option<Option<String>>,
+    #[serde(alias = "isPublished", default, deserialize_with = "nullable")]
+    pub published: Option<Option<bool>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub color: Option<Option<String>>,
+    /// Replaces the whole checklist.
+    #[serde(default, deserialize_with = "nullable")]
+    pub items: Option<Option<Vec<ChecklistItemSchema>>>,
+}
+
+impl CreateNoteSchema {
+    /// Length limits, see `validation::check_note_fields`.
+    pub fn validate(&self, max_content_length: usize) -> Result<()> {
+        validation::check_note_fields(
+            Some(&self.title),
+            Some(&self.content),
+            self.category.as_deref(),
+            max_content_length,
+        )
+    }
+}
+
+impl UpdateNoteSchema {
+    /// Whether the body names no field at all.
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.content.is_none()
+            && self.category.is_none()
+            && self.published.is_none()
+            && self.color.is_none()
+            && self.items.is_none()
+    }
+
+    /// Length limits on the fields the body sets, see
+    /// `validation::check_note_fields`.
+    pub fn validate(&self, max_content_length: usize) -> Result<()> {
+        validation::check_note_fields(
+            self.title.as_ref().and_then(Option::as_deref),
+            self.content.as_ref().and_then(Option::as_deref),
+            self.category.as_ref().and_then(Option::as_deref),
+            max_content_length,
+        )
+    }
+
+    /// `null` removes a field, as in RFC 7386, which `title` and `content`
+    /// cannot be.
+    pub fn validate_nulls(&self) -> Result<()> {
+        let errors: Vec<FieldError> = [("title", &self.title), ("content", &self.content)]
+            .into_iter()
+            .filter(|(_, value)| matches!(value, Some(None)))
+            .map(|(field, _)| FieldError {
+                field: field.to_string(),
+                code: "REQUIRED".to_string(),
+                message: format!("{} cannot be removed", field),
+            })
+            .collect();
+        if !errors.is_empty() {
+            return Err(ValidationError(errors));
+        }
+        Ok(())
+    }
+}
+
+/// Only called for keys that are present, so `null` becomes `Some(None)`.
+pub fn nullable<'de, D, T>(deserializer: D) -> std::result::Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateReminderSchema {
+    pub at: DateTime<Utc>,
+    pub message: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateCommentSchema {
+    pub author: String,
+    pub body: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UpcomingRemindersOptions {
+    pub within_hours: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RenameCategorySchema {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChecklistItemSchema {
+    pub text: String,
+    #[serde(default)]
+    pub done: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UpdateChecklistItemSchema {
+    pub text: Option<String>,
+    pub done: Option<bool>,
+}
+
+/// Exactly one of the two, naming the note to move next to.
+#[derive(Deserialize, Debug)]
+pub struct MoveNoteSchema {
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AddTagSchema {
+    pub tag: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RenameNoteSchema {
+    pub title: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SaveDraftSchema {
+    pub content: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeStrategy {
+    Append,
+    Prepend,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MergeNoteSchema {
+    pub source_id: String,
+    pub strategy: Option<MergeStrategy>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MergeNoteOptions {
+    /// `true` deletes the source for good, `false` keeps it; without the
+    /// parameter it goes to the trash.
+    pub delete_source: Option<bool>,
+}
+
+/// What `DB::merge_notes` does with the source note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeSourceFate {
+    Keep,
+    Trash,
+    Delete,
+}
+
+impl MergeNoteOptions {
+    pub fn source_fate(&self) -> MergeSourceFate {
+        match self.delete_source {
+            None => MergeSourceFate::Trash,
+            Some(true) => MergeSourceFate::Delete,
+            Some(false) => MergeSourceFate::Keep,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn update(body: serde_json::Value) -> UpdateNoteSchema {
+        serde_json::from_value(body).expect("body should deserialize")
+    }
+
+    fn required_fields(result: Result<()>) -> Vec<String> {
+        match result {
+            Err(ValidationError(errors)) => errors
+                .into_iter()
+                .inspect(|error| assert_eq!(error.code, "REQUIRED"))
+                .map(|error| error.field)
+                .collect(),
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn absent_keys_leave_every_field_untouched() {
+        let body = update(json!({}));
+        assert_eq!(body.title, None);
+        assert_eq!(body.content, None);
+        assert_eq!(body.category, None);
+        assert_eq!(body.published, None);
+        assert_eq!(body.color, None);
+        assert!(body.items.is_none());
+        assert!(body.is_empty());
+        assert!(body.validate_nulls().is_ok());
+    }
+
+    #[test]
+    fn explicit_nulls_are_kept_apart_from_absent_keys() {
+        let body = update(json!({
+            "title": null,
+            "content": null,
+            "category": null,
+            "published": null,
+            "color": null,
+            "items": null,
+        }));
+        assert_eq!(body.title, Some(None));
+        assert_eq!(body.content, Some(None));
+        assert_eq!(body.category, Some(None));
+        assert_eq!(body.published, Some(None));
+        assert_eq!(body.color, Some(None));
+        assert!(matches!(body.items, Some(None)));
+        assert!(!body.is_empty());
+    }
+
+    #[test]
+    fn values_are_set() {
+        let body = update(json!({
+            "title": "Groceries",
+            "content": "milk",
+            "category": "home",
+            "published": true,
+            "color": "red",
+            "items": [{"text": "eggs", "done": true}],
+        }));
+        assert_eq!(body.title, Some(Some("Groceries".to_string())));
+        assert_eq!(body.content, Some(Some("milk".to_string())));
+        assert_eq!(body.category, Some(Some("home".to_string())));
+        assert_eq!(body.published, Some(Some(true)));
+        assert_eq!(body.color, Some(Some("red".to_string())));
+        assert!(body.validate_nulls().is_ok());
+        let items = body.items.flatten().expect("items should be set");
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].text, "eggs");
+        assert!(items[0].done);
+    }
+
+    #[test]
+    fn null_title_and_content_are_rejected() {
+        let body = update(json!({"title": null, "content": null}));
+        assert_eq!(required_fields(body.validate_nulls()), ["title", "content"]);
+
+        let body = update(json!({"title": null, "category": "home"}));
+        assert_eq!(required_fields(body.validate_nulls()), ["title"]);
+    }
+
+    #[test]
+    fn null_optional_fields_may_be_cleared() {
+        let body = update(json!({
+            "category": null,
+            "published": null,
+            "color": null,
+            "items": null,
+        }));
+        assert!(body.validate_nulls().is_ok());
+    }
+
+    #[test]
+    fn legacy_names_become_canonical() {
+        let mut body = json!({"title": "t", "body": "text", "isPublished": true});
+        let used = normalize_legacy_fields(&mut body).unwrap();
+        assert_eq!(used.0, ["isPublished", "body"]);
+        assert_eq!(
+            body,
+            json!({"title": "t", "content": "text", "published": true})
+        );
+
+        // What gets stored is serialized from the schema: canonical names only.
+        let note: CreateNoteSchema = serde_json::from_value(body).unwrap();
+        let stored = serde_json::to_value(&note).unwrap();
+        assert_eq!(stored["content"], "text");
+        assert_eq!(stored["published"], true);
+        for (legacy, _) in LEGACY_FIELDS {
+            assert!(stored.get(legacy).is_none(), "{} was stored", legacy);
+        }
+    }
+
+    #[test]
+    fn legacy_and_canonical_names_may_agree() {
+        let mut body = json!({"content": "text", "body": "text"});
+        let used = normalize_legacy_fields(&mut body).unwrap();
+        assert_eq!(used.0, ["body"]);
+        assert_eq!(body, json!({"content": "text"}));
+    }
+
+    #[test]
+    fn legacy_and_canonical_names_may_not_disagree() {
+        let mut body = json!({"published": false, "isPublished": true});
+        match normalize_legacy_fields(&mut body) {
+            Err(BadRequestError(message)) => assert_eq!(
+                message,
+                "Conflicting values for 'published' and its legacy name 'isPublished'"
+            ),
+            other => panic!("expected a conflict, got {:?}", other.map(|used| used.0)),
+        }
+    }
+
+    #[test]
+    fn bodies_without_legacy_names_are_left_alone() {
+        for original in [
+            json!({"title": "t", "content": "c"}),
+            json!([1, 2]),
+            json!(null),
+        ] {
+            let mut body = original.clone();
+            assert!(normalize_legacy_fields(&mut body).unwrap().0.is_empty());
+            assert_eq!(body, original);
+        }
+    }
+
+    fn query_error<T: DeserializeOwned + std::fmt::Debug>(raw: &str) -> String {
+        match parse_query_string::<T>(raw) {
+            Err(InvalidQueryError(message)) => message,
+            other => panic!("expected a query error for {:?}, got {:?}", raw, other),
+        }
+    }
+
+    #[test]
+    fn bad_numbers_name_their_parameter() {
+        assert_eq!(
+            query_error::<PageParams>("page=abc"),
+            "page must be a non-negative integer"
+        );
+        assert_eq!(
+            query_error::<PageParams>("page=2&limit=-5"),
+            "limit must be a non-negative integer"
+        );
+        assert_eq!(
+            query_error::<FilterOptions>("strength=999"),
+            "strength must be a non-negative integer"
+        );
+    }
+
+    #[test]
+    fn bad_booleans_and_enums_name_their_parameter() {
+        assert_eq!(
+            query_error::<FilterOptions>("title=x&published=maybe"),
+            "published must be true or false"
+        );
+        let message = query_error::<FilterOptions>("sort_by=colour");
+        assert!(
+            message.starts_with("invalid value for sort_by: unknown variant `colour`"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn unknown_parameters_are_ignored() {
+        let params: PageParams = parse_query_string("frobnicate=1&page=3").unwrap();
+        assert_eq!(params.page, Some(3));
+        assert_eq!(params.limit, None);
+    }
+
+    fn page(page: Option<u64>, limit: Option<u64>) -> Result<Page> {
+        PageParams { page, limit }.validate(50)
+    }
+
+    #[test]
+    fn page_bounds() {
+        assert!(page(Some(0), None).is_err());
+        assert_eq!(page(Some(1), None).unwrap().page, 1);
+        assert_eq!(page(Some(MAX_PAGE), None).unwrap().page, MAX_PAGE);
+        match page(Some(MAX_PAGE + 1), None) {
+            Err(InvalidQueryError(message)) => assert!(message.contains("after="), "{}", message),
+            other => panic!("expected a page error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn limit_bounds() {
+        for limit in [0, 51] {
+            match page(None, Some(limit)) {
+                Err(InvalidQueryError(message)) => {
+                    assert_eq!(message, "limit must be between 1 and 50")
+                }
+                other => panic!("expected a limit error for {}, got {:?}", limit, other),
+            }
+        }
+        assert_eq!(page(None, Some(1)).unwrap().limit, 1);
+        assert_eq!(page(None, Some(50)).unwrap().limit, 50);
+    }
+
+    #[test]
+    fn defaults_fit_under_a_small_max_limit() {
+        let page = page(None, None).unwrap();
+        assert_eq!((page.page, page.limit), (1, DEFAULT_PAGE_LIMIT));
+        let small = PageParams {
+            page: None,
+            limit: None,
+        }
+        .validate(3)
+        .unwrap();
+        assert_eq!(small.limit, 3);
+    }
 }
@@ -0,0 +1,101 @@
+use crate::{
+    db::{escape_regex, DB},
+    error::{mongo_error_code, Error::*},
+    response::{NoteListResponse, TitleSuggestion},
+    schema::{ListExtras, ListOrder, NoteListFilter, NotesQuery, Page},
+    Result,
+};
+use futures::StreamExt;
+use log::warn;
+use mongodb::bson::doc;
+use mongodb::options::FindOptions;
+
+const LOG_TARGET: &str = "api::search";
+
+/// Mongo's `IndexNotFound`, which `$text` reports when there is no text index.
+const INDEX_NOT_FOUND: i32 = 27;
+
+/// Name of the text index `reconcile_schema` creates over title and content.
+pub const TEXT_INDEX_NAME: &str = "title_content_text";
+
+pub const DEFAULT_SUGGESTIONS: u64 = 10;
+pub const MAX_SUGGESTIONS: u64 = 25;
+pub const MIN_SUGGEST_PREFIX_CHARS: usize = 2;
+
+impl DB {
+    /// Full-text search over title and content, best matches first. Every
+    /// search goes through here, so another backend (e.g. Atlas Search)
+    /// would only have to replace this method's body.
+    pub async fn search_notes(&self, q: &str, page: &Page) -> Result<NoteListResponse> {
+        let query = NotesQuery {
+            filter: NoteListFilter {
+                text: Some(q.to_owned()),
+                ..NoteListFilter::default()
+            },
+            order: ListOrder::Relevance,
+            page: *page,
+            collation: None,
+            extras: ListExtras {
+                facets: false,
+                total: true,
+            },
+            projection: None,
+        };
+        self.fetch_notes(&query).await.map_err(text_index_error)
+    }
+
+    /// Titles starting with `prefix`, for a quick switcher. With case
+    /// folding on (the default) this is an anchored regex on the indexed
+    /// `title_normalized`; otherwise it falls back to a case-insensitive
+    /// match on `title`, which cannot use an index as well.
+    pub async fn suggest_titles(&self, prefix: &str, limit: i64) -> Result<Vec<TitleSuggestion>> {
+        let (field, filter) = if self.title_normalizer.case_fold {
+            let prefix = escape_regex(&self.title_normalizer.normalize(prefix));
+            (
+                "title_normalized",
+                doc! {"title_normalized": {"$regex": format!("^{}", prefix)}, "deletedAt": null},
+            )
+        } else {
+            let prefix = escape_regex(prefix);
+            (
+                "title",
+                doc! {"title": {"$regex": format!("^{}", prefix), "$options": "i"}, "deletedAt": null},
+            )
+        };
+        let options = FindOptions::builder()
+            .projection(doc! {"title": 1})
+            .sort(doc! {field: 1, "_id": 1})
+            .limit(limit)
+            .build();
+        let mut cursor = self
+            .collection
+            .find(filter, options)
+            .await
+            .map_err(MongoQueryError)?;
+
+        let mut suggestions = Vec::new();
+        while let Some(note) = cursor.next().await {
+            let note = note.map_err(MongoQueryError)?;
+            let (Ok(id), Ok(title)) = (note.get_object_id("_id"), note.get_str("title")) else {
+                continue;
+            };
+            suggestions.push(TitleSuggestion {
+                id: id.to_hex(),
+                title: title.to_owned(),
+            });
+        }
+        Ok(suggestions)
+    }
+}
+
+fn text_index_error(e: crate::error::Error) -> crate::error::Error {
+    match e {
+        MongoQueryError(e) if mongo_error_code(&e) == Some(INDEX_NOT_FOUND) => {
+            warn!(target: LOG_TARGET, "Text search without a text index: {:?}", e);
+            UnavailableError(
+                "Search is unavailable until the text index has been built".to_string(),
+            )
+        }
+        e => e,
+    }
+}
@@ -0,0 +1,46 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// A configured secret. `Debug` and `Serialize` only ever show a short
+/// fingerprint, so a secret cannot leak through logs or the config report
+/// by someone forgetting to skip a field; `expose` is the only way in.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: AsRef<str>> Secret<T> {
+    /// The first four hex digits of the SHA-256, enough to tell which of a
+    /// few known secrets is loaded.
+    pub fn fingerprint(&self) -> String {
+        let digest = hex::encode(Sha256::digest(self.0.as_ref().as_bytes()));
+        format!("sha256:{}", &digest[..4])
+    }
+}
+
+impl<T: AsRef<str>> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({})", self.fingerprint())
+    }
+}
+
+impl<T: AsRef<str>> Serialize for Secret<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.fingerprint())
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Secret)
+    }
+}
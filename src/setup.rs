@@ -0,0 +1,514 @@
+use crate::{
+    attachments::ATTACHMENT_BUCKET,
+    db::DB,
+    error::{mongo_error_code, Error::*, DUPLICATE_KEY},
+    positions::POSITION_GAP,
+    preview,
+    search::TEXT_INDEX_NAME,
+    Result,
+};
+use chrono::{Duration, Utc};
+use futures::StreamExt;
+use log::{info, warn};
+use mongodb::bson::{self, doc, oid::ObjectId, Document};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, IndexOptions, ReturnDocument};
+use mongodb::IndexModel;
+
+const LOG_TARGET: &str = "api::setup";
+
+const SETUP_LEASE: &str = "schema-setup";
+const LEASE_TTL_SECS: i64 = 30;
+
+/// Bump whenever `reconcile_schema` gains a new index or migration step so
+/// that already-initialised databases run it once more.
+const SETUP_VERSION: i32 = 16;
+
+/// Mongo's `IndexAlreadyExists`: the index is there with the same spec.
+const INDEX_ALREADY_EXISTS: i32 = 68;
+
+impl DB {
+    /// Makes sure exactly one replica reconciles indexes and runs migrations.
+    /// The replica holding the `schema-setup` lease does the work and records
+    /// `SETUP_VERSION`; the others wait until they can take the lease and then
+    /// only verify that the recorded version is current. Returns whether
+    /// this call did the work.
+    pub async fn run_setup(&self) -> Result<bool> {
+        let holder = ObjectId::new().to_hex();
+
+        loop {
+            let Some(lease) = self.acquire_lease(SETUP_LEASE, &holder).await? else {
+                info!(
+                    target: LOG_TARGET,
+                    "⏳ Waiting for another instance to finish schema setup"
+                );
+                tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                continue;
+            };
+
+            // A changed DEFAULT_COLLATION needs its own title index, and
+            // changed normalization settings a new backfill, even when the
+            // schema version is current.
+            let collation = self.default_collation.index_name();
+            let normalization = self.title_normalizer.key();
+            let completed = lease.get_i32("completedVersion").unwrap_or(0);
+            if completed >= SETUP_VERSION
+                && lease.get_str("titleCollation") == Ok(&collation)
+                && lease.get_str("titleNormalization") == Ok(&normalization)
+            {
+                self.release_lease(SETUP_LEASE, &holder, None).await?;
+                return Ok(false);
+            }
+
+            let renewal = {
+                let db = self.clone();
+                let holder = holder.clone();
+                tokio::spawn(async move {
+                    let period = std::time::Duration::from_secs((LEASE_TTL_SECS / 3) as u64);
+                    loop {
+                        tokio::time::sleep(period).await;
+                        if let Err(e) = db.renew_lease(SETUP_LEASE, &holder).await {
+                            warn!(
+                                target: LOG_TARGET,
+                                "Could not renew {} lease: {:?}", SETUP_LEASE, e
+                            );
+                        }
+                    }
+                })
+            };
+
+            let result = self.reconcile_schema().await;
+            renewal.abort();
+
+            let completed = result.is_ok().then(|| {
+                doc! {
+                    "completedVersion": SETUP_VERSION,
+                    "titleCollation": collation,
+                    "titleNormalization": normalization,
+                }
+            });
+            self.release_lease(SETUP_LEASE, &holder, completed).await?;
+            return result.map(|_| true);
+        }
+    }
+
+    async fn reconcile_schema(&self) -> Result<()> {
+        let options = IndexOptions::builder().unique(true).build();
+        let index = IndexModel::builder()
+            .keys(doc! {"title": 1})
+            .options(options)
+            .build();
+
+        self.create_index(index).await?;
+
+        // Title sorts run with the default collation, so they need an index
+        // built with that same collation to avoid an in-memory sort.
+        let collation = &self.default_collation;
+        let options = IndexOptions::builder()
+            .name(collation.index_name())
+            .collation(collation.to_mongo())
+            .build();
+        let index = IndexModel::builder()
+            .keys(doc! {"title": 1, "_id": 1})
+            .options(options)
+            .build();
+        self.create_index(index).await?;
+
+        // The default list order, under the same collation for the same
+        // reason.
+        let options = IndexOptions::builder()
+            .name(collation.created_index_name())
+            .collation(collation.to_mongo())
+            .build();
+        let index = IndexModel::builder()
+            .keys(doc! {"createdAt": -1, "_id": -1})
+            .options(options)
+            .build();
+        self.create_index(index).await?;
+
+        // The default list order puts pinned notes first. A missing `pinned`
+        // would sort apart from `false`, so older notes get it filled in.
+        self.collection
+            .update_many(
+                doc! {"pinned": {"$exists": false}},
+                doc! {"$set": {"pinned": false}},
+                None,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+        let options = IndexOptions::builder()
+            .name(collation.pinned_index_name())
+            .collation(collation.to_mongo())
+            .build();
+        let index = IndexModel::builder()
+            .keys(doc! {"pinned": -1, "createdAt": -1, "_id": -1})
+            .options(options)
+            .build();
+        self.create_index(index).await?;
+
+        // `?sort=position`, which also puts pinned notes first.
+        self.backfill_positions().await?;
+        let options = IndexOptions::builder()
+            .name(collation.position_index_name())
+            .collation(collation.to_mongo())
+            .build();
+        let index = IndexModel::builder()
+            .keys(doc! {"pinned": -1, "position": 1, "_id": 1})
+            .options(options)
+            .build();
+        self.create_index(index).await?;
+
+        // Category filters are equality matches under the default collation,
+        // case-insensitive with CATEGORY_CASE_INSENSITIVE.
+        let options = IndexOptions::builder()
+            .name(collation.category_index_name())
+            .collation(collation.to_mongo())
+            .build();
+        let index = IndexModel::builder()
+            .keys(doc! {"category": 1})
+            .options(options)
+            .build();
+        self.create_index(index).await?;
+
+        // Backs `?tags=` and `?tags_all=`; a multikey index serves both.
+        let index = IndexModel::builder().keys(doc! {"tags": 1}).build();
+        self.create_index(index).await?;
+
+        self.backfill_title_normalized().await?;
+        self.backfill_previews().await?;
+        let options = IndexOptions::builder().unique(true).build();
+        let index = IndexModel::builder()
+            .keys(doc! {"title_normalized": 1})
+            .options(options)
+            .build();
+        match self.note_collection.create_index(index, None).await {
+            Ok(_) => {}
+            Err(e) if mongo_error_code(&e) == Some(INDEX_ALREADY_EXISTS) => {}
+            // Existing titles that only differ in case or width collide once
+            // normalized; keep lookups indexed and let someone rename them.
+            Err(e) if mongo_error_code(&e) == Some(DUPLICATE_KEY) => {
+                warn!(
+                    target: LOG_TARGET,
+                    "Titles collide after normalization, title_normalized is not unique: {:?}",
+                    e
+                );
+                let index = IndexModel::builder()
+                    .keys(doc! {"title_normalized": 1})
+                    .build();
+                self.create_index(index).await?;
+            }
+            Err(e) => return Err(MongoQueryError(e)),
+        }
+
+        // Slugs are unique; sparse so that notes written by an older
+        // replica during a rollout, which have none, do not collide.
+        self.backfill_slugs().await?;
+        let options = IndexOptions::builder().unique(true).sparse(true).build();
+        let index = IndexModel::builder()
+            .keys(doc! {"slug": 1})
+            .options(options)
+            .build();
+        self.create_index(index).await?;
+
+        // Title matches count for more than content matches.
+        let options = IndexOptions::builder()
+            .name(TEXT_INDEX_NAME.to_string())
+            .weights(doc! {"title": 3, "content": 1})
+            .build();
+        let index = IndexModel::builder()
+            .keys(doc! {"title": "text", "content": "text"})
+            .options(options)
+            .build();
+        self.create_index(index).await?;
+
+        // Counter adjustments are `$inc` without upsert, so the counter has
+        // to exist and start from the real count.
+        self.reconcile_note_count().await?;
+
+        // The scheduler claims due reminders by state and time.
+        let index = IndexModel::builder()
+            .keys(doc! {"state": 1, "at": 1})
+            .build();
+        match self.reminder_collection.create_index(index, None).await {
+            Ok(_) => {}
+            Err(e) if mongo_error_code(&e) == Some(INDEX_ALREADY_EXISTS) => {}
+            Err(e) => return Err(MongoQueryError(e)),
+        }
+
+        // Revision lists and trimming go by note and revision number.
+        let options = IndexOptions::builder().unique(true).build();
+        let index = IndexModel::builder()
+            .keys(doc! {"noteId": 1, "revision": 1})
+            .options(options)
+            .build();
+        match self.revision_collection.create_index(index, None).await {
+            Ok(_) => {}
+            Err(e) if mongo_error_code(&e) == Some(INDEX_ALREADY_EXISTS) => {}
+            Err(e) => return Err(MongoQueryError(e)),
+        }
+
+        // Attachment lists and cleanup go by note.
+        let files = self
+            .client
+            .database(&self.collection.namespace().db)
+            .collection::<Document>(&format!("{}.files", ATTACHMENT_BUCKET));
+        let index = IndexModel::builder()
+            .keys(doc! {"metadata.noteId": 1, "uploadDate": 1})
+            .build();
+        match files.create_index(index, None).await {
+            Ok(_) => {}
+            Err(e) if mongo_error_code(&e) == Some(INDEX_ALREADY_EXISTS) => {}
+            Err(e) => return Err(MongoQueryError(e)),
+        }
+
+        // Comment lists go by note, newest first.
+        let index = IndexModel::builder()
+            .keys(doc! {"noteId": 1, "createdAt": -1})
+            .build();
+        match self.comment_collection.create_index(index, None).await {
+            Ok(_) => {}
+            Err(e) if mongo_error_code(&e) == Some(INDEX_ALREADY_EXISTS) => {}
+            Err(e) => return Err(MongoQueryError(e)),
+        }
+        Ok(())
+    }
+
+    /// Recomputes `title_normalized` for every note with the current
+    /// settings.
+    async fn backfill_title_normalized(&self) -> Result<()> {
+        let options = FindOptions::builder()
+            .projection(doc! {"title": 1, "title_normalized": 1})
+            .build();
+        let mut cursor = self
+            .collection
+            .find(None, options)
+            .await
+            .map_err(MongoQueryError)?;
+
+        while let Some(note) = cursor.next().await {
+            let note = note.map_err(MongoQueryError)?;
+            let (Ok(id), Ok(title)) = (note.get_object_id("_id"), note.get_str("title")) else {
+                continue;
+            };
+            let normalized = self.title_normalizer.normalize(title);
+            if note.get_str("title_normalized") == Ok(&normalized) {
+                continue;
+            }
+            self.collection
+                .update_one(
+                    doc! {"_id": id},
+                    doc! {"$set": {"title_normalized": normalized}},
+                    None,
+                )
+                .await
+                .map_err(MongoQueryError)?;
+        }
+        Ok(())
+    }
+
+    /// Gives every note without a slug one derived from its title, oldest
+    /// notes first so they get the unnumbered slugs.
+    async fn backfill_slugs(&self) -> Result<()> {
+        let options = FindOptions::builder()
+            .projection(doc! {"title": 1})
+            .sort(doc! {"createdAt": 1, "_id": 1})
+            .build();
+        let mut cursor = self
+            .collection
+            .find(doc! {"slug": {"$exists": false}}, options)
+            .await
+            .map_err(MongoQueryError)?;
+
+        while let Some(note) = cursor.next().await {
+            let note = note.map_err(MongoQueryError)?;
+            let (Ok(id), Ok(title)) = (note.get_object_id("_id"), note.get_str("title")) else {
+                continue;
+            };
+            let slug = self.free_slug(title, Some(id), &[]).await?;
+            self.collection
+                .update_one(doc! {"_id": id}, doc! {"$set": {"slug": slug}}, None)
+                .await
+                .map_err(MongoQueryError)?;
+        }
+        Ok(())
+    }
+
+    /// Places notes written before manual ordering existed after the
+    /// others, oldest first.
+    async fn backfill_positions(&self) -> Result<()> {
+        let options = FindOptions::builder()
+            .projection(doc! {"_id": 1})
+            .sort(doc! {"createdAt": 1, "_id": 1})
+            .build();
+        let mut cursor = self
+            .collection
+            .find(doc! {"position": {"$exists": false}}, options)
+            .await
+            .map_err(MongoQueryError)?;
+
+        let mut position = self.next_position().await?;
+        while let Some(note) = cursor.next().await {
+            let Ok(id) = note.map_err(MongoQueryError)?.get_object_id("_id") else {
+                continue;
+            };
+            self.collection
+                .update_one(
+                    doc! {"_id": id},
+                    doc! {"$set": {"position": position}},
+                    None,
+                )
+                .await
+                .map_err(MongoQueryError)?;
+            position += POSITION_GAP;
+        }
+        Ok(())
+    }
+
+    /// Stores `preview` for notes written before it existed, or whose
+    /// preview the current extraction rules would compute differently.
+    async fn backfill_previews(&self) -> Result<()> {
+        let options = FindOptions::builder()
+            .projection(doc! {"content": 1, "preview": 1})
+            .build();
+        let mut cursor = self
+            .collection
+            .find(None, options)
+            .await
+            .map_err(MongoQueryError)?;
+
+        while let Some(note) = cursor.next().await {
+            let note = note.map_err(MongoQueryError)?;
+            let (Ok(id), Ok(content)) = (note.get_object_id("_id"), note.get_str("content")) else {
+                continue;
+            };
+            let preview = preview::extract(content)
+                .map(|preview| bson::to_bson(&preview))
+                .transpose()
+                .map_err(MongoSerializeBsonError)?;
+            if note.get("preview") == preview.as_ref() {
+                continue;
+            }
+            let update = match preview {
+                Some(preview) => doc! {"$set": {"preview": preview}},
+                None => doc! {"$unset": {"preview": ""}},
+            };
+            self.collection
+                .update_one(doc! {"_id": id}, update, None)
+                .await
+                .map_err(MongoQueryError)?;
+        }
+        Ok(())
+    }
+
+    async fn create_index(&self, index: IndexModel) -> Result<()> {
+        let keys = index.keys.clone();
+        match self.note_collection.create_index(index, None).await {
+            Ok(_) => Ok(()),
+            Err(e) if mongo_error_code(&e) == Some(INDEX_ALREADY_EXISTS) => Ok(()),
+            // Startup stops here rather than serving without the index.
+            Err(e) if mongo_error_code(&e) == Some(DUPLICATE_KEY) => Err(ConflictError(format!(
+                "existing notes repeat a value the unique index on {} forbids, remove or rename them first: {}",
+                keys, e
+            ))),
+            Err(e) => Err(MongoQueryError(e)),
+        }
+    }
+
+    /// Lists the note indexes at startup, whether or not this replica ran
+    /// setup itself.
+    pub(crate) async fn log_indexes(&self) -> Result<()> {
+        let names = self
+            .note_collection
+            .list_index_names()
+            .await
+            .map_err(MongoQueryError)?;
+        info!(target: LOG_TARGET, "✅ Note indexes: {}", names.join(", "));
+        Ok(())
+    }
+
+    /// Takes the lease if it is free, expired, or already ours. Returns the
+    /// lease document, or `None` while another holder's lease is live.
+    async fn acquire_lease(&self, name: &str, holder: &str) -> Result<Option<Document>> {
+        let now = Utc::now();
+        let filter = doc! {
+            "_id": name,
+            "$or": [
+                {"holder": null},
+                {"holder": holder},
+                {"expiresAt": {"$lt": now}},
+            ],
+        };
+        let update = doc! {
+            "$set": {"holder": holder, "expiresAt": now + Duration::seconds(LEASE_TTL_SECS)},
+        };
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+
+        match self
+            .lock_collection
+            .find_one_and_update(filter, update, options)
+            .await
+        {
+            Ok(lease) => Ok(lease),
+            // The upsert collided with a live lease held by someone else.
+            Err(e) if mongo_error_code(&e) == Some(DUPLICATE_KEY) => Ok(None),
+            Err(e) => Err(MongoQueryError(e)),
+        }
+    }
+
+    async fn renew_lease(&self, name: &str, holder: &str) -> Result<()> {
+        let expires_at = Utc::now() + Duration::seconds(LEASE_TTL_SECS);
+        self.lock_collection
+            .update_one(
+                doc! {"_id": name, "holder": holder},
+                doc! {"$set": {"expiresAt": expires_at}},
+                None,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+        Ok(())
+    }
+
+    async fn release_lease(
+        &self,
+        name: &str,
+        holder: &str,
+        completed: Option<Document>,
+    ) -> Result<()> {
+        let mut set = doc! {"holder": null, "expiresAt": Utc::now()};
+        if let Some(completed) = completed {
+            set.extend(completed);
+        }
+
+        self.lock_collection
+            .update_one(
+                doc! {"_id": name, "holder": holder},
+                doc! {"$set": set},
+                None,
+            )
+            .await
+            .map_err(MongoQueryError)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{bare_live_db, config, drop_live_db};
+
+    #[tokio::test]
+    #[ignore = "needs a MongoDB at TEST_DATABASE_URL"]
+    async fn concurrent_setups_reconcile_once() {
+        // Each call takes the lease under its own holder id, so two calls
+        // on one client race like two replicas starting together.
+        let db = bare_live_db(&config()).await;
+        let other = db.clone();
+        let (first, second) = tokio::join!(db.run_setup(), other.run_setup());
+        let ran = [first.unwrap(), second.unwrap()];
+        assert_eq!(ran.iter().filter(|&&ran| ran).count(), 1, "{:?}", ran);
+
+        assert!(!db.run_setup().await.unwrap());
+        drop_live_db(&db).await;
+    }
+}
@@ -0,0 +1,334 @@
+use crate::config::{constant_time_eq, Config};
+use crate::secret::Secret;
+use crate::{error::Error::InvalidSignatureError, Result};
+use hmac::{Hmac, Mac};
+use log::info;
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use warp::http::{HeaderMap, Method};
+use warp::hyper::body::Bytes;
+use warp::path::FullPath;
+use warp::{reject, Filter, Rejection};
+
+/// Signatures older or newer than this many seconds are refused.
+const MAX_CLOCK_SKEW_SECS: i64 = 300;
+
+/// A named shared secret. Several may be configured at once so a secret
+/// can be rotated without downtime.
+#[derive(Serialize, Clone, Debug)]
+pub struct SigningKey {
+    pub name: String,
+    secret: Secret<String>,
+}
+
+/// Parses `SIGNING_KEYS`, e.g. `cron-2024:secretA,cron-2025:secretB`.
+pub fn parse_signing_keys(value: &str) -> std::result::Result<Vec<SigningKey>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once(':') {
+            Some((name, secret)) if !name.is_empty() && !secret.is_empty() => Ok(SigningKey {
+                name: name.to_string(),
+                secret: Secret::new(secret.to_string()),
+            }),
+            _ => Err(format!("'{}' is not in name:secret form", name_only(entry))),
+        })
+        .collect()
+}
+
+fn name_only(entry: &str) -> &str {
+    entry.split(':').next().unwrap_or("")
+}
+
+/// The `X-Signature` header value for a request; what clients must send.
+///
+/// The MAC is HMAC-SHA256 over `"{timestamp}\n{METHOD}\n{target}\n"`
+/// followed by the raw body, where `target` is the path followed by `?` and
+/// the query string exactly as sent, if there is one. For secret `topsecret`, timestamp `1700000000`, and
+/// `POST /api/notes` with body `{"title":"Hello","content":"World"}`, the
+/// header is
+/// `t=1700000000,v1=494934dbdcdf2fee8aa77e2016956483b8d5a687b2a346f808ba6c37cde1cce8`.
+pub fn sign(secret: &str, timestamp: i64, method: &str, target: &str, body: &[u8]) -> String {
+    format!(
+        "t={},v1={}",
+        timestamp,
+        mac_hex(secret, timestamp, method, target, body)
+    )
+}
+
+fn mac_hex(secret: &str, timestamp: i64, method: &str, target: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}\n{}\n{}\n", timestamp, method, target).as_bytes());
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Checks `X-Signature` on write requests and remembers recent signatures
+/// so a captured request cannot be replayed inside the skew window.
+#[derive(Clone, Debug)]
+pub struct RequestVerifier {
+    keys: Arc<Vec<SigningKey>>,
+    required: bool,
+    seen: Arc<Mutex<HashMap<(i64, String), i64>>>,
+}
+
+impl RequestVerifier {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            keys: config.signing_keys.clone(),
+            required: config.require_signed_writes,
+            seen: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Unsigned requests pass unless `REQUIRE_SIGNED_WRITES` is set; signed
+    /// ones must verify against one of the keys, which is logged by name.
+    pub fn verify(
+        &self,
+        header: Option<&str>,
+        method: &str,
+        target: &str,
+        body: &[u8],
+    ) -> Result<()> {
+        let Some(header) = header else {
+            if self.required {
+                return Err(InvalidSignatureError("Missing X-Signature".to_string()));
+            }
+            return Ok(());
+        };
+
+        let (timestamp, signature) = parse_header(header)
+            .ok_or_else(|| InvalidSignatureError("Malformed X-Signature".to_string()))?;
+
+        let now = chrono::Utc::now().timestamp();
+        if (now - timestamp).abs() > MAX_CLOCK_SKEW_SECS {
+            return Err(InvalidSignatureError(
+                "Signature timestamp is outside the allowed window".to_string(),
+            ));
+        }
+
+        let presented = format!("t={},v1={}", timestamp, signature);
+        let key = self
+            .keys
+            .iter()
+            .find(|key| {
+                constant_time_eq(
+                    &sign(key.secret.expose(), timestamp, method, target, body),
+                    &presented,
+                )
+            })
+            .ok_or_else(|| InvalidSignatureError("Signature does not match".to_string()))?;
+
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, at| now - *at <= 2 * MAX_CLOCK_SKEW_SECS);
+        if seen
+            .insert((timestamp, signature.to_string()), now)
+            .is_some()
+        {
+            return Err(InvalidSignatureError(
+                "Signature has already been used".to_string(),
+            ));
+        }
+
+        info!(
+            target: "api::signing",
+            "{} {} signed with key {}", method, target, key.name
+        );
+        Ok(())
+    }
+}
+
+fn parse_header(header: &str) -> Option<(i64, &str)> {
+    let mut timestamp = None;
+    let mut signature = None;
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => timestamp = value.parse().ok(),
+            Some(("v1", value)) => signature = Some(value),
+            _ => {}
+        }
+    }
+    Some((timestamp?, signature?))
+}
+
+/// The path and raw query string of the request, as `sign` covers them.
+fn request_target() -> impl Filter<Extract = (String,), Error = Infallible> + Clone {
+    warp::path::full()
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .map(|path: FullPath, query: String| {
+            if query.is_empty() {
+                path.as_str().to_string()
+            } else {
+                format!("{}?{}", path.as_str(), query)
+            }
+        })
+}
+
+/// Signature check for write routes without a body.
+pub fn signed(verifier: RequestVerifier) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    verified_body(verifier).map(|_| ()).untuple_one()
+}
+
+/// Signature check for routes that stream their body, which cannot be
+/// held back until it is verified: the MAC covers the method, path and
+/// query with an empty body.
+pub fn signed_headers(
+    verifier: RequestVerifier,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::method()
+        .and(request_target())
+        .and(warp::header::headers_cloned())
+        .and_then(move |method: Method, target: String, headers: HeaderMap| {
+            let result = verifier
+                .verify(
+                    headers
+                        .get("x-signature")
+                        .and_then(|value| value.to_str().ok()),
+                    method.as_str(),
+                    &target,
+                    &[],
+                )
+                .map_err(reject::custom);
+            async move { result }
+        })
+        .untuple_one()
+}
+
+/// The raw request body, after checking its signature.
+pub fn verified_body(
+    verifier: RequestVerifier,
+) -> impl Filter<Extract = (Bytes,), Error = Rejection> + Clone {
+    warp::method()
+        .and(request_target())
+        .and(warp::header::headers_cloned())
+        .and(warp::body::bytes())
+        .and_then(
+            move |method: Method, target: String, headers: HeaderMap, body: Bytes| {
+                let result = verifier
+                    .verify(
+                        headers
+                            .get("x-signature")
+                            .and_then(|value| value.to_str().ok()),
+                        method.as_str(),
+                        &target,
+                        &body,
+                    )
+                    .map(|_| body)
+                    .map_err(reject::custom);
+                async move { result }
+            },
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::config;
+    use warp::test::request;
+
+    const BODY: &[u8] = br#"{"title":"Hello","content":"World"}"#;
+
+    fn verifier(keys: &str) -> RequestVerifier {
+        let mut config = config();
+        config.signing_keys = Arc::new(parse_signing_keys(keys).unwrap());
+        config.require_signed_writes = true;
+        RequestVerifier::new(&config)
+    }
+
+    fn refusal(result: Result<()>) -> String {
+        match result {
+            Err(InvalidSignatureError(message)) => message,
+            other => panic!("expected a refused signature, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn documented_vector() {
+        assert_eq!(
+            sign("topsecret", 1700000000, "POST", "/api/notes", BODY),
+            "t=1700000000,v1=494934dbdcdf2fee8aa77e2016956483b8d5a687b2a346f808ba6c37cde1cce8"
+        );
+    }
+
+    #[test]
+    fn a_tampered_body_is_refused() {
+        let verifier = verifier("cron:topsecret");
+        let now = chrono::Utc::now().timestamp();
+        let header = sign("topsecret", now, "POST", "/api/notes", BODY);
+        let tampered = br#"{"title":"Hello","content":"Mars"}"#;
+        assert_eq!(
+            refusal(verifier.verify(Some(&header), "POST", "/api/notes", tampered)),
+            "Signature does not match"
+        );
+    }
+
+    #[test]
+    fn a_stale_timestamp_is_refused() {
+        let verifier = verifier("cron:topsecret");
+        let stale = chrono::Utc::now().timestamp() - MAX_CLOCK_SKEW_SECS - 1;
+        let header = sign("topsecret", stale, "POST", "/api/notes", BODY);
+        assert_eq!(
+            refusal(verifier.verify(Some(&header), "POST", "/api/notes", BODY)),
+            "Signature timestamp is outside the allowed window"
+        );
+    }
+
+    #[test]
+    fn a_replayed_signature_is_refused() {
+        let verifier = verifier("cron:topsecret");
+        let now = chrono::Utc::now().timestamp();
+        let header = sign("topsecret", now, "POST", "/api/notes", BODY);
+        verifier
+            .verify(Some(&header), "POST", "/api/notes", BODY)
+            .unwrap();
+        assert_eq!(
+            refusal(verifier.verify(Some(&header), "POST", "/api/notes", BODY)),
+            "Signature has already been used"
+        );
+    }
+
+    #[test]
+    fn both_keys_verify_while_rotating() {
+        let now = chrono::Utc::now().timestamp();
+        let old = sign("secretA", now, "POST", "/api/notes", BODY);
+        let new = sign("secretB", now, "POST", "/api/notes", BODY);
+
+        let rotating = verifier("cron-2024:secretA,cron-2025:secretB");
+        rotating
+            .verify(Some(&old), "POST", "/api/notes", BODY)
+            .unwrap();
+        rotating
+            .verify(Some(&new), "POST", "/api/notes", BODY)
+            .unwrap();
+
+        let rotated = verifier("cron-2025:secretB");
+        assert_eq!(
+            refusal(rotated.verify(Some(&old), "POST", "/api/notes", BODY)),
+            "Signature does not match"
+        );
+    }
+
+    #[tokio::test]
+    async fn the_query_is_signed() {
+        let verifier = verifier("cron:topsecret");
+        let now = chrono::Utc::now().timestamp();
+        let header = sign("topsecret", now, "DELETE", "/api/notes?category=old", &[]);
+        let filter = verified_body(verifier);
+        let send = |path: &str| {
+            request()
+                .method("DELETE")
+                .path(path)
+                .header("x-signature", &header)
+                .filter(&filter)
+        };
+
+        assert!(send("/api/notes?category=old&confirm=all").await.is_err());
+        assert!(send("/api/notes").await.is_err());
+        assert!(send("/api/notes?category=old").await.is_ok());
+    }
+}
@@ -0,0 +1,278 @@
+use crate::archive::ZipStream;
+use crate::response::{SiteExportError, SiteExportReport};
+use crate::{db::DB, error::Error::*, model::NoteModel, Result};
+use chrono::Utc;
+use mongodb::bson;
+use pulldown_cmark::{html, CowStr, Event, Parser, Tag};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::runtime::Handle;
+
+const PAGE_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{{title}}</title>
+</head>
+<body>
+{{body}}
+</body>
+</html>
+"#;
+
+const NOTE_TEMPLATE: &str = r#"<article>
+<h1>{{title}}</h1>
+<p><small>{{category}} &middot; {{date}}</small></p>
+{{content}}
+</article>
+<p><a href="../index.html">&larr; All notes</a></p>"#;
+
+static EXPORT_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Held for the duration of an export; only one export may run at a time.
+pub struct ExportGuard;
+
+impl ExportGuard {
+    pub fn acquire() -> Result<Self> {
+        EXPORT_RUNNING
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .map_err(|_| ConflictError("A site export is already running".to_string()))?;
+        Ok(ExportGuard)
+    }
+}
+
+impl Drop for ExportGuard {
+    fn drop(&mut self) {
+        EXPORT_RUNNING.store(false, Ordering::Release);
+    }
+}
+
+pub trait BundleWriter {
+    fn write_file(&mut self, name: &str, contents: &[u8]) -> io::Result<()>;
+}
+
+pub struct DirWriter {
+    root: PathBuf,
+}
+
+impl DirWriter {
+    pub fn new(root: &str) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl BundleWriter for DirWriter {
+    fn write_file(&mut self, name: &str, contents: &[u8]) -> io::Result<()> {
+        let path = self.root.join(name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, contents)
+    }
+}
+
+impl BundleWriter for ZipStream {
+    fn write_file(&mut self, name: &str, contents: &[u8]) -> io::Result<()> {
+        self.add_file(name, contents)
+    }
+}
+
+#[derive(Serialize)]
+struct ManifestEntry {
+    id: String,
+    title: String,
+    category: String,
+    file: String,
+    bytes: u64,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+struct Manifest<'a> {
+    generatedAt: chrono::DateTime<Utc>,
+    notes: &'a [ManifestEntry],
+    errors: &'a [SiteExportError],
+}
+
+/// Renders every published note into `writer`, one note at a time. Must run
+/// on a blocking thread; notes that fail to render are reported, not fatal.
+pub fn export(db: &DB, writer: &mut dyn BundleWriter) -> Result<SiteExportReport> {
+    let handle = Handle::current();
+    let mut cursor = db.published_notes_cursor();
+
+    let mut entries: Vec<ManifestEntry> = Vec::new();
+    let mut errors: Vec<SiteExportError> = Vec::new();
+    let mut total_bytes: u64 = 0;
+
+    while let Some(doc) = handle.block_on(cursor.next()) {
+        let doc = doc?;
+        let id = doc
+            .get_object_id("_id")
+            .map(|oid| oid.to_hex())
+            .unwrap_or_default();
+
+        let note: NoteModel = match bson::from_document(doc) {
+            Ok(note) => note,
+            Err(e) => {
+                errors.push(SiteExportError {
+                    id,
+                    message: format!("could not read note: {}", e),
+                });
+                continue;
+            }
+        };
+
+        let file = format!("notes/{}.html", id);
+        let page = render_note(&note);
+        if let Err(e) = writer.write_file(&file, page.as_bytes()) {
+            errors.push(SiteExportError {
+                id,
+                message: format!("could not write {}: {}", file, e),
+            });
+            continue;
+        }
+
+        total_bytes += page.len() as u64;
+        entries.push(ManifestEntry {
+            id,
+            title: note.title,
+            category: note.category.unwrap_or_default(),
+            file,
+            bytes: page.len() as u64,
+        });
+    }
+
+    let index = render_index(&entries);
+    writer
+        .write_file("index.html", index.as_bytes())
+        .map_err(|e| ExportError(format!("could not write index.html: {}", e)))?;
+    total_bytes += index.len() as u64;
+
+    let manifest = serde_json::to_vec_pretty(&Manifest {
+        generatedAt: Utc::now(),
+        notes: &entries,
+        errors: &errors,
+    })
+    .map_err(|e| ExportError(e.to_string()))?;
+    writer
+        .write_file("manifest.json", &manifest)
+        .map_err(|e| ExportError(format!("could not write manifest.json: {}", e)))?;
+    total_bytes += manifest.len() as u64;
+
+    Ok(SiteExportReport {
+        exported: entries.len(),
+        skipped: errors.len(),
+        total_bytes,
+        errors,
+    })
+}
+
+fn render_note(note: &NoteModel) -> String {
+    let category = note.category.as_deref().unwrap_or("");
+    let article = fill(
+        NOTE_TEMPLATE,
+        &[
+            ("title", &escape_html(&note.title)),
+            ("category", &escape_html(category)),
+            ("date", &note.createdAt.format("%Y-%m-%d").to_string()),
+            ("content", &render_markdown(&note.content)),
+        ],
+    );
+
+    fill(
+        PAGE_TEMPLATE,
+        &[("title", &escape_html(&note.title)), ("body", &article)],
+    )
+}
+
+fn render_index(entries: &[ManifestEntry]) -> String {
+    let mut by_category: BTreeMap<&str, Vec<&ManifestEntry>> = BTreeMap::new();
+    for entry in entries {
+        let category = match entry.category.as_str() {
+            "" => "Uncategorized",
+            category => category,
+        };
+        by_category.entry(category).or_default().push(entry);
+    }
+
+    let mut body = String::from("<h1>Notes</h1>\n");
+    for (category, mut notes) in by_category {
+        notes.sort_by(|a, b| a.title.cmp(&b.title));
+        body.push_str(&format!("<h2>{}</h2>\n<ul>\n", escape_html(category)));
+        for note in notes {
+            body.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>\n",
+                note.file,
+                escape_html(&note.title)
+            ));
+        }
+        body.push_str("</ul>\n");
+    }
+
+    fill(PAGE_TEMPLATE, &[("title", "Notes"), ("body", &body)])
+}
+
+/// Markdown to HTML with raw HTML escaped and script-capable links defused.
+fn render_markdown(markdown: &str) -> String {
+    let events = Parser::new(markdown).map(|event| match event {
+        Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+
+    let mut output = String::new();
+    html::push_html(&mut output, events);
+    output
+}
+
+fn safe_url(url: CowStr) -> CowStr {
+    let scheme = url.trim_start().to_ascii_lowercase();
+    if ["javascript:", "vbscript:", "data:"]
+        .iter()
+        .any(|unsafe_scheme| scheme.starts_with(unsafe_scheme))
+    {
+        return CowStr::Borrowed("#");
+    }
+    url
+}
+
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |page, (key, value)| {
+            page.replace(&format!("{{{{{}}}}}", key), value)
+        })
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
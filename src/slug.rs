@@ -0,0 +1,173 @@
+use crate::{
+    db::{escape_regex, live_note, DB},
+    error::{mongo_error_code, Error::*, DUPLICATE_KEY},
+    events::NoteEvent,
+    model::NoteModel,
+    response::{NoteData, SingleNoteResponse},
+    routes::NOTE_LITERAL_SEGMENTS,
+    Result,
+};
+use futures::StreamExt;
+use mongodb::bson::{doc, oid::ObjectId};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+/// Inserts retried after losing a race for a slug.
+pub const MAX_SLUG_ATTEMPTS: u32 = 5;
+
+/// Lowercases `title` and collapses every run of other characters than
+/// letters and digits into one dash. A title with neither becomes `note`.
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::with_capacity(title.len());
+    for c in title.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+    if slug.is_empty() {
+        slug.push_str("note");
+    }
+    slug
+}
+
+/// Whether `value` could be a slug, i.e. `slugify` leaves it unchanged.
+pub fn is_slug(value: &str) -> bool {
+    !value.is_empty() && slugify(value) == value
+}
+
+/// Slugs the note routes could never reach: the literal segments, and
+/// anything that parses as an ObjectId first.
+fn is_reserved(slug: &str) -> bool {
+    NOTE_LITERAL_SEGMENTS.contains(&slug) || ObjectId::from_str(slug).is_ok()
+}
+
+impl DB {
+    /// The first of `base`, `base-2`, `base-3`, ... for `title` that no
+    /// note uses. The slug of `own`, the note being given the slug, does not
+    /// count, and neither do slugs in `reserved`, which a batch has handed
+    /// out but not written yet.
+    pub(crate) async fn free_slug(
+        &self,
+        title: &str,
+        own: Option<ObjectId>,
+        reserved: &[String],
+    ) -> Result<String> {
+        let base = slugify(title);
+        let mut filter = doc! {"slug": {"$regex": format!("^{}(-[0-9]+)?$", escape_regex(&base))}};
+        if let Some(own) = own {
+            filter.insert("_id", doc! {"$ne": own});
+        }
+        let options = FindOptions::builder().projection(doc! {"slug": 1}).build();
+        let mut cursor = self
+            .collection
+            .find(filter, options)
+            .await
+            .map_err(MongoQueryError)?;
+        let mut taken = HashSet::new();
+        while let Some(note) = cursor.next().await {
+            if let Ok(slug) = note.map_err(MongoQueryError)?.get_str("slug") {
+                taken.insert(slug.to_owned());
+            }
+        }
+
+        let mut number = 1;
+        loop {
+            let candidate = match number {
+                1 => base.clone(),
+                n => format!("{}-{}", base, n),
+            };
+            if !taken.contains(&candidate)
+                && !reserved.contains(&candidate)
+                && !is_reserved(&candidate)
+            {
+                return Ok(candidate);
+            }
+            number += 1;
+        }
+    }
+
+    /// Whether some note holds `slug`, to tell a lost slug race apart from a
+    /// duplicate title after an insert failed on a unique index.
+    pub(crate) async fn slug_taken(&self, slug: &str) -> Result<bool> {
+        let count = self
+            .collection
+            .count_documents(doc! {"slug": slug}, None)
+            .await
+            .map_err(MongoQueryError)?;
+        Ok(count > 0)
+    }
+
+    /// The id of the live note with `slug`.
+    pub(crate) async fn note_id_by_slug(&self, slug: &str) -> Result<Option<ObjectId>> {
+        let note = self
+            .collection
+            .find_one(doc! {"slug": slug, "deletedAt": null}, None)
+            .await
+            .map_err(MongoQueryError)?;
+        Ok(note.and_then(|note| note.get_object_id("_id").ok()))
+    }
+
+    /// Derives the slug afresh from the note's current title.
+    pub async fn regenerate_slug(&self, id: &str) -> Result<Option<SingleNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+
+        for _ in 0..MAX_SLUG_ATTEMPTS {
+            let Some(note) = self
+                .note_collection
+                .find_one(live_note(oid), None)
+                .await
+                .map_err(MongoQueryError)?
+            else {
+                return Ok(None);
+            };
+            let slug = self.free_slug(&note.title, Some(oid), &[]).await?;
+            if note.slug.as_deref() == Some(slug.as_str()) {
+                return Ok(Some(self.single_note(&note)?));
+            }
+
+            let updated = self
+                .note_collection
+                .find_one_and_update(
+                    live_note(oid),
+                    doc! {"$set": {"slug": &slug}},
+                    options.clone(),
+                )
+                .await;
+            match updated {
+                Ok(Some(note)) => {
+                    let note_response = self.single_note(&note)?;
+                    self.events
+                        .publish(NoteEvent::changed(&note_response, &["slug"]));
+                    return Ok(Some(note_response));
+                }
+                Ok(None) => return Ok(None),
+                // Another note took the slug since it was picked.
+                Err(e) if mongo_error_code(&e) == Some(DUPLICATE_KEY) => continue,
+                Err(e) => return Err(MongoQueryError(e)),
+            }
+        }
+        Err(ConflictError(format!(
+            "could not find a free slug for note {}",
+            id
+        )))
+    }
+
+    fn single_note(&self, note: &NoteModel) -> Result<SingleNoteResponse> {
+        Ok(SingleNoteResponse {
+            status: "success".to_string(),
+            data: NoteData {
+                note: self.doc_to_note(note)?,
+                draft: None,
+            },
+        })
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{
+    db::{live_note, DB},
+    error::Error::*,
+    events::NoteEvent,
+    model::NoteModel,
+    response::{FieldError, NoteData, SingleNoteResponse},
+    Result,
+};
+use chrono::Utc;
+use mongodb::bson::{doc, oid::ObjectId, Document};
+use mongodb::options::{FindOneAndUpdateOptions, ReturnDocument};
+use std::str::FromStr;
+
+pub const MAX_TAG_LENGTH: usize = 32;
+pub const MAX_TAGS_PER_NOTE: usize = 20;
+
+/// Tags are stored trimmed and lowercased, so `Rust` and ` rust` are the
+/// same tag.
+pub fn normalize_tag(field: &str, tag: &str) -> Result<String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() {
+        return Err(ValidationError(vec![tag_error(
+            field,
+            "REQUIRED",
+            "Tag must not be empty".to_string(),
+        )]));
+    }
+    if tag.chars().count() > MAX_TAG_LENGTH {
+        return Err(ValidationError(vec![tag_error(
+            field,
+            "TOO_LONG",
+            format!("Tag must be at most {} characters", MAX_TAG_LENGTH),
+        )]));
+    }
+    Ok(tag)
+}
+
+/// Normalizes a whole set of tags, dropping repeats, and reports every
+/// invalid one at once.
+pub fn normalize_tags(tags: &[String]) -> Result<Vec<String>> {
+    let mut normalized: Vec<String> = Vec::new();
+    let mut errors = Vec::new();
+    for (index, tag) in tags.iter().enumerate() {
+        match normalize_tag(&format!("tags[{}]", index), tag) {
+            Ok(tag) if !normalized.contains(&tag) => normalized.push(tag),
+            Ok(_) => {}
+            Err(ValidationError(tag_errors)) => errors.extend(tag_errors),
+            Err(e) => return Err(e),
+        }
+    }
+    if normalized.len() > MAX_TAGS_PER_NOTE {
+        errors.push(too_many_tags("tags"));
+    }
+    if !errors.is_empty() {
+        return Err(ValidationError(errors));
+    }
+    Ok(normalized)
+}
+
+/// A comma-separated `?tags=` value, normalized like stored tags. Empty
+/// items are skipped.
+pub fn parse_list(raw: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in raw.split(',').map(|tag| tag.trim().to_lowercase()) {
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+impl DB {
+    /// Adds `tag` unless the note has it already. Either way `updatedAt`
+    /// moves.
+    pub async fn add_tag(&self, id: &str, tag: &str) -> Result<Option<SingleNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+        let tag = normalize_tag("tag", tag)?;
+
+        // A note at the cap can only be "given" a tag it already has.
+        let mut filter = live_note(oid);
+        filter.insert(
+            "$or",
+            vec![
+                doc! {"tags": &tag},
+                doc! {format!("tags.{}", MAX_TAGS_PER_NOTE - 1): {"$exists": false}},
+            ],
+        );
+        let update = doc! {
+            "$addToSet": {"tags": &tag},
+            "$set": {"updatedAt": Utc::now()},
+        };
+        match self.update_tags(filter, update).await? {
+            Some(note) => Ok(Some(note)),
+            None if self.note_exists(oid).await? => {
+                Err(ValidationError(vec![too_many_tags("tag")]))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub async fn remove_tag(&self, id: &str, tag: &str) -> Result<Option<SingleNoteResponse>> {
+        let oid = ObjectId::from_str(id).map_err(|_| InvalidIDError(id.to_owned()))?;
+        let tag = tag.trim().to_lowercase();
+
+        let update = doc! {
+            "$pull": {"tags": &tag},
+            "$set": {"updatedAt": Utc::now()},
+        };
+        self.update_tags(live_note(oid), update).await
+    }
+
+    async fn update_tags(
+        &self,
+        filter: Document,
+        update: Document,
+    ) -> Result<Option<SingleNoteResponse>> {
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let note: Option<NoteModel> = self
+            .note_collection
+            .find_one_and_update(filter, update, options)
+            .await
+            .map_err(MongoQueryError)?;
+        let Some(note) = note else {
+            return Ok(None);
+        };
+
+        let note_response = SingleNoteResponse {
+            status: "success".to_string(),
+            data: NoteData {
+                note: self.doc_to_note(&note)?,
+                draft: None,
+            },
+        };
+        self.events
+            .publish(NoteEvent::changed(&note_response, &["tags"]));
+
+        Ok(Some(note_response))
+    }
+
+    pub(crate) async fn note_exists(&self, oid: ObjectId) -> Result<bool> {
+        let count = self
+            .collection
+            .count_documents(live_note(oid), None)
+            .await
+            .map_err(MongoQueryError)?;
+        Ok(count > 0)
+    }
+}
+
+fn too_many_tags(field: &str) -> FieldError {
+    tag_error(
+        field,
+        "TOO_MANY_TAGS",
+        format!("A note can have at most {} tags", MAX_TAGS_PER_NOTE),
+    )
+}
+
+fn tag_error(field: &str, code: &str, message: String) -> FieldError {
+    FieldError {
+        field: field.to_owned(),
+        code: code.to_string(),
+        message,
+    }
+}
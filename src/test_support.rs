@@ -0,0 +1,113 @@
+//! Shared setup for the unit tests.
+//!
+//! `offline_db` hands out a `DB` whose server never answers, for tests that
+//! stop before a query runs or only need one to fail. `block_on` runs
+//! tests that drive requests through the full route tree. Tests that need
+//! real documents use `live_db` and are `#[ignore]`d: run them with
+//! `TEST_DATABASE_URL=mongodb://... cargo test -- --ignored`.
+
+use crate::{config::Config, db::DB, monitoring::MongoMonitor};
+use mongodb::bson::oid::ObjectId;
+use mongodb::error::{ErrorKind, WriteError, WriteFailure};
+use mongodb::options::ClientOptions;
+use mongodb::Client;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The defaults `Config::init` gives, without reading `.env`.
+pub fn config() -> Config {
+    Config::init()
+}
+
+/// A `DB` pointed at a port nothing listens on. Every query fails after
+/// a short server selection timeout.
+pub fn offline_db(config: &Config) -> DB {
+    let options = ClientOptions::builder()
+        .hosts(vec!["127.0.0.1:9".parse().expect("valid address")])
+        .server_selection_timeout(Duration::from_millis(100))
+        .connect_timeout(Duration::from_millis(100))
+        .build();
+    let client = Client::with_options(options).expect("client options are valid");
+    DB::with_client(
+        client,
+        "notes_offline",
+        "notes",
+        config,
+        false,
+        Arc::new(MongoMonitor::new()),
+    )
+}
+
+/// A `DB` on a fresh database of the server at `TEST_DATABASE_URL`, set up
+/// like at startup. Drop it with `drop_live_db` when done.
+pub async fn live_db(config: &Config) -> DB {
+    let db = bare_live_db(config).await;
+    db.run_setup().await.expect("schema setup");
+    db
+}
+
+/// `live_db` before `run_setup`: no indexes, no counter.
+pub async fn bare_live_db(config: &Config) -> DB {
+    let uri = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+    let options = ClientOptions::parse(uri)
+        .await
+        .expect("TEST_DATABASE_URL must be a MongoDB URI");
+    let client = Client::with_options(options).expect("client options are valid");
+    let supports_transactions = client
+        .database("admin")
+        .run_command(mongodb::bson::doc! {"hello": 1}, None)
+        .await
+        .map(|hello| hello.contains_key("setName"))
+        .unwrap_or(false);
+    DB::with_client(
+        client,
+        &format!("notes_test_{}", ObjectId::new().to_hex()),
+        "notes",
+        config,
+        supports_transactions,
+        Arc::new(MongoMonitor::new()),
+    )
+}
+
+pub async fn drop_live_db(db: &DB) {
+    let name = db.collection.namespace().db;
+    db.client
+        .database(&name)
+        .drop(None)
+        .await
+        .expect("drop test database");
+}
+
+/// A driver error for a single failed write, as the server reports one.
+pub fn write_error(code: i32, message: &str) -> mongodb::error::Error {
+    let write_error: WriteError =
+        mongodb::bson::from_document(mongodb::bson::doc! {"code": code, "errmsg": message})
+            .expect("a valid write error");
+    ErrorKind::Write(WriteFailure::WriteError(write_error)).into()
+}
+
+/// Runs a test body on its own thread and runtime.
+///
+/// The whole route tree is far deeper than a test thread's stack allows in
+/// a debug build, so tests that go through `routes()` run here.
+pub fn block_on<F>(future: F) -> F::Output
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn_scoped(scope, || {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("test runtime")
+                    .block_on(future)
+            })
+            .expect("test thread")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
@@ -0,0 +1,52 @@
+use crate::{db::DB, error::Error::*, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::{error, info};
+use mongodb::bson::doc;
+use tokio::sync::watch;
+
+const LOG_TARGET: &str = "api::trash";
+
+/// How often the purge task looks for notes past their retention.
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+impl DB {
+    /// Permanently deletes trashed notes, only those trashed before
+    /// `older_than` if given. They left the counter, their reminders and the
+    /// event stream when they were trashed; only their comments,
+    /// attachments and revisions go now.
+    pub async fn purge_trash(&self, older_than: Option<DateTime<Utc>>) -> Result<u64> {
+        let query = match older_than {
+            Some(cutoff) => doc! {"deletedAt": {"$lt": cutoff}},
+            None => doc! {"deletedAt": {"$ne": null}},
+        };
+        // Only the notes whose comments are about to go, even if more are
+        // trashed meanwhile.
+        let ids = self
+            .matching_ids(query.clone(), &self.default_collation)
+            .await?;
+        let result = self
+            .collection
+            .delete_many(doc! {"$and": [query, {"_id": {"$in": &ids}}]}, None)
+            .await
+            .map_err(MongoQueryError)?;
+        self.delete_note_data(&ids).await;
+        Ok(result.deleted_count)
+    }
+}
+
+/// Purges notes trashed more than `retention_days` ago every
+/// `PURGE_INTERVAL` until `shutdown` flips to true.
+pub async fn purge_periodically(db: DB, retention_days: u32, mut shutdown: watch::Receiver<bool>) {
+    loop {
+        let cutoff = Utc::now() - Duration::days(retention_days.into());
+        match db.purge_trash(Some(cutoff)).await {
+            Ok(purged) => info!(target: LOG_TARGET, "🗑️ Purged {} note(s) from the trash", purged),
+            Err(e) => error!(target: LOG_TARGET, "Could not purge the trash: {:?}", e),
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(PURGE_INTERVAL) => {}
+            _ = shutdown.changed() => return,
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{
+    error::Error::{InvalidDateError, ValidationError},
+    response::FieldError,
+    Result,
+};
+use chrono::{DateTime, TimeZone, Utc};
+
+/// Counted in characters after trimming.
+pub const MAX_TITLE_LENGTH: usize = 200;
+pub const MAX_CATEGORY_LENGTH: usize = 64;
+pub const DEFAULT_MAX_CONTENT_LENGTH: usize = 100_000;
+
+/// Earliest and latest instants accepted from clients. BSON datetimes are
+/// i64 milliseconds, but anything outside this window is a client bug and
+/// would only surface later as an opaque serialization error.
+pub fn datetime_window() -> (DateTime<Utc>, DateTime<Utc>) {
+    (
+        Utc.with_ymd_and_hms(1970, 1, 1, 0, 0, 0).unwrap(),
+        Utc.with_ymd_and_hms(9999, 12, 31, 23, 59, 59).unwrap()
+            + chrono::Duration::milliseconds(999),
+    )
+}
+
+pub fn check_datetime_range(field: &str, value: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let (min, max) = datetime_window();
+    if value < min || value > max {
+        return Err(InvalidDateError {
+            field: field.to_owned(),
+            message: format!(
+                "{} must be between {} and {}",
+                field,
+                min.to_rfc3339(),
+                max.to_rfc3339()
+            ),
+        });
+    }
+    Ok(value)
+}
+
+/// Checks whichever of a note's text fields a body sets and reports every
+/// problem at once.
+pub fn check_note_fields(
+    title: Option<&str>,
+    content: Option<&str>,
+    category: Option<&str>,
+    max_content_length: usize,
+) -> Result<()> {
+    let mut errors = Vec::new();
+    if let Some(title) = title {
+        let length = title.trim().chars().count();
+        if length == 0 {
+            errors.push(field_error(
+                "title",
+                "REQUIRED",
+                "title must not be empty".to_string(),
+            ));
+        } else if length > MAX_TITLE_LENGTH {
+            errors.push(too_long("title", MAX_TITLE_LENGTH));
+        }
+    }
+    if let Some(content) = content {
+        if content.trim().is_empty() {
+            errors.push(field_error(
+                "content",
+                "REQUIRED",
+                "content must not be empty".to_string(),
+            ));
+        } else if content.chars().count() > max_content_length {
+            errors.push(too_long("content", max_content_length));
+        }
+    }
+    if let Some(category) = category {
+        if category.chars().count() > MAX_CATEGORY_LENGTH {
+            errors.push(too_long("category", MAX_CATEGORY_LENGTH));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(ValidationError(errors));
+    }
+    Ok(())
+}
+
+fn too_long(field: &str, max: usize) -> FieldError {
+    field_error(
+        field,
+        "TOO_LONG",
+        format!("{} must be at most {} characters", field, max),
+    )
+}
+
+fn field_error(field: &str, code: &str, message: String) -> FieldError {
+    FieldError {
+        field: field.to_owned(),
+        code: code.to_string(),
+        message,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::{handle_rejection, Error, ErrorContext};
+    use chrono::Duration;
+    use mongodb::bson::{self, Bson};
+    use warp::Reply;
+
+    fn round_trip(value: DateTime<Utc>) -> DateTime<Utc> {
+        let stored = bson::to_bson(&bson::DateTime::from_chrono(value)).unwrap();
+        let Bson::DateTime(stored) = stored else {
+            panic!("stored as {:?}", stored);
+        };
+        stored.to_chrono()
+    }
+
+    #[test]
+    fn window_edges_are_accepted_and_survive_bson() {
+        let (min, max) = datetime_window();
+        for edge in [min, max] {
+            assert_eq!(check_datetime_range("dueAt", edge).unwrap(), edge);
+            assert_eq!(round_trip(edge), edge);
+        }
+    }
+
+    #[test]
+    fn one_millisecond_outside_is_rejected() {
+        let (min, max) = datetime_window();
+        for outside in [
+            min - Duration::milliseconds(1),
+            max + Duration::milliseconds(1),
+        ] {
+            match check_datetime_range("dueAt", outside) {
+                Err(InvalidDateError { field, message }) => {
+                    assert_eq!(field, "dueAt");
+                    assert!(message.starts_with("dueAt must be between"), "{}", message);
+                }
+                other => panic!("{} was not rejected: {:?}", outside, other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn out_of_range_is_a_422_naming_the_field() {
+        let rejection = warp::reject::custom(Error::InvalidDateError {
+            field: "publishAt".to_string(),
+            message: "publishAt must be between ...".to_string(),
+        });
+        let context = ErrorContext {
+            debug: false,
+            request_id: "test".to_string(),
+            method: "POST".to_string(),
+            path: "/api/notes".to_string(),
+        };
+        let response = handle_rejection(rejection, context)
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status(), 422);
+        let body = warp::hyper::body::to_bytes(response.into_body())
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["errors"][0]["field"], "publishAt");
+        assert_eq!(body["errors"][0]["code"], "DATE_OUT_OF_RANGE");
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{db::DB, error::Error::*, Result};
+use futures::StreamExt;
+use log::{info, warn};
+use mongodb::bson::doc;
+use mongodb::options::FindOptions;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+const LOG_TARGET: &str = "api::warmup";
+
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// What the health check reports as `ready`.
+pub fn is_ready() -> bool {
+    READY.load(Ordering::Acquire)
+}
+
+pub fn mark_ready() {
+    READY.store(true, Ordering::Release);
+}
+
+/// Opens `connections` pooled connections and reads the `notes` most
+/// recently updated notes, so the first requests after a deploy do not pay
+/// for a cold pool and a cold server cache. Readiness flips when this is
+/// done or `deadline` has passed; a failure only means a cold start.
+pub async fn run(db: DB, connections: u32, notes: i64, deadline: Duration) {
+    let started = Instant::now();
+    match tokio::time::timeout(deadline, warm_up(&db, connections, notes)).await {
+        Ok(Ok(())) => info!(
+            target: LOG_TARGET,
+            "warm-up finished in {:?}",
+            started.elapsed()
+        ),
+        Ok(Err(e)) => warn!(
+            target: LOG_TARGET,
+            "warm-up failed after {:?}, starting cold: {}",
+            started.elapsed(),
+            e
+        ),
+        Err(_) => warn!(
+            target: LOG_TARGET,
+            "warm-up did not finish within {:?}, starting cold",
+            deadline
+        ),
+    }
+    mark_ready();
+}
+
+async fn warm_up(db: &DB, connections: u32, notes: i64) -> Result<()> {
+    // Concurrent pings each need their own connection.
+    let mut pings = JoinSet::new();
+    for _ in 0..connections.max(1) {
+        let admin = db.client.database("admin");
+        pings.spawn(async move { admin.run_command(doc! {"ping": 1}, None).await });
+    }
+    while let Some(ping) = pings.join_next().await {
+        ping.map_err(|e| UnavailableError(e.to_string()))?
+            .map_err(MongoQueryError)?;
+    }
+
+    let options = FindOptions::builder()
+        .sort(doc! {"updatedAt": -1})
+        .limit(notes)
+        .build();
+    let mut cursor = db
+        .collection
+        .find(None, options)
+        .await
+        .map_err(MongoQueryError)?;
+    while let Some(note) = cursor.next().await {
+        note.map_err(MongoQueryError)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{config, offline_db};
+
+    /// Runs a warm-up against a server that never answers and checks
+    /// readiness only flips once `run` returns.
+    async fn check_readiness_waits(deadline: Duration) {
+        READY.store(false, Ordering::Release);
+        let warm_up = tokio::spawn(run(offline_db(&config()), 2, 10, deadline));
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        assert!(!is_ready(), "ready while warming up");
+        warm_up.await.unwrap();
+        assert!(is_ready(), "not ready after warming up");
+    }
+
+    #[tokio::test]
+    async fn readiness_waits_for_the_warm_up() {
+        // The pings fail on server selection well before the deadline.
+        check_readiness_waits(Duration::from_secs(5)).await;
+        // The deadline passes before server selection gives up.
+        check_readiness_waits(Duration::from_millis(50)).await;
+    }
+}